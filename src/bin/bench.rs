@@ -0,0 +1,205 @@
+use std::{
+    env, fs,
+    net::{Ipv4Addr, UdpSocket},
+    process, thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use recursor::{dns::question::QueryType, lookup};
+
+mod common;
+
+// names used when no name list is given
+const DEFAULT_NAMES: [&str; 10] = [
+    "google.com",
+    "youtube.com",
+    "facebook.com",
+    "wikipedia.org",
+    "amazon.com",
+    "github.com",
+    "cloudflare.com",
+    "reddit.com",
+    "apple.com",
+    "nathanielfernandes.ca",
+];
+
+const USAGE: &str = "usage: bench [--server ip] [--port port] [--qps qps] [--count n] [--threads n]
+             [--names file] [--types A:70,AAAA:20,MX:10]";
+
+// generates synthetic query load against a resolver
+fn main() {
+    let mut server_ip = Ipv4Addr::LOCALHOST;
+    let mut port = 2053;
+    let mut qps: u32 = 0; // 0 sends as fast as the resolver answers
+    let mut count: usize = 1000;
+    let mut threads: usize = 1;
+    let mut names: Vec<String> = DEFAULT_NAMES.iter().map(|n| n.to_string()).collect();
+    let mut types = vec![(QueryType::A, 1)];
+
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let valid = match flag.as_str() {
+            "--server" => parse(&mut args, &mut server_ip),
+            "--port" => parse(&mut args, &mut port),
+            "--qps" => parse(&mut args, &mut qps),
+            "--count" => parse(&mut args, &mut count),
+            "--threads" => parse(&mut args, &mut threads),
+            "--names" => {
+                let path = args.next().unwrap_or_else(|| fail("missing the name list"));
+                names = fs::read_to_string(path)
+                    .unwrap_or_else(|_| fail("failed to read name list"))
+                    .lines()
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(String::from)
+                    .collect();
+                true
+            }
+            "--types" => match args.next().as_deref().and_then(parse_types) {
+                Some(mix) => {
+                    types = mix;
+                    true
+                }
+                None => false,
+            },
+            _ => fail(&format!("unknown flag {}", flag)),
+        };
+        if !valid {
+            fail(&format!("invalid or missing value for {}", flag));
+        }
+    }
+
+    if names.is_empty() || threads == 0 {
+        fail("need at least one name and one thread");
+    }
+
+    println!(
+        "Sending {} queries over {} names to {}:{} with {} threads",
+        count,
+        names.len(),
+        server_ip,
+        port,
+        threads
+    );
+
+    // each thread gets an even share of the queries and the rate
+    let interval = match qps {
+        0 => Duration::ZERO,
+        qps => Duration::from_secs(1) * threads as u32 / qps,
+    };
+
+    let mut report = common::Report::new();
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|n| {
+                let (names, types) = (&names, &types);
+                let share = count / threads + usize::from(n < count % threads);
+                scope.spawn(move || {
+                    let socket = UdpSocket::bind(("0.0.0.0", 0)).unwrap();
+                    socket
+                        .set_read_timeout(Some(Duration::from_secs(5)))
+                        .unwrap();
+
+                    let mut rng = Rng::new(n as u64);
+                    let mut report = common::Report::new();
+                    for _ in 0..share {
+                        let qname = &names[rng.zipf(names.len())];
+                        let qtype = rng.weighted(types);
+
+                        let sent = Instant::now();
+                        let result = lookup(qname.as_str(), qtype, (server_ip, port), &socket);
+                        report.record(result, sent.elapsed());
+
+                        if let Some(wait) = interval.checked_sub(sent.elapsed()) {
+                            thread::sleep(wait);
+                        }
+                    }
+                    report
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            report.merge(worker.join().unwrap());
+        }
+    });
+
+    report.print();
+}
+
+// read the next argument into value, false if it's missing or invalid
+fn parse<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>, value: &mut T) -> bool {
+    match args.next().map(|arg| arg.parse()) {
+        Some(Ok(parsed)) => {
+            *value = parsed;
+            true
+        }
+        _ => false,
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    process::exit(2);
+}
+
+// parse a type mix like "A:70,AAAA:20,MX:10"
+fn parse_types(mix: &str) -> Option<Vec<(QueryType, u32)>> {
+    mix.split(',')
+        .map(|entry| {
+            let (qtype, weight) = entry.split_once(':').unwrap_or((entry, "1"));
+            Some((qtype.parse().ok()?, weight.parse().ok()?))
+        })
+        .collect()
+}
+
+// xorshift generator, good enough for picking names
+struct Rng(u64);
+
+impl Rng {
+    fn new(stream: u64) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        Rng((nanos ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15)) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // uniform float in [0, 1)
+    fn float(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // pick an index in 0..n where index i has weight 1 / (i + 1),
+    // so the first names in the list are the most popular
+    fn zipf(&mut self, n: usize) -> usize {
+        let total: f64 = (1..=n).map(|i| 1.0 / i as f64).sum();
+        let mut target = self.float() * total;
+        for i in 0..n {
+            target -= 1.0 / (i + 1) as f64;
+            if target < 0.0 {
+                return i;
+            }
+        }
+        n - 1
+    }
+
+    fn weighted(&mut self, types: &[(QueryType, u32)]) -> QueryType {
+        let total: u32 = types.iter().map(|(_, weight)| weight).sum();
+        let mut target = (self.float() * total as f64) as u32;
+        for (qtype, weight) in types {
+            if target < *weight {
+                return *qtype;
+            }
+            target -= weight;
+        }
+        types[types.len() - 1].0
+    }
+}
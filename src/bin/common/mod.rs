@@ -0,0 +1,82 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use recursor::dns::packet::DnsPacket;
+
+// collects the outcome of each query sent by the load tools
+pub struct Report {
+    start: Instant,
+    latencies: Vec<Duration>,
+    rcodes: BTreeMap<String, usize>,
+    errors: usize,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Report {
+            start: Instant::now(),
+            latencies: Vec::new(),
+            rcodes: BTreeMap::new(),
+            errors: 0,
+        }
+    }
+
+    pub fn record(&mut self, result: Result<DnsPacket, &'static str>, latency: Duration) {
+        match result {
+            Ok(packet) => {
+                self.latencies.push(latency);
+                *self
+                    .rcodes
+                    .entry(packet.header.rcode.to_string())
+                    .or_insert(0) += 1;
+            }
+            Err(_) => self.errors += 1,
+        }
+    }
+
+    // fold another report into this one, keeping the earliest start
+    #[allow(dead_code)] // only the multi-threaded tools merge reports
+    pub fn merge(&mut self, other: Report) {
+        self.start = self.start.min(other.start);
+        self.latencies.extend(other.latencies);
+        for (rcode, count) in other.rcodes {
+            *self.rcodes.entry(rcode).or_insert(0) += count;
+        }
+        self.errors += other.errors;
+    }
+
+    pub fn print(mut self) {
+        let elapsed = self.start.elapsed();
+        let total = self.latencies.len() + self.errors;
+
+        println!(
+            "Sent {} queries in {:.2?} ({:.1} qps, {} errors, {:.2}%)",
+            total,
+            elapsed,
+            total as f64 / elapsed.as_secs_f64(),
+            self.errors,
+            self.errors as f64 * 100.0 / total.max(1) as f64
+        );
+
+        for (rcode, count) in &self.rcodes {
+            println!("  {:<10}{}", rcode, count);
+        }
+
+        if self.latencies.is_empty() {
+            return;
+        }
+
+        self.latencies.sort();
+        let latencies = &self.latencies;
+        let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+        println!(
+            "Latency p50 {:.2?}, p90 {:.2?}, p99 {:.2?}, max {:.2?}",
+            percentile(50),
+            percentile(90),
+            percentile(99),
+            latencies[latencies.len() - 1]
+        );
+    }
+}
@@ -1,23 +1,533 @@
-use std::net::UdpSocket;
+use std::{
+    env,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, UdpSocket},
+    path::PathBuf,
+    thread,
+    time::Duration,
+};
 
-use recursor::{handle_query, rootserver};
+use recursor::{
+    capture::Capture,
+    dns::question::QueryType,
+    leases::Leases,
+    policy::{self, Policies},
+    rootserver,
+    router::{Router, Upstream},
+    Resolver, Transport,
+};
 
 fn main() {
     // Bind an UDP socket on port 2053
     let listen_socket = UdpSocket::bind(("0.0.0.0", 2053)).unwrap();
 
+    // Serve the same queries over TCP on port 2053, for clients retrying
+    // answers that were too large for UDP
+    let tcp_listener = TcpListener::bind(("0.0.0.0", 2053)).unwrap();
+
     // Bind an UDP socket on port 43210 for sending queries
     let send_socket = UdpSocket::bind(("0.0.0.0", 43210)).unwrap();
 
     // The root server we will be querying
-    let ns = rootserver::A;
+    let mut resolver = Resolver::new(rootserver::A);
+
+    // Never send queries to the address we are answering on
+    resolver
+        .listen_addrs
+        .push(listen_socket.local_addr().unwrap());
+
+    // Packets that fail to parse are logged, or written to the directory
+    // passed with --capture, at most 10 per minute
+    // --unix additionally serves queries on a unix stream socket
+    // --tls-cert and --tls-key, PEM files with a certificate chain and its
+    // private key, additionally serve dns over tls on port 853
+    // --doh also serves dns over https on port 443 with that certificate
+    // --dot-listen and --doh-listen <ip>:<port> serve them elsewhere
+    // --tls-ciphers <suite>[,...] only negotiates those cipher suites, e.g.
+    // TLS13_AES_256_GCM_SHA384, in order of preference
+    // --paranoid only accepts answers that two nameservers agree on
+    // --resolve-targets adds the addresses of MX/NS targets to answers
+    // --identify-clients logs the MAC address of LAN clients with their queries
+    // --leases <path> answers for the hosts in a dnsmasq or ISC dhcpd lease
+    // file under --local-domain (lan by default), and their reverse names
+    // --route <type>=<ip>[:port] forwards queries of a type to an upstream
+    // instead of resolving them, repeat it for more types or upstreams.
+    // <ip>[:port]#<name> forwards over tls (port 853 by default) to a server
+    // with a certificate for name, with the tls feature.
+    // <ip>[:port]@<provider name>/<public key> forwards over dnscrypt (port
+    // 443 by default), with the dnscrypt feature
+    // --edns-payload <bytes> sets the udp response size advertised upstream
+    // (1232 by default), 0 sends plain queries limited to 512 bytes
+    // --spoof-window <ms> keeps listening after each upstream response and
+    // logs any other responses that arrive, which hints at spoofing attempts
+    // --tcp-idle-timeout <seconds> closes tcp and tls connections left idle
+    // that long (10 by default), and is announced to clients that ask with
+    // the edns keepalive option
+    // --request-nsid asks nameservers for their NSID and logs it
+    // --nsid <identity> is returned to clients that ask for ours
+    // --padding <bytes> pads tls and https responses to clients using EDNS
+    // to a multiple of that (468 by default), 0 turns it off
+    // --acl <transport>=<ip>[/<len>][,...] refuses queries over a transport
+    // (udp, tcp, tls or https) from clients outside those networks
+    // --rate-limit <transport>=<queries> refuses queries over a transport
+    // from clients sending more than that many a second
+    let mut args = env::args().skip(1);
+    let mut capture_dir = None;
+    let mut unix_path = None;
+    let mut tls_cert = None;
+    let mut tls_key = None;
+    let mut doh = false;
+    let mut ciphers = Vec::new();
+    let mut dot_addr = SocketAddr::from(([0, 0, 0, 0], 853));
+    let mut doh_addr = SocketAddr::from(([0, 0, 0, 0], 443));
+    let mut router = Router::new();
+    let mut policies = Policies::new();
+    let mut leases_path = None;
+    let mut local_domain = String::from("lan");
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--capture" => capture_dir = args.next().map(PathBuf::from),
+            "--unix" => unix_path = args.next().map(PathBuf::from),
+            "--tls-cert" => tls_cert = args.next().map(PathBuf::from),
+            "--tls-key" => tls_key = args.next().map(PathBuf::from),
+            "--doh" => doh = true,
+            "--tls-ciphers" => match args.next() {
+                Some(names) => ciphers = names.split(',').map(String::from).collect(),
+                None => eprintln!("invalid cipher suites, expected <suite>[,...]"),
+            },
+            "--dot-listen" | "--doh-listen" => match args.next().map(|addr| addr.parse()) {
+                Some(Ok(addr)) if flag == "--dot-listen" => dot_addr = addr,
+                Some(Ok(addr)) => doh_addr = addr,
+                _ => eprintln!("invalid listen address, expected <ip>:<port>"),
+            },
+            "--paranoid" => resolver.paranoid = true,
+            "--resolve-targets" => resolver.resolve_targets = true,
+            "--identify-clients" => resolver.identify_clients = true,
+            "--leases" => leases_path = args.next().map(PathBuf::from),
+            "--local-domain" => local_domain = args.next().unwrap_or(local_domain),
+            "--route" => match args.next().as_deref().and_then(parse_route) {
+                Some((qtype, upstream)) => router.route(qtype, upstream),
+                None => eprintln!(
+                    "invalid route, expected <type>=<ip>[:port][#<tls name>] \
+                     or <type>=<ip>[:port]@<provider name>/<public key>"
+                ),
+            },
+            "--edns-payload" => match args.next().map(|size| size.parse()) {
+                Some(Ok(size)) => resolver.edns_payload = size,
+                _ => eprintln!("invalid payload size, expected 0 to 65535"),
+            },
+            "--tcp-idle-timeout" => match args.next().map(|secs| secs.parse()) {
+                Some(Ok(secs)) => resolver.tcp_idle_timeout = Duration::from_secs(secs),
+                _ => eprintln!("invalid idle timeout, expected seconds"),
+            },
+            "--request-nsid" => resolver.request_nsid = true,
+            "--nsid" => resolver.nsid = args.next(),
+            "--padding" => match args.next().map(|bytes| bytes.parse()) {
+                Some(Ok(bytes)) => resolver.padding_block = bytes,
+                _ => eprintln!("invalid padding block size, expected bytes"),
+            },
+            "--spoof-window" => match args.next().map(|ms| ms.parse()) {
+                Some(Ok(ms)) => resolver.spoof_window = Some(Duration::from_millis(ms)),
+                _ => eprintln!("invalid spoof window, expected milliseconds"),
+            },
+            "--acl" => match args.next().as_deref().and_then(parse_acl) {
+                Some((transport, networks)) => policies.policy(transport).allow.extend(networks),
+                None => eprintln!("invalid acl, expected <transport>=<ip>[/<len>][,...]"),
+            },
+            "--rate-limit" => match args.next().as_deref().and_then(parse_rate_limit) {
+                Some((transport, rate)) => policies.policy(transport).rate = Some(rate),
+                None => eprintln!("invalid rate limit, expected <transport>=<queries a second>"),
+            },
+            _ => eprintln!("unknown flag {}", flag),
+        }
+    }
+    resolver.capture = Some(Capture::new(capture_dir, 10));
+    if !policies.rules.is_empty() {
+        resolver.layers.push(Box::new(policies));
+    }
+    if let Some(path) = leases_path {
+        resolver
+            .layers
+            .push(Box::new(Leases::new(path, &local_domain)));
+    }
+
+    // routes to our own listeners would only have queries wait on
+    // themselves, dns over tls included
+    if tls_cert.is_some() && tls_key.is_some() {
+        resolver.listen_addrs.push(dot_addr);
+    }
+    for upstream in router.drop_self(|addr| resolver.is_self(addr)) {
+        eprintln!(
+            "Not routing to {}, it is one of our listen addresses",
+            upstream
+        );
+    }
+    if !router.rules.is_empty() {
+        resolver.layers.push(Box::new(router));
+    }
+
+    thread::scope(|scope| {
+        let tcp_resolver = &resolver;
+        scope.spawn(move || tcp::serve(scope, tcp_resolver, tcp_listener));
+
+        if let Some(path) = unix_path {
+            let resolver = &resolver;
+            scope.spawn(move || unix::serve(resolver, path));
+        }
+
+        match (tls_cert, tls_key) {
+            (Some(cert), Some(key)) => {
+                let resolver = &resolver;
+                if doh {
+                    let (cert, key, ciphers) = (cert.clone(), key.clone(), ciphers.clone());
+                    scope.spawn(move || doh::serve(scope, resolver, doh_addr, cert, key, ciphers));
+                }
+                scope.spawn(move || dot::serve(scope, resolver, dot_addr, cert, key, ciphers));
+            }
+            (None, None) => {}
+            _ => eprintln!("dns over tls and https need both --tls-cert and --tls-key"),
+        }
+
+        // For now, queries are handled sequentially, so an infinite loop for servicing
+        // requests is initiated.
+        loop {
+            match resolver.handle_query(&listen_socket, &send_socket) {
+                Ok(_) => {}
+                Err(e) => eprintln!("An error occurred: {}", e),
+            }
+        }
+    });
+}
+
+// parse an acl like "udp=192.168.0.0/16,fd00::/8"
+fn parse_acl(acl: &str) -> Option<(Transport, Vec<(IpAddr, u8)>)> {
+    let (transport, networks) = acl.split_once('=')?;
+    let networks: Option<Vec<_>> = networks.split(',').map(policy::parse_network).collect();
+    Some((transport.parse().ok()?, networks?))
+}
+
+// parse a rate limit like "https=20", in queries a second
+fn parse_rate_limit(limit: &str) -> Option<(Transport, u32)> {
+    let (transport, rate) = limit.split_once('=')?;
+    let rate = rate.parse().ok().filter(|&rate| rate > 0)?;
+    Some((transport.parse().ok()?, rate))
+}
+
+// parse a route like "PTR=192.168.1.1", "HTTPS=1.1.1.1:53", or with a name
+// to verify the certificate for, a dns over tls one like
+// "A=1.1.1.1#cloudflare-dns.com". a dnscrypt one has the provider name and
+// its public key in hex, like "A=9.9.9.9:8443@2.dnscrypt-cert.quad9.net/67C8..."
+fn parse_route(route: &str) -> Option<(QueryType, Upstream)> {
+    let (qtype, upstream) = route.split_once('=')?;
+    let qtype = qtype.parse().ok()?;
+
+    if let Some((addr, provider)) = upstream.split_once('@') {
+        let (ip, port) = parse_addr(addr, 443)?;
+        let (provider, key) = provider.split_once('/')?;
+        return Some((qtype, dnscrypt_upstream(ip, port, provider, key)?));
+    }
+
+    let (addr, name) = match upstream.split_once('#') {
+        Some((addr, name)) => (addr, Some(name)),
+        None => (upstream, None),
+    };
+
+    let default_port = if name.is_some() { 853 } else { 53 };
+    let (ip, port) = parse_addr(addr, default_port)?;
+
+    let upstream = match name {
+        None => Upstream::Udp(ip, port),
+        #[cfg(feature = "tls")]
+        Some(name) => Upstream::Tls(ip, port, name.to_string()),
+        #[cfg(not(feature = "tls"))]
+        Some(_) => {
+            eprintln!("dns over tls routes need the tls feature");
+            return None;
+        }
+    };
+
+    Some((qtype, upstream))
+}
+
+// an "<ip>[:port]" address
+fn parse_addr(addr: &str, default_port: u16) -> Option<(Ipv4Addr, u16)> {
+    match addr.parse::<SocketAddrV4>() {
+        Ok(addr) => Some((*addr.ip(), addr.port())),
+        Err(_) => Some((addr.parse().ok()?, default_port)),
+    }
+}
+
+// the key is written in hex, optionally grouped with colons
+#[cfg(feature = "dnscrypt")]
+fn dnscrypt_upstream(ip: Ipv4Addr, port: u16, provider: &str, key: &str) -> Option<Upstream> {
+    let key: Vec<char> = key.chars().filter(|c| *c != ':').collect();
+    if key.len() != 64 {
+        return None;
+    }
+
+    let mut bytes = [0; 32];
+    for (byte, hex) in bytes.iter_mut().zip(key.chunks(2)) {
+        *byte = u8::from_str_radix(&hex.iter().collect::<String>(), 16).ok()?;
+    }
+    Some(Upstream::DnsCrypt(ip, port, provider.to_string(), bytes))
+}
+
+#[cfg(not(feature = "dnscrypt"))]
+fn dnscrypt_upstream(_: Ipv4Addr, _: u16, _: &str, _: &str) -> Option<Upstream> {
+    eprintln!("dnscrypt routes need the dnscrypt feature");
+    None
+}
+
+mod tcp {
+    use std::{net::TcpListener, thread::Scope};
+
+    use recursor::{Resolver, Transport};
 
-    // For now, queries are handled sequentially, so an infinite loop for servicing
-    // requests is initiated.
-    loop {
-        match handle_query(ns, &listen_socket, &send_socket) {
-            Ok(_) => {}
-            Err(e) => eprintln!("An error occurred: {}", e),
+    pub fn serve<'scope>(
+        scope: &'scope Scope<'scope, '_>,
+        resolver: &'scope Resolver,
+        listener: TcpListener,
+    ) {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => {
+                    eprintln!("An error occurred on tcp: failed to accept");
+                    continue;
+                }
+            };
+
+            // every connection gets its own thread, and its queries are
+            // answered concurrently, so a slow client or query only holds
+            // up itself
+            scope.spawn(move || {
+                let result = match (stream.peer_addr(), stream.try_clone()) {
+                    // connections left idle are closed (RFC 7766 6.2.3)
+                    (Ok(peer), Ok(mut writer)) => stream
+                        .set_read_timeout(Some(resolver.tcp_idle_timeout))
+                        .map_err(|_| "failed to set timeout")
+                        .and_then(|_| {
+                            resolver.handle_stream_concurrent(
+                                &mut stream,
+                                &mut writer,
+                                peer,
+                                Some(peer.ip()),
+                                Transport::Tcp,
+                            )
+                        }),
+                    _ => Err("failed to set up connection"),
+                };
+
+                if let Err(e) = result {
+                    eprintln!("An error occurred on tcp: {}", e);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{fs, net::UdpSocket, os::unix::net::UnixListener, path::PathBuf};
+
+    use recursor::{Resolver, Transport};
+
+    pub fn serve(resolver: &Resolver, path: PathBuf) {
+        // a socket left behind by a previous run would make bind fail
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        // the udp loop reads responses off its own query socket, so this
+        // listener needs a separate one
+        let send_socket = UdpSocket::bind(("0.0.0.0", 0)).unwrap();
+
+        // connections are served one at a time, like the udp queries
+        for stream in listener.incoming() {
+            let result = match stream {
+                Ok(mut stream) => {
+                    resolver.handle_stream(&mut stream, "unix", None, Transport::Unix, &send_socket)
+                }
+                Err(_) => Err("failed to accept"),
+            };
+
+            if let Err(e) = result {
+                eprintln!("An error occurred on {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod unix {
+    use std::path::PathBuf;
+
+    use recursor::Resolver;
+
+    pub fn serve(_: &Resolver, _: PathBuf) {
+        eprintln!("unix sockets are not supported on this platform");
+    }
+}
+
+#[cfg(feature = "tls")]
+mod dot {
+    use std::{
+        net::{SocketAddr, TcpListener, UdpSocket},
+        path::PathBuf,
+        thread::Scope,
+    };
+
+    use recursor::{tls, Resolver};
+
+    pub fn serve<'scope>(
+        scope: &'scope Scope<'scope, '_>,
+        resolver: &'scope Resolver,
+        addr: SocketAddr,
+        cert: PathBuf,
+        key: PathBuf,
+        ciphers: Vec<String>,
+    ) {
+        let config = match tls::server_config(&cert, &key, &ciphers) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Not serving dns over tls: {}", e);
+                return;
+            }
+        };
+        let listener = match TcpListener::bind(addr) {
+            Ok(listener) => listener,
+            Err(_) => {
+                eprintln!("Not serving dns over tls: failed to bind {}", addr);
+                return;
+            }
+        };
+
+        for stream in listener.incoming() {
+            let tcp = match stream {
+                Ok(tcp) => tcp,
+                Err(_) => {
+                    eprintln!("An error occurred on tls: failed to accept");
+                    continue;
+                }
+            };
+
+            // a tls stream can't be read and written from different threads,
+            // so its queries are answered in order, on one thread per
+            // connection
+            let config = config.clone();
+            scope.spawn(move || {
+                let result = match UdpSocket::bind(("0.0.0.0", 0)) {
+                    Ok(send_socket) => tls::serve(resolver, tcp, config, &send_socket),
+                    Err(_) => Err("failed to set up connection"),
+                };
+
+                if let Err(e) = result {
+                    eprintln!("An error occurred on tls: {}", e);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+mod doh {
+    use std::{
+        net::{SocketAddr, TcpListener, UdpSocket},
+        path::PathBuf,
+        thread::Scope,
+    };
+
+    use recursor::{tls, Resolver};
+    use rustls::{ServerConnection, StreamOwned};
+
+    pub fn serve<'scope>(
+        scope: &'scope Scope<'scope, '_>,
+        resolver: &'scope Resolver,
+        addr: SocketAddr,
+        cert: PathBuf,
+        key: PathBuf,
+        ciphers: Vec<String>,
+    ) {
+        let config = match tls::https_server_config(&cert, &key, &ciphers) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Not serving dns over https: {}", e);
+                return;
+            }
+        };
+        let listener = match TcpListener::bind(addr) {
+            Ok(listener) => listener,
+            Err(_) => {
+                eprintln!("Not serving dns over https: failed to bind {}", addr);
+                return;
+            }
+        };
+
+        for stream in listener.incoming() {
+            let tcp = match stream {
+                Ok(tcp) => tcp,
+                Err(_) => {
+                    eprintln!("An error occurred on https: failed to accept");
+                    continue;
+                }
+            };
+
+            // like dns over tls, one thread per connection answering its
+            // requests in order
+            let config = config.clone();
+            scope.spawn(move || {
+                let peer = tcp.peer_addr();
+                let setup = (
+                    tcp.set_read_timeout(Some(resolver.tcp_idle_timeout)),
+                    ServerConnection::new(config),
+                    UdpSocket::bind(("0.0.0.0", 0)),
+                );
+                let result = match (peer, setup) {
+                    (Ok(peer), (Ok(()), Ok(conn), Ok(send_socket))) => {
+                        let mut stream = StreamOwned::new(conn, tcp);
+                        resolver.handle_https(&mut stream, peer, Some(peer.ip()), &send_socket)
+                    }
+                    _ => Err("failed to set up connection"),
+                };
+
+                if let Err(e) = result {
+                    eprintln!("An error occurred on https: {}", e);
+                }
+            });
         }
     }
 }
+
+#[cfg(not(feature = "tls"))]
+mod doh {
+    use std::{net::SocketAddr, path::PathBuf, thread::Scope};
+
+    use recursor::Resolver;
+
+    pub fn serve<'scope>(
+        _: &'scope Scope<'scope, '_>,
+        _: &'scope Resolver,
+        _: SocketAddr,
+        _: PathBuf,
+        _: PathBuf,
+        _: Vec<String>,
+    ) {
+        eprintln!("dns over https needs the tls feature");
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+mod dot {
+    use std::{net::SocketAddr, path::PathBuf, thread::Scope};
+
+    use recursor::Resolver;
+
+    pub fn serve<'scope>(
+        _: &'scope Scope<'scope, '_>,
+        _: &'scope Resolver,
+        _: SocketAddr,
+        _: PathBuf,
+        _: PathBuf,
+        _: Vec<String>,
+    ) {
+        eprintln!("dns over tls needs the tls feature");
+    }
+}
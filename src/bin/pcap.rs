@@ -0,0 +1,210 @@
+use std::{collections::BTreeMap, env, fs};
+
+use recursor::{
+    buffer::PacketBuffer,
+    dns::{packet::DnsPacket, record::DnsRecord},
+    pcap::{self, Datagram},
+};
+
+// prints the dns messages in a pcap capture and a summary of them
+// usage: pcap <file> [--json] [--port <n>]
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let Some(path) = args.get(1).filter(|arg| !arg.starts_with("--")) else {
+        eprintln!("usage: {} <file> [--json] [--port <n>]", args[0]);
+        return;
+    };
+
+    let json = args.iter().any(|arg| arg == "--json");
+    // only datagrams to or from this port are taken as dns
+    let port: u16 = match args.iter().position(|arg| arg == "--port") {
+        Some(i) => args
+            .get(i + 1)
+            .and_then(|port| port.parse().ok())
+            .expect("invalid port"),
+        None => 53,
+    };
+
+    let data = fs::read(path).expect("failed to read capture");
+    let datagrams = pcap::datagrams(&data).expect("failed to read capture");
+
+    let mut stats = Stats::default();
+    for datagram in datagrams
+        .iter()
+        .filter(|datagram| datagram.src.port() == port || datagram.dst.port() == port)
+    {
+        stats.datagrams += 1;
+
+        let Some(packet) = parse(&datagram.payload) else {
+            stats.failed += 1;
+            continue;
+        };
+        stats.record(&packet);
+
+        if json {
+            println!("{}", to_json(datagram, &packet));
+        } else {
+            print_zone(datagram, &packet);
+        }
+    }
+
+    stats.print();
+}
+
+fn parse(payload: &[u8]) -> Option<DnsPacket> {
+    if payload.len() > PacketBuffer::MAX_LEN {
+        return None;
+    }
+
+    let mut buffer = PacketBuffer::with_len(payload.len());
+    buffer.as_mut_slice().copy_from_slice(payload);
+    DnsPacket::read(&mut buffer).ok()
+}
+
+// the header flags that are set, in the order dig lists them
+fn flags(packet: &DnsPacket) -> String {
+    let header = &packet.header;
+    let flags = [
+        (header.qr, "qr"),
+        (header.aa, "aa"),
+        (header.tc, "tc"),
+        (header.rd, "rd"),
+        (header.ra, "ra"),
+    ];
+    let set: Vec<&str> = flags
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| *name)
+        .collect();
+    set.join(" ")
+}
+
+// one message in the style of dig, the records in zone file format
+fn print_zone(datagram: &Datagram, packet: &DnsPacket) {
+    println!(
+        ";; {}.{:09} {} -> {} id {} {} {} [{}]",
+        datagram.secs,
+        datagram.nanos,
+        datagram.src,
+        datagram.dst,
+        packet.header.id,
+        packet.header.opcode,
+        packet.header.rcode,
+        flags(packet)
+    );
+    for question in &packet.questions {
+        println!(
+            ";{}.\t\t{}\t{}",
+            question.qname, question.qclass, question.qtype
+        );
+    }
+    for record in packet
+        .answers
+        .iter()
+        .chain(&packet.authorities)
+        .chain(&packet.additionals)
+    {
+        println!("{}", record);
+    }
+    println!();
+}
+
+// one message as a json object on a single line
+fn to_json(datagram: &Datagram, packet: &DnsPacket) -> String {
+    let questions: Vec<String> = packet
+        .questions
+        .iter()
+        .map(|question| {
+            format!(
+                "{{\"name\":{},\"class\":{},\"type\":{}}}",
+                string(&format!("{}.", question.qname)),
+                string(&question.qclass.to_string()),
+                string(&question.qtype.to_string())
+            )
+        })
+        .collect();
+    let records = |records: &[DnsRecord]| {
+        let records: Vec<String> = records
+            .iter()
+            .map(|record| string(&record.to_string()))
+            .collect();
+        format!("[{}]", records.join(","))
+    };
+
+    format!(
+        "{{\"time\":\"{}.{:09}\",\"src\":{},\"dst\":{},\"id\":{},\"opcode\":{},\"rcode\":{},\"flags\":{},\"questions\":[{}],\"answers\":{},\"authorities\":{},\"additionals\":{}}}",
+        datagram.secs,
+        datagram.nanos,
+        string(&datagram.src.to_string()),
+        string(&datagram.dst.to_string()),
+        packet.header.id,
+        string(&packet.header.opcode.to_string()),
+        string(&packet.header.rcode.to_string()),
+        string(&flags(packet)),
+        questions.join(","),
+        records(&packet.answers),
+        records(&packet.authorities),
+        records(&packet.additionals)
+    )
+}
+
+// a json string literal
+fn string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[derive(Default)]
+struct Stats {
+    datagrams: usize,
+    failed: usize,
+    queries: usize,
+    responses: usize,
+    rcodes: BTreeMap<String, usize>,
+    qtypes: BTreeMap<String, usize>,
+}
+
+impl Stats {
+    fn record(&mut self, packet: &DnsPacket) {
+        if packet.header.qr {
+            self.responses += 1;
+            *self
+                .rcodes
+                .entry(packet.header.rcode.to_string())
+                .or_insert(0) += 1;
+        } else {
+            self.queries += 1;
+            for question in &packet.questions {
+                *self.qtypes.entry(question.qtype.to_string()).or_insert(0) += 1;
+            }
+        }
+    }
+
+    // on stderr, so the messages on stdout can be piped on their own
+    fn print(&self) {
+        eprintln!(
+            ";; {} datagrams, {} parsed, {} failed",
+            self.datagrams,
+            self.datagrams - self.failed,
+            self.failed
+        );
+        eprintln!(";; {} queries, {} responses", self.queries, self.responses);
+        for (rcode, count) in &self.rcodes {
+            eprintln!(";;   {}: {}", rcode, count);
+        }
+        for (qtype, count) in &self.qtypes {
+            eprintln!(";;   {}: {}", qtype, count);
+        }
+    }
+}
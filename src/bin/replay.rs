@@ -0,0 +1,294 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    net::{Ipv4Addr, UdpSocket},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use recursor::{
+    buffer::PacketBuffer,
+    dns::{
+        packet::DnsPacket,
+        question::{DnsQuestion, QueryType},
+    },
+};
+
+mod common;
+
+// how long a query may go unanswered before it counts as an error
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+const USAGE: &str = "usage: replay <log file> [--server ip] [--port port] [--qps qps]
+       replay --pcap <capture> [--server ip] [--port port] [--speed x] [--dns-port port]";
+
+// a query to replay, and when to send it after the replay starts. queries
+// without a time are sent once the one before is answered
+struct Scheduled {
+    question: DnsQuestion,
+    at: Option<Duration>,
+}
+
+// replays the queries recorded in a server log, or captured with tcpdump,
+// against a resolver. a log has no timestamps, so it is replayed at --qps
+// (0 as fast as the resolver answers). a capture is replayed at the pace
+// it was taken, or --speed times as fast (0 as fast as possible)
+fn main() {
+    let mut input = None;
+    let mut pcap = false;
+    let mut server_ip = Ipv4Addr::LOCALHOST;
+    let mut port = 2053;
+    let mut qps: Option<u32> = None;
+    let mut speed: Option<f64> = None;
+    let mut dns_port = 53;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let valid = match arg.as_str() {
+            "--pcap" => {
+                pcap = true;
+                input = args.next();
+                input.is_some()
+            }
+            "--server" => parse(&mut args, &mut server_ip),
+            "--port" => parse(&mut args, &mut port),
+            "--qps" => parse(&mut args, qps.get_or_insert(0)),
+            "--speed" => parse(&mut args, speed.get_or_insert(1.0)),
+            "--dns-port" => parse(&mut args, &mut dns_port),
+            path if !path.starts_with("--") && input.is_none() => {
+                input = Some(path.to_string());
+                true
+            }
+            _ => false,
+        };
+        if !valid {
+            fail(&format!("invalid argument {}", arg));
+        }
+    }
+
+    let Some(input) = input else {
+        fail("missing the log file or capture");
+    };
+    if speed.is_some_and(|speed| speed < 0.0 || !speed.is_finite()) {
+        fail("invalid speed, expected a factor like 2 or 0.5");
+    }
+
+    let queries = match (pcap, qps, speed) {
+        (false, _, Some(_)) => fail("a log has no timestamps to speed up, pace it with --qps"),
+        (true, Some(_), _) => fail("a capture is paced by its timestamps, use --speed"),
+        (false, qps, None) => {
+            let log =
+                fs::read_to_string(&input).unwrap_or_else(|_| fail("failed to read log file"));
+            from_log(&log, qps.unwrap_or(0))
+        }
+        (true, None, speed) => {
+            let data = fs::read(&input).unwrap_or_else(|_| fail("failed to read capture"));
+            from_capture(&data, dns_port, speed.unwrap_or(1.0))
+        }
+    };
+
+    println!(
+        "Replaying {} queries against {}:{}",
+        queries.len(),
+        server_ip,
+        port
+    );
+    replay(&queries, (server_ip, port)).print();
+}
+
+// read the next argument into value, false if it's missing or invalid
+fn parse<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>, value: &mut T) -> bool {
+    match args.next().map(|arg| arg.parse()) {
+        Some(Ok(parsed)) => {
+            *value = parsed;
+            true
+        }
+        _ => false,
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    process::exit(2);
+}
+
+// the queries of the "Received query for <name> <type>" lines of the server
+// output, qps a second
+fn from_log(log: &str, qps: u32) -> Vec<Scheduled> {
+    let interval = match qps {
+        0 => None,
+        qps => Some(Duration::from_secs(1) / qps),
+    };
+
+    log.lines()
+        .filter_map(parse_line)
+        .enumerate()
+        .map(|(i, (qname, qtype))| Scheduled {
+            question: DnsQuestion::new(qname.to_string(), qtype),
+            at: interval.map(|interval| interval * i as u32),
+        })
+        .collect()
+}
+
+// parse a "Received query for <name> <type>" line from the server output
+fn parse_line(line: &str) -> Option<(&str, QueryType)> {
+    let mut parts = line.strip_prefix("Received query for ")?.split_whitespace();
+    let qname = parts.next()?;
+    let qtype = parts.next()?.parse().ok()?;
+
+    Some((qname, qtype))
+}
+
+// the queries sent to dns_port in a capture, at the times they were sent
+// there divided by speed
+#[cfg(feature = "pcap")]
+fn from_capture(data: &[u8], dns_port: u16, speed: f64) -> Vec<Scheduled> {
+    let datagrams = recursor::pcap::datagrams(data).unwrap_or_else(|e| fail(e));
+
+    let mut start = None;
+    datagrams
+        .iter()
+        .filter(|datagram| datagram.dst.port() == dns_port)
+        .filter_map(|datagram| {
+            let packet = read_packet(&datagram.payload)?;
+            if packet.header.qr {
+                return None;
+            }
+            let question = packet.questions.into_iter().next()?;
+
+            let sent = Duration::new(datagram.secs as u64, datagram.nanos);
+            let offset = sent.saturating_sub(*start.get_or_insert(sent));
+            let at = if speed == 0.0 {
+                Duration::ZERO
+            } else {
+                offset.div_f64(speed)
+            };
+            Some(Scheduled {
+                question,
+                at: Some(at),
+            })
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "pcap"))]
+fn from_capture(_: &[u8], _: u16, _: f64) -> Vec<Scheduled> {
+    fail("replaying captures needs the pcap feature");
+}
+
+#[cfg(feature = "pcap")]
+fn read_packet(payload: &[u8]) -> Option<DnsPacket> {
+    if payload.len() > PacketBuffer::MAX_LEN {
+        return None;
+    }
+
+    let mut buffer = PacketBuffer::with_len(payload.len());
+    buffer.as_mut_slice().copy_from_slice(payload);
+    DnsPacket::read(&mut buffer).ok()
+}
+
+// send the queries on schedule, each with an id of its own, and match the
+// responses to them by id and question. a late response to a query that
+// timed out is then dropped rather than taken for the next one's
+fn replay(queries: &[Scheduled], server: (Ipv4Addr, u16)) -> common::Report {
+    let socket = UdpSocket::bind(("0.0.0.0", 0)).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+
+    // queries waiting for a response by id, with when they were sent
+    let in_flight: Mutex<HashMap<u16, (DnsQuestion, Instant)>> = Mutex::new(HashMap::new());
+    let answered = Condvar::new();
+    let sent_all = AtomicBool::new(false);
+
+    let mut report = common::Report::new();
+    thread::scope(|scope| {
+        let receiver = scope.spawn(|| {
+            let mut report = common::Report::new();
+            loop {
+                let mut buffer = PacketBuffer::new();
+                let response = socket
+                    .recv_from(buffer.as_mut_slice())
+                    .ok()
+                    .and_then(|_| DnsPacket::read(&mut buffer).ok());
+
+                let mut in_flight = in_flight.lock().unwrap_or_else(|e| e.into_inner());
+                if let Some(response) = response {
+                    let id = response.header.id;
+                    // names come back lowercased
+                    let matches = in_flight.get(&id).is_some_and(|(question, _)| {
+                        response.questions.first().is_some_and(|answered| {
+                            answered.qname.eq_ignore_ascii_case(&question.qname)
+                                && answered.qtype == question.qtype
+                        })
+                    });
+                    if matches {
+                        let (_, sent) = in_flight.remove(&id).unwrap();
+                        report.record(Ok(response), sent.elapsed());
+                    }
+                }
+
+                in_flight.retain(|_, (_, sent)| {
+                    let expired = sent.elapsed() >= TIMEOUT;
+                    if expired {
+                        report.record(Err("timed out"), sent.elapsed());
+                    }
+                    !expired
+                });
+                answered.notify_all();
+
+                if sent_all.load(Ordering::SeqCst) && in_flight.is_empty() {
+                    return report;
+                }
+            }
+        });
+
+        let start = Instant::now();
+        for (i, query) in queries.iter().enumerate() {
+            match query.at {
+                Some(at) => thread::sleep(at.saturating_sub(start.elapsed())),
+                None => {
+                    let in_flight = in_flight.lock().unwrap_or_else(|e| e.into_inner());
+                    drop(
+                        answered
+                            .wait_while(in_flight, |in_flight| !in_flight.is_empty())
+                            .unwrap_or_else(|e| e.into_inner()),
+                    );
+                }
+            }
+
+            let id = i as u16;
+            let mut packet = DnsPacket::new();
+            packet.header.id = id;
+            packet.header.rd = true;
+            packet.add_question(query.question.clone());
+            let mut buffer = PacketBuffer::new();
+            if packet.write(&mut buffer).is_err() {
+                report.record(Err("failed to write query"), Duration::ZERO);
+                continue;
+            }
+
+            in_flight
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(id, (query.question.clone(), Instant::now()));
+            if socket.send_to(buffer.as_slice(), server).is_err() {
+                in_flight
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .remove(&id);
+                report.record(Err("failed to send"), Duration::ZERO);
+            }
+        }
+        sent_all.store(true, Ordering::SeqCst);
+
+        report.merge(receiver.join().unwrap());
+    });
+
+    report
+}
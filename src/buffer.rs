@@ -1,19 +1,46 @@
+use std::collections::HashMap;
+
 pub struct PacketBuffer {
-    pub buf: [u8; 512],
+    pub buf: Vec<u8>,
     pub pos: usize,
 }
 
+impl Default for PacketBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PacketBuffer {
-    const LEN: usize = 512;
+    // largest message there is, the length prefix of streams is a u16
+    pub const MAX_LEN: usize = 65535;
 
-    // fresh packet buffer
+    // fresh packet buffer, sized for plain udp
     pub fn new() -> PacketBuffer {
+        Self::with_len(512)
+    }
+
+    // fresh packet buffer of len bytes, at most MAX_LEN. the size is fixed,
+    // reads and writes past it fail like they do past 512 bytes
+    pub fn with_len(len: usize) -> PacketBuffer {
         PacketBuffer {
-            buf: [0; 512],
+            buf: vec![0; len.min(Self::MAX_LEN)],
             pos: 0,
         }
     }
 
+    // bytes that can be read or written
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    // the whole buffer, for receiving into
+    #[inline]
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+
     #[inline]
     pub fn as_slice(&self) -> &[u8] {
         &self.buf[0..self.pos]
@@ -39,7 +66,7 @@ impl PacketBuffer {
 
     #[inline]
     pub fn get(&self, pos: usize) -> Result<u8, &'static str> {
-        if pos >= Self::LEN {
+        if pos >= self.buf.len() {
             return Err("out of bounds");
         }
 
@@ -48,7 +75,7 @@ impl PacketBuffer {
 
     #[inline]
     pub fn get_range(&self, start: usize, end: usize) -> Result<&[u8], &'static str> {
-        if start + end >= Self::LEN {
+        if start > end || end > self.buf.len() {
             return Err("out of bounds");
         }
 
@@ -57,7 +84,7 @@ impl PacketBuffer {
 
     #[inline]
     pub fn read_slice<const N: usize>(&mut self) -> Result<[u8; N], &'static str> {
-        if self.pos + N >= Self::LEN {
+        if self.pos + N > self.buf.len() {
             return Err("end of buffer");
         }
 
@@ -71,7 +98,7 @@ impl PacketBuffer {
     // read a byte from the buffer, and step forward
     #[inline]
     pub fn read_u8(&mut self) -> Result<u8, &'static str> {
-        if self.pos >= Self::LEN {
+        if self.pos >= self.buf.len() {
             return Err("end of buffer");
         }
 
@@ -83,7 +110,7 @@ impl PacketBuffer {
     // read 2 bytes from the buffer, and step forward
     #[inline]
     pub fn read_u16(&mut self) -> Result<u16, &'static str> {
-        if self.pos + 2 >= Self::LEN {
+        if self.pos + 2 > self.buf.len() {
             return Err("end of buffer");
         }
 
@@ -97,7 +124,7 @@ impl PacketBuffer {
     // read 4 bytes from the buffer, and step forward
     #[inline]
     pub fn read_u32(&mut self) -> Result<u32, &'static str> {
-        if self.pos + 4 >= Self::LEN {
+        if self.pos + 4 > self.buf.len() {
             return Err("end of buffer");
         }
 
@@ -188,7 +215,7 @@ impl PacketBuffer {
 
     #[inline]
     pub fn write_u8(&mut self, val: u8) -> Result<(), &'static str> {
-        if self.pos >= Self::LEN {
+        if self.pos >= self.buf.len() {
             return Err("end of buffer");
         }
 
@@ -200,7 +227,7 @@ impl PacketBuffer {
 
     #[inline]
     pub fn write_u16(&mut self, val: u16) -> Result<(), &'static str> {
-        if self.pos + 2 >= Self::LEN {
+        if self.pos + 2 > self.buf.len() {
             return Err("end of buffer");
         }
 
@@ -212,7 +239,7 @@ impl PacketBuffer {
 
     #[inline]
     pub fn write_u32(&mut self, val: u32) -> Result<(), &'static str> {
-        if self.pos + 4 >= Self::LEN {
+        if self.pos + 4 > self.buf.len() {
             return Err("end of buffer");
         }
 
@@ -224,7 +251,7 @@ impl PacketBuffer {
 
     #[inline]
     pub fn write_slice<const N: usize>(&mut self, slice: &[u8; N]) -> Result<(), &'static str> {
-        if self.pos + N >= Self::LEN {
+        if self.pos + N > self.buf.len() {
             return Err("end of buffer");
         }
 
@@ -257,7 +284,7 @@ impl PacketBuffer {
 
     #[inline]
     pub fn set_u8(&mut self, pos: usize, val: u8) -> Result<(), &'static str> {
-        if pos >= Self::LEN {
+        if pos >= self.buf.len() {
             return Err("end of buffer");
         }
 
@@ -267,7 +294,7 @@ impl PacketBuffer {
 
     #[inline]
     pub fn set_u16(&mut self, pos: usize, val: u16) -> Result<(), &'static str> {
-        if pos + 2 >= Self::LEN {
+        if pos + 2 > self.buf.len() {
             return Err("end of buffer");
         }
 
@@ -277,7 +304,7 @@ impl PacketBuffer {
 
     #[inline]
     pub fn set_u32(&mut self, pos: usize, val: u32) -> Result<(), &'static str> {
-        if pos + 4 >= Self::LEN {
+        if pos + 4 > self.buf.len() {
             return Err("end of buffer");
         }
 
@@ -285,3 +312,64 @@ impl PacketBuffer {
         Ok(())
     }
 }
+
+// writes names into a packet buffer, replacing suffixes that were already
+// written with a pointer back to them (RFC 1035 4.1.4)
+// one writer is used per packet, as the offsets it remembers are only
+// valid within the buffer they were written to
+#[derive(Debug, Default)]
+pub struct NameWriter {
+    offsets: HashMap<String, u16>, // suffix -> position it was first written at
+}
+
+impl NameWriter {
+    pub fn new() -> Self {
+        NameWriter {
+            offsets: HashMap::new(),
+        }
+    }
+
+    // write name at the current position, pointing at an earlier copy of
+    // its longest known suffix if compress is set. names written without
+    // compression can still be pointed at by later ones
+    pub fn write_name(
+        &mut self,
+        buf: &mut PacketBuffer,
+        name: &str,
+        compress: bool,
+    ) -> Result<(), &'static str> {
+        let name = name.trim_end_matches('.');
+        let labels: Vec<&str> = match name {
+            "" => Vec::new(),
+            name => name.split('.').collect(),
+        };
+
+        for i in 0..labels.len() {
+            let suffix = labels[i..].join(".").to_ascii_lowercase();
+
+            if compress {
+                if let Some(offset) = self.offsets.get(&suffix) {
+                    return buf.write_u16(0xC000 | offset);
+                }
+            }
+
+            // pointers only have 14 bits for the offset
+            if buf.pos() < 0x4000 {
+                self.offsets.entry(suffix).or_insert(buf.pos() as u16);
+            }
+
+            let label = labels[i];
+            if label.len() > 63 {
+                return Err("label too long (max 63 bytes)");
+            }
+
+            buf.write_u8(label.len() as u8)?;
+            for byte in label.as_bytes() {
+                buf.write_u8(*byte)?;
+            }
+        }
+
+        // write the zero length byte
+        buf.write_u8(0)
+    }
+}
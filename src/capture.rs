@@ -0,0 +1,117 @@
+use std::{
+    fmt::{Display, Write as _},
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+// dumps packets that failed to parse, so interop bugs with odd clients
+// and servers can be looked at after the fact
+pub struct Capture {
+    dir: Option<PathBuf>, // write a file per packet here, or log to stderr if None
+    limit: u32,           // max packets captured per minute
+    window: Mutex<(Instant, u32)>,
+    captured: AtomicU64, // numbers the files, packets can arrive in the same millisecond
+}
+
+impl Capture {
+    // creating dir if it doesn't exist yet
+    pub fn new(dir: Option<PathBuf>, limit: u32) -> Self {
+        if let Some(dir) = &dir {
+            if let Err(e) = fs::create_dir_all(dir) {
+                eprintln!(
+                    "failed to create capture directory {}: {}",
+                    dir.display(),
+                    e
+                );
+            }
+        }
+
+        Capture {
+            dir,
+            limit,
+            window: Mutex::new((Instant::now(), 0)),
+            captured: AtomicU64::new(0),
+        }
+    }
+
+    // record a malformed packet, dropped silently once the rate limit is hit
+    pub fn record<A: Display>(&self, src: A, data: &[u8], err: &str) {
+        if !self.allow() {
+            return;
+        }
+
+        let dump = format!("source: {}\nerror: {}\n{}", src, err, hexdump(data));
+        match &self.dir {
+            Some(dir) => {
+                let millis = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis();
+                let n = self.captured.fetch_add(1, Ordering::Relaxed);
+                // sources are addresses like [::1]:53, of identified clients
+                // followed by their MAC address in parentheses
+                let src: String = src
+                    .to_string()
+                    .chars()
+                    .map(|c| match c {
+                        'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' => c,
+                        _ => '_',
+                    })
+                    .collect();
+                let path = dir.join(format!("{}-{}-{}.txt", millis, n, src));
+                if let Err(e) = fs::write(&path, dump) {
+                    eprintln!("failed to write capture {}: {}", path.display(), e);
+                }
+            }
+            None => eprintln!("Captured malformed packet\n{}", dump),
+        }
+    }
+
+    fn allow(&self) -> bool {
+        let mut window = match self.window.lock() {
+            Ok(window) => window,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        // start a new window every minute
+        if window.0.elapsed() >= Duration::from_secs(60) {
+            *window = (Instant::now(), 0);
+        }
+
+        if window.1 >= self.limit {
+            return false;
+        }
+
+        window.1 += 1;
+        true
+    }
+}
+
+// offset, 16 hex bytes and the printable ascii per line
+fn hexdump(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, chunk) in data.chunks(16).enumerate() {
+        let _ = write!(out, "{:04x}  ", i * 16);
+        for byte in chunk {
+            let _ = write!(out, "{:02x} ", byte);
+        }
+        for _ in chunk.len()..16 {
+            out.push_str("   ");
+        }
+        out.push(' ');
+        for &byte in chunk {
+            out.push(if byte.is_ascii_graphic() {
+                byte as char
+            } else {
+                '.'
+            });
+        }
+        out.push('\n');
+    }
+    out
+}
@@ -0,0 +1,94 @@
+use std::{
+    sync::Mutex,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::buffer::PacketBuffer;
+
+// test only fault injection for upstream responses, so the resolution
+// logic can be exercised against a misbehaving network
+// each probability is checked independently, between 0.0 and 1.0
+pub struct Chaos {
+    pub loss: f64,         // response is dropped, as if the query timed out
+    pub delay: f64,        // response is held back by latency
+    pub latency: Duration, // how long a delayed response waits
+    pub truncate: f64,     // response comes back with tc set and no records
+    pub malform: f64,      // a byte past the header is corrupted
+    rng: Mutex<u64>,
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Chaos {
+    // no faults until the probabilities are set
+    pub fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+
+        Chaos {
+            loss: 0.0,
+            delay: 0.0,
+            latency: Duration::from_millis(500),
+            truncate: 0.0,
+            malform: 0.0,
+            rng: Mutex::new(seed | 1),
+        }
+    }
+
+    // fixed seed, so a failing run can be reproduced
+    pub fn with_seed(seed: u64) -> Self {
+        let chaos = Self::new();
+        *chaos.rng.lock().unwrap() = seed | 1;
+        chaos
+    }
+
+    // apply faults to a raw response of len bytes before it is parsed
+    pub fn inject(&self, buf: &mut PacketBuffer, len: usize) -> Result<(), &'static str> {
+        if self.roll(self.loss) {
+            return Err("failed to recv");
+        }
+
+        if self.roll(self.delay) {
+            thread::sleep(self.latency);
+        }
+
+        if self.roll(self.truncate) {
+            buf.set_u8(2, buf.get(2)? | 0x02)?; // tc flag
+            buf.set_u16(6, 0)?; // ancount
+            buf.set_u16(8, 0)?; // nscount
+            buf.set_u16(10, 0)?; // arcount
+        }
+
+        if len > 12 && self.roll(self.malform) {
+            let pos = 12 + (self.next() as usize % (len - 12));
+            buf.set_u8(pos, buf.get(pos)? ^ 0xFF)?;
+        }
+
+        Ok(())
+    }
+
+    fn roll(&self, probability: f64) -> bool {
+        // uniform float in [0, 1)
+        let sample = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        probability > 0.0 && sample < probability
+    }
+
+    // xorshift, shared by every thread using the resolver
+    fn next(&self) -> u64 {
+        let mut state = match self.rng.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+}
@@ -0,0 +1,66 @@
+use super::{header::ResultCode, packet::DnsPacket, record::DnsRecord};
+
+// collects the records of a zone transfer (RFC 5936), which arrive over as
+// many messages as the zone needs: the zone's SOA first, then the rest of
+// the zone, then the same SOA again. messages are pushed in the order they
+// come off the stream, e.g. from StreamDecoder::next_packet
+#[derive(Debug, Default)]
+pub struct AxfrAssembler {
+    records: Vec<DnsRecord>, // the zone so far, starting with its SOA
+    complete: bool,          // the closing SOA has been seen
+}
+
+impl AxfrAssembler {
+    pub fn new() -> Self {
+        AxfrAssembler {
+            records: Vec::new(),
+            complete: false,
+        }
+    }
+
+    // consume the next message of the transfer, returning whether it was
+    // the last one
+    pub fn push(&mut self, packet: DnsPacket) -> Result<bool, &'static str> {
+        if packet.header.rcode != ResultCode::NOERROR {
+            return Err("zone transfer refused");
+        }
+
+        for record in packet.answers {
+            if self.complete {
+                return Err("records after the closing SOA");
+            }
+
+            match self.records.first() {
+                None if !is_soa(&record) => return Err("zone transfer does not start with a SOA"),
+                Some(first) if is_soa(&record) => {
+                    // the closing SOA repeats the opening one, anything else
+                    // means the zone changed while it was being sent
+                    if !record.same_data(first) {
+                        return Err("zone transfer ends with a different SOA");
+                    }
+                    self.complete = true;
+                }
+                _ => self.records.push(record),
+            }
+        }
+
+        Ok(self.complete)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    // the records of the zone, with its SOA first and only once
+    pub fn finish(self) -> Result<Vec<DnsRecord>, &'static str> {
+        if !self.complete {
+            return Err("zone transfer incomplete");
+        }
+
+        Ok(self.records)
+    }
+}
+
+fn is_soa(record: &DnsRecord) -> bool {
+    matches!(record, DnsRecord::SOA { .. })
+}
@@ -1,8 +1,8 @@
-use packed_struct::prelude::*;
+use std::{fmt, str::FromStr};
 
 use crate::buffer::PacketBuffer;
 
-#[derive(PrimitiveEnum_u8, Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ResultCode {
     NOERROR = 0,  // no error condition
     FORMERR = 1,  // format error - the name server was unable to interpret the query
@@ -12,69 +12,173 @@ pub enum ResultCode {
     REFUSED = 5, // refused - the name server refuses to perform the specified operation for policy reasons
 }
 
-impl ResultCode {
+// unassigned codes are rejected rather than guessed at
+impl TryFrom<u8> for ResultCode {
+    type Error = &'static str;
+
     #[inline]
-    pub fn from_u8(val: u8) -> ResultCode {
+    fn try_from(val: u8) -> Result<Self, Self::Error> {
         match val {
-            1 => ResultCode::FORMERR,
-            2 => ResultCode::SERVFAIL,
-            3 => ResultCode::NXDOMAIN,
-            4 => ResultCode::NOTIMP,
-            5 => ResultCode::REFUSED,
-            0 | _ => ResultCode::NOERROR,
+            0 => Ok(ResultCode::NOERROR),
+            1 => Ok(ResultCode::FORMERR),
+            2 => Ok(ResultCode::SERVFAIL),
+            3 => Ok(ResultCode::NXDOMAIN),
+            4 => Ok(ResultCode::NOTIMP),
+            5 => Ok(ResultCode::REFUSED),
+            _ => Err("unknown result code"),
         }
     }
 }
 
-#[derive(PackedStruct, Clone, Copy, Debug, PartialEq, Eq)]
-#[packed_struct(bit_numbering = "msb0")]
-pub struct DnsHeader {
-    #[packed_field(bits = "0..=15", endian = "msb")]
-    pub id: u16, // identification number; 16 bits
+impl From<ResultCode> for u8 {
+    #[inline]
+    fn from(rcode: ResultCode) -> Self {
+        rcode as u8
+    }
+}
 
-    #[packed_field(bits = "16")]
-    pub qr: bool, // query (0) or response (1); 1 bit
+impl fmt::Display for ResultCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ResultCode::NOERROR => "NOERROR",
+            ResultCode::FORMERR => "FORMERR",
+            ResultCode::SERVFAIL => "SERVFAIL",
+            ResultCode::NXDOMAIN => "NXDOMAIN",
+            ResultCode::NOTIMP => "NOTIMP",
+            ResultCode::REFUSED => "REFUSED",
+        })
+    }
+}
 
-    #[packed_field(bits = "17..=20")]
-    pub opcode: Integer<u8, packed_bits::Bits<4>>, // operation code; 4 bits
+// accepts the mnemonic in any case, or the numeric code
+impl FromStr for ResultCode {
+    type Err = &'static str;
 
-    #[packed_field(bits = "21")]
-    pub aa: bool, // authoritative answer; 1 bit
-    #[packed_field(bits = "22")]
-    pub tc: bool, // truncated; 1 bit
-    #[packed_field(bits = "23")]
-    pub rd: bool, // recursion desired; 1 bit
-    #[packed_field(bits = "24")]
-    pub ra: bool, // recursion available; 1 bit
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "NOERROR" => Ok(ResultCode::NOERROR),
+            "FORMERR" => Ok(ResultCode::FORMERR),
+            "SERVFAIL" => Ok(ResultCode::SERVFAIL),
+            "NXDOMAIN" => Ok(ResultCode::NXDOMAIN),
+            "NOTIMP" => Ok(ResultCode::NOTIMP),
+            "REFUSED" => Ok(ResultCode::REFUSED),
+            other => match other.parse::<u8>() {
+                Ok(val) => ResultCode::try_from(val),
+                Err(_) => Err("unknown result code"),
+            },
+        }
+    }
+}
 
-    #[packed_field(bits = "25..=27")]
-    pub z: Integer<u8, packed_bits::Bits<3>>, // reserved for future use; 3 bits
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Opcode {
+    Query,       // 0 - a standard query
+    IQuery,      // 1 - an inverse query, obsolete
+    Status,      // 2 - a server status request
+    Notify,      // 4 - zone change notification
+    Update,      // 5 - dynamic update
+    Unknown(u8), // any other 4 bit value
+}
+
+impl From<u8> for Opcode {
+    #[inline]
+    fn from(val: u8) -> Self {
+        match val {
+            0 => Opcode::Query,
+            1 => Opcode::IQuery,
+            2 => Opcode::Status,
+            4 => Opcode::Notify,
+            5 => Opcode::Update,
+            _ => Opcode::Unknown(val),
+        }
+    }
+}
+
+impl From<Opcode> for u8 {
+    #[inline]
+    fn from(opcode: Opcode) -> Self {
+        match opcode {
+            Opcode::Query => 0,
+            Opcode::IQuery => 1,
+            Opcode::Status => 2,
+            Opcode::Notify => 4,
+            Opcode::Update => 5,
+            Opcode::Unknown(val) => val,
+        }
+    }
+}
 
-    #[packed_field(bits = "28..=31", endian = "msb", ty = "enum")]
+// unknown opcodes are written as OPCODE<n>
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Opcode::Query => f.write_str("QUERY"),
+            Opcode::IQuery => f.write_str("IQUERY"),
+            Opcode::Status => f.write_str("STATUS"),
+            Opcode::Notify => f.write_str("NOTIFY"),
+            Opcode::Update => f.write_str("UPDATE"),
+            Opcode::Unknown(val) => write!(f, "OPCODE{}", val),
+        }
+    }
+}
+
+// accepts the mnemonic in any case, or OPCODE<n>
+impl FromStr for Opcode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let upper = s.to_ascii_uppercase();
+        match upper.as_str() {
+            "QUERY" => Ok(Opcode::Query),
+            "IQUERY" => Ok(Opcode::IQuery),
+            "STATUS" => Ok(Opcode::Status),
+            "NOTIFY" => Ok(Opcode::Notify),
+            "UPDATE" => Ok(Opcode::Update),
+            _ => match upper.strip_prefix("OPCODE").map(str::parse::<u8>) {
+                Some(Ok(val)) if val < 16 => Ok(Opcode::from(val)),
+                _ => Err("unknown opcode"),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DnsHeader {
+    pub id: u16, // identification number; 16 bits
+
+    pub qr: bool,          // query (0) or response (1); 1 bit
+    pub opcode: Opcode,    // operation code; 4 bits
+    pub aa: bool,          // authoritative answer; 1 bit
+    pub tc: bool,          // truncated; 1 bit
+    pub rd: bool,          // recursion desired; 1 bit
+    pub ra: bool,          // recursion available; 1 bit
+    pub z: u8,             // reserved for future use; 3 bits
     pub rcode: ResultCode, // response code; 4 bits
 
-    #[packed_field(bits = "32..=47", endian = "msb")]
     pub qdcount: u16, // number of entries in the question section; 16 bits
-    #[packed_field(bits = "48..=63", endian = "msb")]
     pub ancount: u16, // number of resource records in the answer section; 16 bits
-    #[packed_field(bits = "64..=79", endian = "msb")]
     pub nscount: u16, // number of name server resource records in the authority records section; 16 bits
-    #[packed_field(bits = "80..=95", endian = "msb")]
     pub arcount: u16, // number of resource records in the additional records section; 16 bits
 }
 
+impl Default for DnsHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DnsHeader {
     pub fn new() -> Self {
         DnsHeader {
             id: 0,
             qr: false,
-            opcode: 0.into(),
+            opcode: Opcode::Query,
             aa: false,
             tc: false,
             rd: false,
             ra: false,
 
-            z: 0.into(),
+            z: 0,
 
             rcode: ResultCode::NOERROR,
             qdcount: 0,
@@ -85,20 +189,46 @@ impl DnsHeader {
     }
 
     pub fn read(buf: &mut PacketBuffer) -> Result<Self, &'static str> {
-        let buf = buf.read_slice::<12>()?;
-        match DnsHeader::unpack(&buf) {
-            Ok(header) => Ok(header),
-            Err(_) => Err("Failed to unpack header"),
-        }
+        let id = buf.read_u16()?;
+
+        // qr | opcode (4) | aa | tc | rd | ra | z (3) | rcode (4)
+        let flags = buf.read_u16()?;
+
+        Ok(DnsHeader {
+            id,
+            qr: flags & 0x8000 != 0,
+            opcode: Opcode::from((flags >> 11) as u8 & 0x0F),
+            aa: flags & 0x0400 != 0,
+            tc: flags & 0x0200 != 0,
+            rd: flags & 0x0100 != 0,
+            ra: flags & 0x0080 != 0,
+            z: (flags >> 4) as u8 & 0x07,
+            rcode: ResultCode::try_from(flags as u8 & 0x0F)?,
+
+            qdcount: buf.read_u16()?,
+            ancount: buf.read_u16()?,
+            nscount: buf.read_u16()?,
+            arcount: buf.read_u16()?,
+        })
     }
 
     pub fn write(&self, buf: &mut PacketBuffer) -> Result<(), &'static str> {
-        match self.pack() {
-            Ok(packed) => {
-                buf.write_slice(&packed)?;
-                Ok(())
-            }
-            Err(_) => Err("Failed to pack header"),
-        }
+        let flags = (self.qr as u16) << 15
+            | (u8::from(self.opcode) as u16 & 0x0F) << 11
+            | (self.aa as u16) << 10
+            | (self.tc as u16) << 9
+            | (self.rd as u16) << 8
+            | (self.ra as u16) << 7
+            | (self.z as u16 & 0x07) << 4
+            | u8::from(self.rcode) as u16;
+
+        buf.write_u16(self.id)?;
+        buf.write_u16(flags)?;
+        buf.write_u16(self.qdcount)?;
+        buf.write_u16(self.ancount)?;
+        buf.write_u16(self.nscount)?;
+        buf.write_u16(self.arcount)?;
+
+        Ok(())
     }
 }
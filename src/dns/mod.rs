@@ -1,4 +1,8 @@
+pub mod axfr;
 pub mod header;
+pub mod name;
 pub mod packet;
+pub mod presentation;
 pub mod question;
 pub mod record;
+pub mod stream;
@@ -0,0 +1,23 @@
+use std::cmp::Ordering;
+
+// order two names the way DNSSEC does (RFC 4034 6.1): label by label from
+// the rightmost, comparing labels as lowercased bytes, so a name sorts
+// right before the names below it. example < a.example < z.example <
+// a.z.example
+pub fn canonical_cmp(a: &str, b: &str) -> Ordering {
+    let labels = |name: &str| -> Vec<Vec<u8>> {
+        name.trim_end_matches('.')
+            .split('.')
+            .filter(|label| !label.is_empty())
+            .map(|label| label.as_bytes().to_ascii_lowercase())
+            .rev()
+            .collect()
+    };
+
+    labels(a).cmp(&labels(b))
+}
+
+// sort names into canonical order
+pub fn canonical_sort<S: AsRef<str>>(names: &mut [S]) {
+    names.sort_by(|a, b| canonical_cmp(a.as_ref(), b.as_ref()));
+}
@@ -1,8 +1,28 @@
-use std::net::Ipv4Addr;
+use std::{net::Ipv4Addr, time::Duration};
 
-use crate::buffer::PacketBuffer;
+use crate::buffer::{NameWriter, PacketBuffer};
 
-use super::{header::DnsHeader, question::DnsQuestion, record::DnsRecord};
+use super::{
+    header::{DnsHeader, ResultCode},
+    question::{DnsClass, DnsQuestion, QueryType},
+    record::DnsRecord,
+};
+
+// EDNS option codes (RFC 6891 6.1.2)
+pub const OPTION_NSID: u16 = 3; // name server identifier (RFC 5001)
+pub const OPTION_TCP_KEEPALIVE: u16 = 11; // idle timeout of a tcp connection (RFC 7828)
+pub const OPTION_PADDING: u16 = 12; // zeros hiding the length of the message (RFC 7830)
+
+// what a response tells us about the question it answers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseKind {
+    Answer,   // records for the question are in the answer section
+    Referral, // delegation to nameservers closer to the name
+    NoData,   // the name exists, but has no records of the requested type
+    NxDomain, // the name does not exist
+    Lame,     // not authoritative and no referral to follow
+    Error,    // any other response code
+}
 
 #[derive(Debug, Clone)]
 pub struct DnsPacket {
@@ -13,6 +33,12 @@ pub struct DnsPacket {
     pub additionals: Vec<DnsRecord>,
 }
 
+impl Default for DnsPacket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DnsPacket {
     pub fn new() -> Self {
         DnsPacket {
@@ -24,15 +50,53 @@ impl DnsPacket {
         }
     }
 
+    // build a referral for question to the nameservers of zone, the way an
+    // authoritative server answers for names below one of its zone cuts.
+    // the NS records go in the authorities with aa clear, and whichever of
+    // the glue records are addresses of those nameservers in the additionals
+    pub fn referral<'a, I: IntoIterator<Item = &'a DnsRecord>>(
+        question: DnsQuestion,
+        zone: &str,
+        nameservers: &[&str],
+        glue: I,
+        ttl: u32,
+    ) -> Self {
+        let mut packet = DnsPacket::new();
+        packet.header.qr = true;
+        packet.header.aa = false;
+        packet.header.rcode = ResultCode::NOERROR;
+        packet.add_question(question);
+
+        for ns in nameservers {
+            packet.add_authority(DnsRecord::NS {
+                domain: zone.to_string(),
+                ns: ns.to_string(),
+                class: DnsClass::IN,
+                ttl,
+            });
+        }
+
+        for record in glue {
+            if nameservers.iter().any(|ns| record.is_address_of(ns)) {
+                packet.add_additional(record.clone());
+            }
+        }
+
+        packet
+    }
+
     // get the first A record from the answers
     // it does not matter which one we use
     pub fn get_any_a(&self) -> Option<Ipv4Addr> {
-        for answer in &self.answers {
-            if let DnsRecord::A { addr, .. } = answer {
-                return Some(*addr);
-            }
-        }
-        None
+        self.iter_a().next()
+    }
+
+    // iterate over all A records in the answers
+    pub fn iter_a(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        self.answers.iter().filter_map(|answer| match answer {
+            DnsRecord::A { addr, .. } => Some(*addr),
+            _ => None,
+        })
     }
 
     // iterate over all name servers in the authorities
@@ -48,16 +112,19 @@ impl DnsPacket {
     // get the first actual ip for an ns record if it exists
     // look for a matching a record in the additionals
     pub fn get_resolved_ns(&self, qname: &str) -> Option<Ipv4Addr> {
-        for (_, ns) in self.iter_ns(qname) {
-            for record in &self.additionals {
-                if let DnsRecord::A { domain, addr, .. } = record {
-                    if domain == ns {
-                        return Some(*addr);
-                    }
-                }
-            }
-        }
-        None
+        self.iter_resolved_ns(qname).next()
+    }
+
+    // iterate over the glue addresses of all name servers in the authorities
+    pub fn iter_resolved_ns<'a>(&'a self, qname: &'a str) -> impl Iterator<Item = Ipv4Addr> + 'a {
+        self.iter_ns(qname).flat_map(move |(_, ns)| {
+            self.additionals
+                .iter()
+                .filter_map(move |record| match record {
+                    DnsRecord::A { domain, addr, .. } if domain == ns => Some(*addr),
+                    _ => None,
+                })
+        })
     }
 
     // get the first unresolved ns record if it exists
@@ -65,6 +132,136 @@ impl DnsPacket {
         self.iter_ns(qname).map(|(_, host)| host).next()
     }
 
+    // add the CNAME a DNAME in the answers implies for qname, unless the
+    // server already sent it (RFC 6672 3.1). names that would grow past the
+    // 255 byte limit are left alone
+    pub fn synthesize_dname(&mut self, qname: &str) {
+        let qname = qname.to_ascii_lowercase();
+        let has_cname = self
+            .answers
+            .iter()
+            .any(|record| matches!(record, DnsRecord::CNAME { domain, .. } if *domain == qname));
+        if has_cname {
+            return;
+        }
+
+        let cname = self.answers.iter().find_map(|record| match record {
+            DnsRecord::DNAME {
+                domain,
+                target,
+                class,
+                ttl,
+            } => {
+                // only names strictly below the owner are redirected
+                let prefix = qname.strip_suffix(domain.as_str())?.strip_suffix('.')?;
+                let cname = format!("{}.{}", prefix, target);
+                (cname.len() <= 253).then(|| DnsRecord::CNAME {
+                    domain: qname.clone(),
+                    cname,
+                    class: *class,
+                    ttl: *ttl,
+                })
+            }
+            _ => None,
+        });
+
+        if let Some(cname) = cname {
+            self.add_answer(cname);
+        }
+    }
+
+    // give every record of an rrset the lowest ttl among them. they must
+    // share one (RFC 2181 5.2) but upstreams don't always agree, and
+    // clients would drop parts of the set at different times. signatures
+    // count as a set per type they cover. returns the owner, type and new
+    // ttl of each rrset that was changed
+    pub fn harmonize_ttls(&mut self) -> Vec<(String, QueryType, u32)> {
+        // OPT keeps flags where the ttl would be
+        let rrset = |record: &DnsRecord| {
+            let covered = match record {
+                DnsRecord::RRSIG { type_covered, .. } => Some(*type_covered),
+                _ => None,
+            };
+            let key = (
+                record.domain().to_ascii_lowercase(),
+                record.qtype(),
+                record.class(),
+                covered,
+            );
+            (key.1 != QueryType::OPT).then_some(key)
+        };
+
+        // each rrset with its lowest ttl, and whether any record differs
+        let mut rrsets = Vec::new();
+        let records = self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals);
+        for record in records {
+            let Some(key) = rrset(record) else { continue };
+            match rrsets.iter_mut().find(|(other, _, _)| *other == key) {
+                Some((_, ttl, mixed)) => {
+                    *mixed |= *ttl != record.ttl();
+                    *ttl = record.ttl().min(*ttl);
+                }
+                None => rrsets.push((key, record.ttl(), false)),
+            }
+        }
+        rrsets.retain(|(_, _, mixed)| *mixed);
+
+        let records = self
+            .answers
+            .iter_mut()
+            .chain(&mut self.authorities)
+            .chain(&mut self.additionals);
+        for record in records {
+            let Some(key) = rrset(record) else { continue };
+            if let Some((_, ttl, _)) = rrsets.iter().find(|(other, _, _)| *other == key) {
+                record.set_ttl(*ttl);
+            }
+        }
+
+        rrsets
+            .into_iter()
+            .map(|((domain, qtype, _, _), ttl, _)| (domain, qtype, ttl))
+            .collect()
+    }
+
+    // classify a response to the first question it carries
+    pub fn classify(&self) -> ResponseKind {
+        match self.header.rcode {
+            ResultCode::NOERROR => {}
+            ResultCode::NXDOMAIN => return ResponseKind::NxDomain,
+            _ => return ResponseKind::Error,
+        }
+
+        if !self.answers.is_empty() {
+            return ResponseKind::Answer;
+        }
+
+        // an empty authoritative answer, or a SOA in the authority section,
+        // means the name exists but has no records of the type
+        let has_soa = self
+            .authorities
+            .iter()
+            .any(|record| matches!(record, DnsRecord::SOA { .. }));
+        if self.header.aa || has_soa {
+            return ResponseKind::NoData;
+        }
+
+        let qname = match self.questions.first() {
+            Some(question) => question.qname.as_str(),
+            None => "",
+        };
+
+        if self.iter_ns(qname).next().is_some() {
+            return ResponseKind::Referral;
+        }
+
+        ResponseKind::Lame
+    }
+
     pub fn read(buf: &mut PacketBuffer) -> Result<Self, &'static str> {
         let header = DnsHeader::read(buf)?;
 
@@ -106,20 +303,24 @@ impl DnsPacket {
 
         self.header.write(buf)?;
 
+        // names repeat a lot across records, compressing them keeps
+        // responses within the 512 bytes we have
+        let mut names = NameWriter::new();
+
         for q in &self.questions {
-            q.write(buf)?;
+            q.write(buf, &mut names)?;
         }
 
         for a in &self.answers {
-            a.write(buf)?;
+            a.write(buf, &mut names)?;
         }
 
         for a in &self.authorities {
-            a.write(buf)?;
+            a.write(buf, &mut names)?;
         }
 
         for a in &self.additionals {
-            a.write(buf)?;
+            a.write(buf, &mut names)?;
         }
 
         Ok(())
@@ -144,4 +345,127 @@ impl DnsPacket {
         self.additionals.push(additional);
         self.header.arcount += 1;
     }
+
+    // advertise the largest udp response we can take (RFC 6891 6.1.2). the
+    // OPT record has the root as owner and carries the size as its class
+    pub fn add_edns(&mut self, payload: u16) {
+        self.add_additional(DnsRecord::UNKOWN {
+            domain: String::new(),
+            qtype: QueryType::OPT.into(),
+            data: Vec::new(),
+            class: DnsClass::from(payload),
+            ttl: 0,
+        });
+    }
+
+    // the udp payload size the sender advertised, None without EDNS
+    pub fn edns_payload(&self) -> Option<u16> {
+        self.additionals.iter().find_map(|record| match record {
+            DnsRecord::UNKOWN { qtype, class, .. } if QueryType::from(*qtype) == QueryType::OPT => {
+                Some((*class).into())
+            }
+            _ => None,
+        })
+    }
+
+    // add an option to the OPT record added by add_edns, does nothing
+    // without one
+    pub fn add_edns_option(&mut self, code: u16, value: &[u8]) {
+        for record in &mut self.additionals {
+            if let DnsRecord::UNKOWN { qtype, data, .. } = record {
+                if QueryType::from(*qtype) == QueryType::OPT {
+                    data.extend_from_slice(&code.to_be_bytes());
+                    data.extend_from_slice(&(value.len() as u16).to_be_bytes());
+                    data.extend_from_slice(value);
+                    return;
+                }
+            }
+        }
+    }
+
+    // the options in the OPT record as code and value pairs, empty without
+    // EDNS. an option running past the end of the record ends the list
+    pub fn edns_options(&self) -> Vec<(u16, Vec<u8>)> {
+        let mut rest = match self.additionals.iter().find_map(|record| match record {
+            DnsRecord::UNKOWN { qtype, data, .. } if QueryType::from(*qtype) == QueryType::OPT => {
+                Some(data.as_slice())
+            }
+            _ => None,
+        }) {
+            Some(data) => data,
+            None => return Vec::new(),
+        };
+
+        let mut options = Vec::new();
+        while let [c1, c2, l1, l2, tail @ ..] = rest {
+            let code = u16::from_be_bytes([*c1, *c2]);
+            let len = u16::from_be_bytes([*l1, *l2]) as usize;
+            let Some((value, tail)) = tail.split_at_checked(len) else {
+                break;
+            };
+            options.push((code, value.to_vec()));
+            rest = tail;
+        }
+
+        options
+    }
+
+    // the value of the first option with code, if the OPT record has one
+    pub fn edns_option(&self, code: u16) -> Option<Vec<u8>> {
+        self.edns_options()
+            .into_iter()
+            .find_map(|(option, value)| (option == code).then_some(value))
+    }
+
+    // pad the message with the padding option to a multiple of block bytes,
+    // or as close to it as a message can get. the option goes in the OPT
+    // record, which is added if there isn't one
+    pub fn pad(&mut self, block: usize) -> Result<(), &'static str> {
+        if self.edns_payload().is_none() {
+            self.add_edns(512);
+        }
+
+        // the length with an empty padding option, which takes 4 bytes
+        let mut buffer = PacketBuffer::with_len(PacketBuffer::MAX_LEN);
+        self.write(&mut buffer)?;
+        let len = buffer.pos() + 4;
+
+        let padded = len.div_ceil(block) * block;
+        let padding = padded.min(PacketBuffer::MAX_LEN).saturating_sub(len);
+        self.add_edns_option(OPTION_PADDING, &vec![0; padding]);
+        Ok(())
+    }
+
+    // how long the server will keep the connection a response arrived on
+    // open while idle, if it said. sent in units of 100 milliseconds
+    pub fn tcp_keepalive(&self) -> Option<Duration> {
+        match self.edns_option(OPTION_TCP_KEEPALIVE)?.as_slice() {
+            [hi, lo] => Some(Duration::from_millis(
+                u16::from_be_bytes([*hi, *lo]) as u64 * 100,
+            )),
+            _ => None,
+        }
+    }
+
+    // add the known addresses of MX/NS targets to the additionals, as real
+    // resolvers do, so clients don't need a second query to connect
+    pub fn add_target_glue<'a, I: IntoIterator<Item = &'a DnsRecord>>(&mut self, known: I) {
+        let targets: Vec<String> = self
+            .answers
+            .iter()
+            .filter_map(|answer| answer.target().map(String::from))
+            .collect();
+
+        for record in known {
+            let wanted = targets.iter().any(|target| record.is_address_of(target));
+            let present = self
+                .additionals
+                .iter()
+                .any(|additional| additional.same_data(record));
+
+            if wanted && !present {
+                self.add_additional(record.clone());
+            }
+        }
+    }
 }
@@ -0,0 +1,330 @@
+use std::fmt::{self, Write as _};
+
+use super::{
+    question::QueryType,
+    record::{DnsRecord, SvcParam},
+};
+
+// records are shown the way zone files and dig write them (RFC 1035 5.1),
+// "<owner> <ttl> <class> <type> <rdata>" with fully qualified names
+impl fmt::Display for DnsRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t",
+            fqdn(self.domain()),
+            self.ttl(),
+            self.class(),
+            self.qtype()
+        )?;
+
+        match self {
+            DnsRecord::UNKOWN { data, .. } => write!(f, "\\# {} {}", data.len(), hex(data)),
+            DnsRecord::A { addr, .. } => write!(f, "{}", addr),
+            DnsRecord::NS { ns: host, .. }
+            | DnsRecord::CNAME { cname: host, .. }
+            | DnsRecord::PTR { host, .. }
+            | DnsRecord::DNAME { target: host, .. } => f.write_str(&fqdn(host)),
+            DnsRecord::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ..
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                fqdn(mname),
+                fqdn(rname),
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum
+            ),
+            DnsRecord::HINFO { cpu, os, .. } => {
+                write!(f, "{} {}", quoted(cpu.as_bytes()), quoted(os.as_bytes()))
+            }
+            DnsRecord::MX {
+                preference,
+                exchange,
+                ..
+            } => write!(f, "{} {}", preference, fqdn(exchange)),
+            DnsRecord::TXT { data, .. } => {
+                let strings: Vec<String> = data.iter().map(|s| quoted(s)).collect();
+                f.write_str(&strings.join(" "))
+            }
+            DnsRecord::AAAA { addr, .. } => write!(f, "{}", addr),
+            DnsRecord::SRV {
+                priority,
+                weight,
+                port,
+                target,
+                ..
+            } => write!(f, "{} {} {} {}", priority, weight, port, fqdn(target)),
+            DnsRecord::DS {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+                ..
+            } => write!(
+                f,
+                "{} {} {} {}",
+                key_tag,
+                algorithm,
+                digest_type,
+                hex(digest)
+            ),
+            DnsRecord::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer,
+                signature,
+                ..
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {} {} {}",
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                timestamp(*expiration),
+                timestamp(*inception),
+                key_tag,
+                fqdn(signer),
+                base64(signature)
+            ),
+            DnsRecord::NSEC {
+                next_domain, types, ..
+            } => write!(f, "{}{}", fqdn(next_domain), type_list(types)),
+            DnsRecord::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                key,
+                ..
+            } => write!(f, "{} {} {} {}", flags, protocol, algorithm, base64(key)),
+            DnsRecord::NSEC3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed,
+                types,
+                ..
+            } => write!(
+                f,
+                "{} {} {} {} {}{}",
+                hash_algorithm,
+                flags,
+                iterations,
+                salt_text(salt),
+                base32hex(next_hashed),
+                type_list(types)
+            ),
+            DnsRecord::NSEC3PARAM {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                ..
+            } => write!(
+                f,
+                "{} {} {} {}",
+                hash_algorithm,
+                flags,
+                iterations,
+                salt_text(salt)
+            ),
+            DnsRecord::TLSA {
+                usage,
+                selector,
+                matching_type,
+                data,
+                ..
+            } => write!(f, "{} {} {} {}", usage, selector, matching_type, hex(data)),
+            DnsRecord::SVCB {
+                priority,
+                target,
+                params,
+                ..
+            }
+            | DnsRecord::HTTPS {
+                priority,
+                target,
+                params,
+                ..
+            } => {
+                write!(f, "{} {}", priority, fqdn(target))?;
+                for param in params {
+                    write!(f, " {}", param)?;
+                }
+                Ok(())
+            }
+            DnsRecord::CAA {
+                flags, tag, value, ..
+            } => write!(f, "{} {} {}", flags, tag, quoted(value)),
+        }
+    }
+}
+
+// key=value, as in RFC 9460 2.1
+impl fmt::Display for SvcParam {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list = |items: Vec<String>| items.join(",");
+        match self {
+            SvcParam::Mandatory(keys) => write!(
+                f,
+                "mandatory={}",
+                list(keys.iter().map(|key| key_name(*key)).collect())
+            ),
+            SvcParam::Alpn(ids) => write!(f, "alpn={}", quoted(list(ids.clone()).as_bytes())),
+            SvcParam::NoDefaultAlpn => f.write_str("no-default-alpn"),
+            SvcParam::Port(port) => write!(f, "port={}", port),
+            SvcParam::Ipv4Hint(addrs) => write!(
+                f,
+                "ipv4hint={}",
+                list(addrs.iter().map(|addr| addr.to_string()).collect())
+            ),
+            SvcParam::Ech(config) => write!(f, "ech={}", base64(config)),
+            SvcParam::Ipv6Hint(addrs) => write!(
+                f,
+                "ipv6hint={}",
+                list(addrs.iter().map(|addr| addr.to_string()).collect())
+            ),
+            SvcParam::Unknown(key, value) => {
+                write!(f, "{}={}", key_name(*key), quoted(value))
+            }
+        }
+    }
+}
+
+fn key_name(key: u16) -> String {
+    match key {
+        0 => "mandatory".to_string(),
+        1 => "alpn".to_string(),
+        2 => "no-default-alpn".to_string(),
+        3 => "port".to_string(),
+        4 => "ipv4hint".to_string(),
+        5 => "ech".to_string(),
+        6 => "ipv6hint".to_string(),
+        key => format!("key{}", key),
+    }
+}
+
+// names are kept without the trailing dot, the root as an empty string
+fn fqdn(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
+
+// a character string in quotes, with quotes, backslashes and anything
+// unprintable escaped
+fn quoted(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(byte as char);
+            }
+            0x20..=0x7E => out.push(byte as char),
+            _ => {
+                let _ = write!(out, "\\{:03}", byte);
+            }
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn type_list(types: &[QueryType]) -> String {
+    types.iter().map(|qtype| format!(" {}", qtype)).collect()
+}
+
+fn salt_text(salt: &[u8]) -> String {
+    match salt {
+        [] => "-".to_string(),
+        salt => hex(salt),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.len();
+        let bits = chunk
+            .iter()
+            .chain([0; 3].iter())
+            .take(3)
+            .fold(0u32, |bits, &byte| bits << 8 | byte as u32);
+        for i in 0..4 {
+            if i <= n {
+                out.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// the hex alphabet variant NSEC3 hashes are written in (RFC 4648 7),
+// without padding
+fn base32hex(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHIJKLMNOPQRSTUV";
+    let mut out = String::new();
+    let (mut bits, mut len) = (0u32, 0);
+    for &byte in bytes {
+        bits = bits << 8 | byte as u32;
+        len += 8;
+        while len >= 5 {
+            len -= 5;
+            out.push(ALPHABET[(bits >> len & 0x1F) as usize] as char);
+        }
+    }
+    if len > 0 {
+        out.push(ALPHABET[(bits << (5 - len) & 0x1F) as usize] as char);
+    }
+    out
+}
+
+// seconds since the epoch as YYYYMMDDHHmmSS in UTC (RFC 4034 3.2), the
+// civil date from days algorithm from Howard Hinnant's date library
+fn timestamp(secs: u32) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
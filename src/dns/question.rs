@@ -1,37 +1,257 @@
-use crate::buffer::PacketBuffer;
+use std::{fmt, str::FromStr};
+
+use crate::buffer::{NameWriter, PacketBuffer};
 
 #[derive(PartialEq, Eq, Debug, Clone, Hash, Copy)]
 pub enum QueryType {
     UNKOWN(u16),
-    A,     // 1
-    NS,    // 2
-    CNAME, // 5
-    MX,    // 15
-    AAAA,  // 28
+    A,          // 1
+    NS,         // 2
+    CNAME,      // 5
+    SOA,        // 6
+    PTR,        // 12
+    HINFO,      // 13
+    MX,         // 15
+    TXT,        // 16
+    AAAA,       // 28
+    SRV,        // 33
+    DNAME,      // 39
+    OPT,        // 41
+    DS,         // 43
+    RRSIG,      // 46
+    NSEC,       // 47
+    DNSKEY,     // 48
+    NSEC3,      // 50
+    NSEC3PARAM, // 51
+    TLSA,       // 52
+    SVCB,       // 64
+    HTTPS,      // 65
+    IXFR,       // 251
+    AXFR,       // 252
+    ANY,        // 255
+    CAA,        // 257
 }
 
-impl QueryType {
+impl From<u16> for QueryType {
     #[inline]
-    pub fn from_u16(val: u16) -> QueryType {
+    fn from(val: u16) -> Self {
         match val {
             1 => QueryType::A,
             2 => QueryType::NS,
             5 => QueryType::CNAME,
+            6 => QueryType::SOA,
+            12 => QueryType::PTR,
+            13 => QueryType::HINFO,
             15 => QueryType::MX,
+            16 => QueryType::TXT,
             28 => QueryType::AAAA,
+            33 => QueryType::SRV,
+            39 => QueryType::DNAME,
+            41 => QueryType::OPT,
+            43 => QueryType::DS,
+            46 => QueryType::RRSIG,
+            47 => QueryType::NSEC,
+            48 => QueryType::DNSKEY,
+            50 => QueryType::NSEC3,
+            51 => QueryType::NSEC3PARAM,
+            52 => QueryType::TLSA,
+            64 => QueryType::SVCB,
+            65 => QueryType::HTTPS,
+            251 => QueryType::IXFR,
+            252 => QueryType::AXFR,
+            255 => QueryType::ANY,
+            257 => QueryType::CAA,
             _ => QueryType::UNKOWN(val),
         }
     }
+}
 
+impl From<QueryType> for u16 {
     #[inline]
-    pub fn to_u16(&self) -> u16 {
-        match self {
+    fn from(qtype: QueryType) -> Self {
+        match qtype {
             QueryType::A => 1,
             QueryType::NS => 2,
             QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::PTR => 12,
+            QueryType::HINFO => 13,
             QueryType::MX => 15,
+            QueryType::TXT => 16,
             QueryType::AAAA => 28,
-            QueryType::UNKOWN(val) => *val,
+            QueryType::SRV => 33,
+            QueryType::DNAME => 39,
+            QueryType::OPT => 41,
+            QueryType::DS => 43,
+            QueryType::RRSIG => 46,
+            QueryType::NSEC => 47,
+            QueryType::DNSKEY => 48,
+            QueryType::NSEC3 => 50,
+            QueryType::NSEC3PARAM => 51,
+            QueryType::TLSA => 52,
+            QueryType::SVCB => 64,
+            QueryType::HTTPS => 65,
+            QueryType::IXFR => 251,
+            QueryType::AXFR => 252,
+            QueryType::ANY => 255,
+            QueryType::CAA => 257,
+            QueryType::UNKOWN(val) => val,
+        }
+    }
+}
+
+impl QueryType {
+    // mnemonic used in zone files and logs, None for unknown types
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            QueryType::A => Some("A"),
+            QueryType::NS => Some("NS"),
+            QueryType::CNAME => Some("CNAME"),
+            QueryType::SOA => Some("SOA"),
+            QueryType::PTR => Some("PTR"),
+            QueryType::HINFO => Some("HINFO"),
+            QueryType::MX => Some("MX"),
+            QueryType::TXT => Some("TXT"),
+            QueryType::AAAA => Some("AAAA"),
+            QueryType::SRV => Some("SRV"),
+            QueryType::DNAME => Some("DNAME"),
+            QueryType::OPT => Some("OPT"),
+            QueryType::DS => Some("DS"),
+            QueryType::RRSIG => Some("RRSIG"),
+            QueryType::NSEC => Some("NSEC"),
+            QueryType::DNSKEY => Some("DNSKEY"),
+            QueryType::NSEC3 => Some("NSEC3"),
+            QueryType::NSEC3PARAM => Some("NSEC3PARAM"),
+            QueryType::TLSA => Some("TLSA"),
+            QueryType::SVCB => Some("SVCB"),
+            QueryType::HTTPS => Some("HTTPS"),
+            QueryType::IXFR => Some("IXFR"),
+            QueryType::AXFR => Some("AXFR"),
+            QueryType::ANY => Some("ANY"),
+            QueryType::CAA => Some("CAA"),
+            QueryType::UNKOWN(_) => None,
+        }
+    }
+}
+
+// unknown types are written as TYPE<n>, per RFC 3597
+impl fmt::Display for QueryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "TYPE{}", u16::from(*self)),
+        }
+    }
+}
+
+// accepts mnemonics in any case, and TYPE<n> for any type
+impl FromStr for QueryType {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let upper = s.to_ascii_uppercase();
+        match upper.as_str() {
+            "A" => Ok(QueryType::A),
+            "NS" => Ok(QueryType::NS),
+            "CNAME" => Ok(QueryType::CNAME),
+            "SOA" => Ok(QueryType::SOA),
+            "PTR" => Ok(QueryType::PTR),
+            "HINFO" => Ok(QueryType::HINFO),
+            "MX" => Ok(QueryType::MX),
+            "TXT" => Ok(QueryType::TXT),
+            "AAAA" => Ok(QueryType::AAAA),
+            "SRV" => Ok(QueryType::SRV),
+            "DNAME" => Ok(QueryType::DNAME),
+            "OPT" => Ok(QueryType::OPT),
+            "DS" => Ok(QueryType::DS),
+            "RRSIG" => Ok(QueryType::RRSIG),
+            "NSEC" => Ok(QueryType::NSEC),
+            "DNSKEY" => Ok(QueryType::DNSKEY),
+            "NSEC3" => Ok(QueryType::NSEC3),
+            "NSEC3PARAM" => Ok(QueryType::NSEC3PARAM),
+            "TLSA" => Ok(QueryType::TLSA),
+            "SVCB" => Ok(QueryType::SVCB),
+            "HTTPS" => Ok(QueryType::HTTPS),
+            "IXFR" => Ok(QueryType::IXFR),
+            "AXFR" => Ok(QueryType::AXFR),
+            "ANY" => Ok(QueryType::ANY),
+            "CAA" => Ok(QueryType::CAA),
+            _ => match upper.strip_prefix("TYPE").map(str::parse::<u16>) {
+                Some(Ok(val)) => Ok(QueryType::from(val)),
+                _ => Err("unknown query type"),
+            },
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Hash, Copy)]
+pub enum DnsClass {
+    UNKOWN(u16),
+    IN,   // 1, the internet
+    CH,   // 3, chaos, used for server info like version.bind
+    HS,   // 4, hesiod
+    NONE, // 254, only in dynamic updates
+    ANY,  // 255, only in questions
+}
+
+impl From<u16> for DnsClass {
+    #[inline]
+    fn from(val: u16) -> Self {
+        match val {
+            1 => DnsClass::IN,
+            3 => DnsClass::CH,
+            4 => DnsClass::HS,
+            254 => DnsClass::NONE,
+            255 => DnsClass::ANY,
+            _ => DnsClass::UNKOWN(val),
+        }
+    }
+}
+
+impl From<DnsClass> for u16 {
+    #[inline]
+    fn from(class: DnsClass) -> Self {
+        match class {
+            DnsClass::IN => 1,
+            DnsClass::CH => 3,
+            DnsClass::HS => 4,
+            DnsClass::NONE => 254,
+            DnsClass::ANY => 255,
+            DnsClass::UNKOWN(val) => val,
+        }
+    }
+}
+
+// unknown classes are written as CLASS<n>, per RFC 3597
+impl fmt::Display for DnsClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DnsClass::IN => f.write_str("IN"),
+            DnsClass::CH => f.write_str("CH"),
+            DnsClass::HS => f.write_str("HS"),
+            DnsClass::NONE => f.write_str("NONE"),
+            DnsClass::ANY => f.write_str("ANY"),
+            DnsClass::UNKOWN(val) => write!(f, "CLASS{}", val),
+        }
+    }
+}
+
+// accepts mnemonics in any case, and CLASS<n> for any class
+impl FromStr for DnsClass {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let upper = s.to_ascii_uppercase();
+        match upper.as_str() {
+            "IN" => Ok(DnsClass::IN),
+            "CH" => Ok(DnsClass::CH),
+            "HS" => Ok(DnsClass::HS),
+            "NONE" => Ok(DnsClass::NONE),
+            "ANY" => Ok(DnsClass::ANY),
+            _ => match upper.strip_prefix("CLASS").map(str::parse::<u16>) {
+                Some(Ok(val)) => Ok(DnsClass::from(val)),
+                _ => Err("unknown class"),
+            },
         }
     }
 }
@@ -40,28 +260,41 @@ impl QueryType {
 pub struct DnsQuestion {
     pub qname: String,
     pub qtype: QueryType,
-    // pub qclass: u16, always 1
+    pub qclass: DnsClass,
 }
 
 impl DnsQuestion {
+    // question in the internet class, which is all recursion deals with
     pub fn new(qname: String, qtype: QueryType) -> Self {
-        DnsQuestion { qname, qtype }
+        DnsQuestion {
+            qname,
+            qtype,
+            qclass: DnsClass::IN,
+        }
     }
 
     pub fn read(buf: &mut PacketBuffer) -> Result<Self, &'static str> {
         let mut qname = String::with_capacity(256);
         buf.read_qname(&mut qname)?;
 
-        let qtype = QueryType::from_u16(buf.read_u16()?);
-        buf.step(2); // qclass (always 1)
+        let qtype = QueryType::from(buf.read_u16()?);
+        let qclass = DnsClass::from(buf.read_u16()?);
 
-        Ok(DnsQuestion { qname, qtype })
+        Ok(DnsQuestion {
+            qname,
+            qtype,
+            qclass,
+        })
     }
 
-    pub fn write(&self, buf: &mut PacketBuffer) -> Result<(), &'static str> {
-        buf.write_qname(&self.qname)?;
-        buf.write_u16(self.qtype.to_u16())?;
-        buf.write_u16(1)?; // qclass (always 1)
+    pub fn write(
+        &self,
+        buf: &mut PacketBuffer,
+        names: &mut NameWriter,
+    ) -> Result<(), &'static str> {
+        names.write_name(buf, &self.qname, true)?;
+        buf.write_u16(self.qtype.into())?;
+        buf.write_u16(self.qclass.into())?;
         Ok(())
     }
 }
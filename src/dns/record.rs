@@ -1,75 +1,562 @@
 use std::net::{Ipv4Addr, Ipv6Addr};
 
-use crate::buffer::PacketBuffer;
+use crate::buffer::{NameWriter, PacketBuffer};
 
-use super::question::QueryType;
+use super::question::{DnsClass, QueryType};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DnsRecord {
     UNKOWN {
         domain: String,
         qtype: u16,
-        data_len: u16,
+        data: Vec<u8>, // raw rdata, passed on as is (RFC 3597)
+        class: DnsClass,
         ttl: u32,
     }, // 0
     A {
         domain: String,
         addr: Ipv4Addr,
+        class: DnsClass,
         ttl: u32,
     }, // 1
     NS {
         domain: String,
         ns: String,
+        class: DnsClass,
         ttl: u32,
     }, // 2
     CNAME {
         domain: String,
         cname: String,
+        class: DnsClass,
         ttl: u32,
     }, // 5
+    SOA {
+        domain: String,
+        mname: String, // primary nameserver of the zone
+        rname: String, // mailbox of the person responsible, with the @ as a dot
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32, // ttl for negative answers from the zone
+        class: DnsClass,
+        ttl: u32,
+    }, // 6
+    PTR {
+        domain: String,
+        host: String,
+        class: DnsClass,
+        ttl: u32,
+    }, // 12
+    HINFO {
+        domain: String,
+        cpu: String,
+        os: String,
+        class: DnsClass,
+        ttl: u32,
+    }, // 13
     MX {
         domain: String,
         preference: u16,
         exchange: String,
+        class: DnsClass,
         ttl: u32,
     }, // 15
+    TXT {
+        domain: String,
+        data: Vec<Vec<u8>>, // one or more character strings, any bytes
+        class: DnsClass,
+        ttl: u32,
+    }, // 16
     AAAA {
         domain: String,
         addr: Ipv6Addr,
+        class: DnsClass,
         ttl: u32,
     }, // 28
+    SRV {
+        domain: String,
+        priority: u16, // lower is tried first
+        weight: u16,   // share of the load within a priority
+        port: u16,
+        target: String,
+        class: DnsClass,
+        ttl: u32,
+    }, // 33
+    DNAME {
+        domain: String,
+        target: String, // replaces domain in every name below it
+        class: DnsClass,
+        ttl: u32,
+    }, // 39
+    DS {
+        domain: String,
+        key_tag: u16,    // tag of the child zone's DNSKEY this vouches for
+        algorithm: u8,   // algorithm of that DNSKEY
+        digest_type: u8, // 1 for SHA-1, 2 for SHA-256, 4 for SHA-384
+        digest: Vec<u8>, // digest of the owner name and DNSKEY rdata
+        class: DnsClass,
+        ttl: u32,
+    }, // 43
+    RRSIG {
+        domain: String,
+        type_covered: QueryType, // type of the rrset signed
+        algorithm: u8,
+        labels: u8,        // labels in the owner name, less for wildcard expansions
+        original_ttl: u32, // ttl of the rrset as signed
+        expiration: u32,   // end of the validity window, seconds since the epoch
+        inception: u32,    // start of the validity window
+        key_tag: u16,      // tag of the DNSKEY that made the signature
+        signer: String,    // zone that signed the rrset
+        signature: Vec<u8>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 46
+    NSEC {
+        domain: String,
+        next_domain: String,   // next name in the zone, in canonical order
+        types: Vec<QueryType>, // types that exist at the owner name
+        class: DnsClass,
+        ttl: u32,
+    }, // 47
+    DNSKEY {
+        domain: String,
+        flags: u16,    // 256 for a zone key, 257 with the secure entry point bit
+        protocol: u8,  // always 3
+        algorithm: u8, // e.g. 8 for RSA/SHA-256, 13 for ECDSA P-256/SHA-256
+        key: Vec<u8>,  // public key, shown base64 encoded in zone files
+        class: DnsClass,
+        ttl: u32,
+    }, // 48
+    NSEC3 {
+        domain: String,
+        hash_algorithm: u8, // 1 for SHA-1, the only one defined
+        flags: u8,          // 1 marks opt-out, unsigned delegations may be skipped
+        iterations: u16,    // extra rounds of hashing
+        salt: Vec<u8>,
+        next_hashed: Vec<u8>, // hash of the next name in the zone, in hash order
+        types: Vec<QueryType>, // types that exist at the original owner name
+        class: DnsClass,
+        ttl: u32,
+    }, // 50
+    NSEC3PARAM {
+        domain: String,
+        hash_algorithm: u8,
+        flags: u8, // always 0 in the apex record
+        iterations: u16,
+        salt: Vec<u8>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 51
+    TLSA {
+        domain: String,
+        usage: u8,         // which certificate in the chain is matched, 0 to 3
+        selector: u8,      // 0 matches the whole certificate, 1 its public key
+        matching_type: u8, // 0 for the data itself, 1 for SHA-256, 2 for SHA-512
+        data: Vec<u8>,     // certificate association data
+        class: DnsClass,
+        ttl: u32,
+    }, // 52
+    SVCB {
+        domain: String,
+        priority: u16,  // 0 for alias form, lower is preferred otherwise
+        target: String, // empty for the owner name itself
+        params: Vec<SvcParam>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 64
+    HTTPS {
+        domain: String,
+        priority: u16,
+        target: String,
+        params: Vec<SvcParam>,
+        class: DnsClass,
+        ttl: u32,
+    }, // 65
+    CAA {
+        domain: String,
+        flags: u8,      // 128 marks the property critical
+        tag: String,    // issue, issuewild, iodef, ...
+        value: Vec<u8>, // e.g. the domain of the CA allowed to issue, any bytes
+        class: DnsClass,
+        ttl: u32,
+    }, // 257
+}
+
+// a service parameter of a SVCB or HTTPS record (RFC 9460 7)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SvcParam {
+    Mandatory(Vec<u16>),     // 0 - keys a client must understand to use the record
+    Alpn(Vec<String>),       // 1 - protocols offered, e.g. h2 and h3
+    NoDefaultAlpn,           // 2 - the scheme's default protocol is not offered
+    Port(u16),               // 3
+    Ipv4Hint(Vec<Ipv4Addr>), // 4
+    Ech(Vec<u8>),            // 5 - encrypted client hello config
+    Ipv6Hint(Vec<Ipv6Addr>), // 6
+    Unknown(u16, Vec<u8>),   // any other key, with its raw value
+}
+
+impl SvcParam {
+    pub fn key(&self) -> u16 {
+        match self {
+            SvcParam::Mandatory(_) => 0,
+            SvcParam::Alpn(_) => 1,
+            SvcParam::NoDefaultAlpn => 2,
+            SvcParam::Port(_) => 3,
+            SvcParam::Ipv4Hint(_) => 4,
+            SvcParam::Ech(_) => 5,
+            SvcParam::Ipv6Hint(_) => 6,
+            SvcParam::Unknown(key, _) => *key,
+        }
+    }
+
+    pub fn decode(key: u16, value: &[u8]) -> Result<Self, &'static str> {
+        match key {
+            0 if value.len().is_multiple_of(2) => Ok(SvcParam::Mandatory(
+                value
+                    .chunks(2)
+                    .map(|key| u16::from_be_bytes([key[0], key[1]]))
+                    .collect(),
+            )),
+            1 => {
+                // protocol ids, each prefixed with its length
+                let mut alpn = Vec::new();
+                let mut rest = value;
+                while let Some((&len, tail)) = rest.split_first() {
+                    let (id, tail) = tail
+                        .split_at_checked(len as usize)
+                        .ok_or("alpn id longer than its param")?;
+                    alpn.push(String::from_utf8_lossy(id).into_owned());
+                    rest = tail;
+                }
+                Ok(SvcParam::Alpn(alpn))
+            }
+            2 if value.is_empty() => Ok(SvcParam::NoDefaultAlpn),
+            3 if value.len() == 2 => Ok(SvcParam::Port(u16::from_be_bytes([value[0], value[1]]))),
+            4 if value.len().is_multiple_of(4) => Ok(SvcParam::Ipv4Hint(
+                value
+                    .chunks(4)
+                    .map(|addr| Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]))
+                    .collect(),
+            )),
+            5 => Ok(SvcParam::Ech(value.to_vec())),
+            6 if value.len().is_multiple_of(16) => Ok(SvcParam::Ipv6Hint(
+                value
+                    .chunks(16)
+                    .map(|addr| Ipv6Addr::from(<[u8; 16]>::try_from(addr).unwrap()))
+                    .collect(),
+            )),
+            0..=6 => Err("malformed service param"),
+            key => Ok(SvcParam::Unknown(key, value.to_vec())),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            SvcParam::Mandatory(keys) => keys.iter().flat_map(|key| key.to_be_bytes()).collect(),
+            SvcParam::Alpn(alpn) => alpn
+                .iter()
+                .flat_map(|id| std::iter::once(id.len() as u8).chain(id.bytes()))
+                .collect(),
+            SvcParam::NoDefaultAlpn => Vec::new(),
+            SvcParam::Port(port) => port.to_be_bytes().to_vec(),
+            SvcParam::Ipv4Hint(addrs) => addrs.iter().flat_map(|addr| addr.octets()).collect(),
+            SvcParam::Ech(config) => config.clone(),
+            SvcParam::Ipv6Hint(addrs) => addrs.iter().flat_map(|addr| addr.octets()).collect(),
+            SvcParam::Unknown(_, value) => value.clone(),
+        }
+    }
 }
 
 impl DnsRecord {
+    // the name the record is owned by
+    pub fn domain(&self) -> &str {
+        match self {
+            DnsRecord::UNKOWN { domain, .. }
+            | DnsRecord::A { domain, .. }
+            | DnsRecord::NS { domain, .. }
+            | DnsRecord::CNAME { domain, .. }
+            | DnsRecord::SOA { domain, .. }
+            | DnsRecord::PTR { domain, .. }
+            | DnsRecord::HINFO { domain, .. }
+            | DnsRecord::MX { domain, .. }
+            | DnsRecord::TXT { domain, .. }
+            | DnsRecord::AAAA { domain, .. }
+            | DnsRecord::SRV { domain, .. }
+            | DnsRecord::DNAME { domain, .. }
+            | DnsRecord::DS { domain, .. }
+            | DnsRecord::RRSIG { domain, .. }
+            | DnsRecord::NSEC { domain, .. }
+            | DnsRecord::DNSKEY { domain, .. }
+            | DnsRecord::NSEC3 { domain, .. }
+            | DnsRecord::NSEC3PARAM { domain, .. }
+            | DnsRecord::TLSA { domain, .. }
+            | DnsRecord::SVCB { domain, .. }
+            | DnsRecord::HTTPS { domain, .. }
+            | DnsRecord::CAA { domain, .. } => domain,
+        }
+    }
+
+    // the type of the record, as a question for it would ask
+    pub fn qtype(&self) -> QueryType {
+        match self {
+            DnsRecord::UNKOWN { qtype, .. } => QueryType::from(*qtype),
+            DnsRecord::A { .. } => QueryType::A,
+            DnsRecord::NS { .. } => QueryType::NS,
+            DnsRecord::CNAME { .. } => QueryType::CNAME,
+            DnsRecord::SOA { .. } => QueryType::SOA,
+            DnsRecord::PTR { .. } => QueryType::PTR,
+            DnsRecord::HINFO { .. } => QueryType::HINFO,
+            DnsRecord::MX { .. } => QueryType::MX,
+            DnsRecord::TXT { .. } => QueryType::TXT,
+            DnsRecord::AAAA { .. } => QueryType::AAAA,
+            DnsRecord::SRV { .. } => QueryType::SRV,
+            DnsRecord::DNAME { .. } => QueryType::DNAME,
+            DnsRecord::DS { .. } => QueryType::DS,
+            DnsRecord::RRSIG { .. } => QueryType::RRSIG,
+            DnsRecord::NSEC { .. } => QueryType::NSEC,
+            DnsRecord::DNSKEY { .. } => QueryType::DNSKEY,
+            DnsRecord::NSEC3 { .. } => QueryType::NSEC3,
+            DnsRecord::NSEC3PARAM { .. } => QueryType::NSEC3PARAM,
+            DnsRecord::TLSA { .. } => QueryType::TLSA,
+            DnsRecord::SVCB { .. } => QueryType::SVCB,
+            DnsRecord::HTTPS { .. } => QueryType::HTTPS,
+            DnsRecord::CAA { .. } => QueryType::CAA,
+        }
+    }
+
+    pub fn ttl(&self) -> u32 {
+        match self {
+            DnsRecord::UNKOWN { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::HINFO { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::DNAME { ttl, .. }
+            | DnsRecord::DS { ttl, .. }
+            | DnsRecord::RRSIG { ttl, .. }
+            | DnsRecord::NSEC { ttl, .. }
+            | DnsRecord::DNSKEY { ttl, .. }
+            | DnsRecord::NSEC3 { ttl, .. }
+            | DnsRecord::NSEC3PARAM { ttl, .. }
+            | DnsRecord::TLSA { ttl, .. }
+            | DnsRecord::SVCB { ttl, .. }
+            | DnsRecord::HTTPS { ttl, .. }
+            | DnsRecord::CAA { ttl, .. } => *ttl,
+        }
+    }
+
+    pub fn set_ttl(&mut self, val: u32) {
+        match self {
+            DnsRecord::UNKOWN { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::HINFO { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::DNAME { ttl, .. }
+            | DnsRecord::DS { ttl, .. }
+            | DnsRecord::RRSIG { ttl, .. }
+            | DnsRecord::NSEC { ttl, .. }
+            | DnsRecord::DNSKEY { ttl, .. }
+            | DnsRecord::NSEC3 { ttl, .. }
+            | DnsRecord::NSEC3PARAM { ttl, .. }
+            | DnsRecord::TLSA { ttl, .. }
+            | DnsRecord::SVCB { ttl, .. }
+            | DnsRecord::HTTPS { ttl, .. }
+            | DnsRecord::CAA { ttl, .. } => *ttl = val,
+        }
+    }
+
+    pub fn class(&self) -> DnsClass {
+        match self {
+            DnsRecord::UNKOWN { class, .. }
+            | DnsRecord::A { class, .. }
+            | DnsRecord::NS { class, .. }
+            | DnsRecord::CNAME { class, .. }
+            | DnsRecord::SOA { class, .. }
+            | DnsRecord::PTR { class, .. }
+            | DnsRecord::HINFO { class, .. }
+            | DnsRecord::MX { class, .. }
+            | DnsRecord::TXT { class, .. }
+            | DnsRecord::AAAA { class, .. }
+            | DnsRecord::SRV { class, .. }
+            | DnsRecord::DNAME { class, .. }
+            | DnsRecord::DS { class, .. }
+            | DnsRecord::RRSIG { class, .. }
+            | DnsRecord::NSEC { class, .. }
+            | DnsRecord::DNSKEY { class, .. }
+            | DnsRecord::NSEC3 { class, .. }
+            | DnsRecord::NSEC3PARAM { class, .. }
+            | DnsRecord::TLSA { class, .. }
+            | DnsRecord::SVCB { class, .. }
+            | DnsRecord::HTTPS { class, .. }
+            | DnsRecord::CAA { class, .. } => *class,
+        }
+    }
+
+    // compare two records ignoring their ttl, which differs between caches
+    pub fn same_data(&self, other: &DnsRecord) -> bool {
+        let (mut a, mut b) = (self.clone(), other.clone());
+        a.set_ttl(0);
+        b.set_ttl(0);
+        a == b
+    }
+
+    // the host a record points clients at, whose addresses belong in the
+    // additional section
+    pub fn target(&self) -> Option<&str> {
+        match self {
+            DnsRecord::MX { exchange, .. } => Some(exchange),
+            DnsRecord::NS { ns, .. } => Some(ns),
+            DnsRecord::SRV { target, .. } => Some(target),
+            _ => None,
+        }
+    }
+
+    // the address records of a host
+    pub fn is_address_of(&self, host: &str) -> bool {
+        match self {
+            DnsRecord::A { domain, .. } | DnsRecord::AAAA { domain, .. } => domain == host,
+            _ => false,
+        }
+    }
+
+    // the tag DS and RRSIG records refer to a DNSKEY by (RFC 4034 appendix B),
+    // None for any other record
+    pub fn key_tag(&self) -> Option<u16> {
+        let DnsRecord::DNSKEY {
+            flags,
+            protocol,
+            algorithm,
+            key,
+            ..
+        } = self
+        else {
+            return None;
+        };
+
+        // RSA/MD5 keys use the bits just before the end of the modulus
+        if *algorithm == 1 {
+            return match key.len() {
+                len if len >= 3 => Some(u16::from_be_bytes([key[len - 3], key[len - 2]])),
+                _ => None,
+            };
+        }
+
+        // ones complement style sum of the rdata, taken as u16s
+        let rdata = flags
+            .to_be_bytes()
+            .into_iter()
+            .chain([*protocol, *algorithm])
+            .chain(key.iter().copied());
+
+        let mut sum: u32 = 0;
+        for (i, byte) in rdata.enumerate() {
+            sum += match i & 1 {
+                0 => (byte as u32) << 8,
+                _ => byte as u32,
+            };
+        }
+        sum += (sum >> 16) & 0xFFFF;
+
+        Some(sum as u16)
+    }
+
     pub fn read(buf: &mut PacketBuffer) -> Result<Self, &'static str> {
         let mut domain = String::with_capacity(256);
         buf.read_qname(&mut domain)?;
 
         let qtype = buf.read_u16()?;
-        let _qclass = buf.read_u16()?;
+        let class = DnsClass::from(buf.read_u16()?);
         let ttl = buf.read_u32()?;
         let data_len = buf.read_u16()?;
 
-        match QueryType::from_u16(qtype) {
+        match QueryType::from(qtype) {
             QueryType::A => Ok(DnsRecord::A {
                 domain,
                 addr: Ipv4Addr::from(buf.read_u32()?),
+                class,
                 ttl,
             }),
             QueryType::AAAA => Ok(DnsRecord::AAAA {
                 domain,
                 addr: Ipv6Addr::from(buf.read_slice::<16>()?),
+                class,
                 ttl,
             }),
             QueryType::NS => {
                 let mut ns = String::with_capacity(256);
                 buf.read_qname(&mut ns)?;
-                Ok(DnsRecord::NS { domain, ns, ttl })
+                Ok(DnsRecord::NS {
+                    domain,
+                    ns,
+                    class,
+                    ttl,
+                })
             }
             QueryType::CNAME => {
                 let mut cname = String::with_capacity(256);
                 buf.read_qname(&mut cname)?;
-                Ok(DnsRecord::CNAME { domain, cname, ttl })
+                Ok(DnsRecord::CNAME {
+                    domain,
+                    cname,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::PTR => {
+                let mut host = String::with_capacity(256);
+                buf.read_qname(&mut host)?;
+                Ok(DnsRecord::PTR {
+                    domain,
+                    host,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::HINFO => {
+                let cpu_len = buf.read_u8()? as usize;
+                let cpu = read_string(buf, cpu_len)?;
+                let os_len = buf.read_u8()? as usize;
+                let os = read_string(buf, os_len)?;
+                Ok(DnsRecord::HINFO {
+                    domain,
+                    cpu,
+                    os,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::SOA => {
+                let mut mname = String::with_capacity(256);
+                buf.read_qname(&mut mname)?;
+                let mut rname = String::with_capacity(256);
+                buf.read_qname(&mut rname)?;
+                Ok(DnsRecord::SOA {
+                    domain,
+                    mname,
+                    rname,
+                    serial: buf.read_u32()?,
+                    refresh: buf.read_u32()?,
+                    retry: buf.read_u32()?,
+                    expire: buf.read_u32()?,
+                    minimum: buf.read_u32()?,
+                    class,
+                    ttl,
+                })
             }
             QueryType::MX => Ok(DnsRecord::MX {
                 domain,
@@ -79,63 +566,395 @@ impl DnsRecord {
                     buf.read_qname(&mut exchange)?;
                     exchange
                 },
+                class,
+                ttl,
+            }),
+            QueryType::SRV => Ok(DnsRecord::SRV {
+                domain,
+                priority: buf.read_u16()?,
+                weight: buf.read_u16()?,
+                port: buf.read_u16()?,
+                target: {
+                    let mut target = String::with_capacity(256);
+                    buf.read_qname(&mut target)?;
+                    target
+                },
+                class,
                 ttl,
             }),
-            QueryType::UNKOWN(_) => {
-                buf.step(data_len as usize);
-                Ok(DnsRecord::UNKOWN {
+            QueryType::DS => {
+                let key_tag = buf.read_u16()?;
+                let algorithm = buf.read_u8()?;
+                let digest_type = buf.read_u8()?;
+
+                let digest_len = (data_len as usize)
+                    .checked_sub(4)
+                    .ok_or("DS record too short")?;
+
+                Ok(DnsRecord::DS {
+                    domain,
+                    key_tag,
+                    algorithm,
+                    digest_type,
+                    digest: read_bytes(buf, digest_len)?,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::RRSIG => {
+                let end = buf.pos() + data_len as usize;
+                let type_covered = QueryType::from(buf.read_u16()?);
+                let algorithm = buf.read_u8()?;
+                let labels = buf.read_u8()?;
+                let original_ttl = buf.read_u32()?;
+                let expiration = buf.read_u32()?;
+                let inception = buf.read_u32()?;
+                let key_tag = buf.read_u16()?;
+
+                let mut signer = String::with_capacity(256);
+                buf.read_qname(&mut signer)?;
+
+                // the signature takes up the rest of the rdata
+                let signature_len = end
+                    .checked_sub(buf.pos())
+                    .ok_or("RRSIG signer longer than its record")?;
+
+                Ok(DnsRecord::RRSIG {
+                    domain,
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    expiration,
+                    inception,
+                    key_tag,
+                    signer,
+                    signature: read_bytes(buf, signature_len)?,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::NSEC => {
+                let end = buf.pos() + data_len as usize;
+                let mut next_domain = String::with_capacity(256);
+                buf.read_qname(&mut next_domain)?;
+
+                // the type bitmap takes up the rest of the rdata
+                let bitmap_len = end
+                    .checked_sub(buf.pos())
+                    .ok_or("NSEC next name longer than its record")?;
+
+                Ok(DnsRecord::NSEC {
+                    domain,
+                    next_domain,
+                    types: read_type_bitmap(buf, bitmap_len)?,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::DNSKEY => {
+                let flags = buf.read_u16()?;
+                let protocol = buf.read_u8()?;
+                let algorithm = buf.read_u8()?;
+
+                let key_len = (data_len as usize)
+                    .checked_sub(4)
+                    .ok_or("DNSKEY record too short")?;
+
+                Ok(DnsRecord::DNSKEY {
+                    domain,
+                    flags,
+                    protocol,
+                    algorithm,
+                    key: read_bytes(buf, key_len)?,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::NSEC3 => {
+                let end = buf.pos() + data_len as usize;
+                let hash_algorithm = buf.read_u8()?;
+                let flags = buf.read_u8()?;
+                let iterations = buf.read_u16()?;
+                let salt_len = buf.read_u8()? as usize;
+                let salt = read_bytes(buf, salt_len)?;
+                let hash_len = buf.read_u8()? as usize;
+                let next_hashed = read_bytes(buf, hash_len)?;
+
+                // the type bitmap takes up the rest of the rdata
+                let bitmap_len = end
+                    .checked_sub(buf.pos())
+                    .ok_or("NSEC3 hashes longer than their record")?;
+
+                Ok(DnsRecord::NSEC3 {
+                    domain,
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                    next_hashed,
+                    types: read_type_bitmap(buf, bitmap_len)?,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::NSEC3PARAM => {
+                let hash_algorithm = buf.read_u8()?;
+                let flags = buf.read_u8()?;
+                let iterations = buf.read_u16()?;
+                let salt_len = buf.read_u8()? as usize;
+
+                Ok(DnsRecord::NSEC3PARAM {
                     domain,
-                    qtype,
-                    data_len,
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt: read_bytes(buf, salt_len)?,
+                    class,
                     ttl,
                 })
             }
+            QueryType::TLSA => {
+                let usage = buf.read_u8()?;
+                let selector = buf.read_u8()?;
+                let matching_type = buf.read_u8()?;
+
+                let data_len = (data_len as usize)
+                    .checked_sub(3)
+                    .ok_or("TLSA record too short")?;
+
+                Ok(DnsRecord::TLSA {
+                    domain,
+                    usage,
+                    selector,
+                    matching_type,
+                    data: read_bytes(buf, data_len)?,
+                    class,
+                    ttl,
+                })
+            }
+            qtype @ (QueryType::SVCB | QueryType::HTTPS) => {
+                let end = buf.pos() + data_len as usize;
+                let priority = buf.read_u16()?;
+                let mut target = String::with_capacity(256);
+                buf.read_qname(&mut target)?;
+
+                // key, length and value triples until the rdata runs out
+                let mut params = Vec::new();
+                while buf.pos() < end {
+                    let key = buf.read_u16()?;
+                    let len = buf.read_u16()? as usize;
+                    params.push(SvcParam::decode(key, &read_bytes(buf, len)?)?);
+                }
+
+                Ok(match qtype {
+                    QueryType::HTTPS => DnsRecord::HTTPS {
+                        domain,
+                        priority,
+                        target,
+                        params,
+                        class,
+                        ttl,
+                    },
+                    _ => DnsRecord::SVCB {
+                        domain,
+                        priority,
+                        target,
+                        params,
+                        class,
+                        ttl,
+                    },
+                })
+            }
+            QueryType::DNAME => {
+                let mut target = String::with_capacity(256);
+                buf.read_qname(&mut target)?;
+                Ok(DnsRecord::DNAME {
+                    domain,
+                    target,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::TXT => {
+                // character strings, each prefixed with its length, until
+                // the rdata runs out
+                let mut data = Vec::new();
+                let end = buf.pos() + data_len as usize;
+                while buf.pos() < end {
+                    let len = buf.read_u8()? as usize;
+                    data.push(read_bytes(buf, len)?);
+                }
+                Ok(DnsRecord::TXT {
+                    domain,
+                    data,
+                    class,
+                    ttl,
+                })
+            }
+            QueryType::CAA => {
+                let flags = buf.read_u8()?;
+                let tag_len = buf.read_u8()? as usize;
+                let tag = read_string(buf, tag_len)?;
+
+                // the value takes up the rest of the rdata
+                let value_len = (data_len as usize)
+                    .checked_sub(2 + tag_len)
+                    .ok_or("CAA tag longer than its record")?;
+                let value = read_bytes(buf, value_len)?;
+
+                Ok(DnsRecord::CAA {
+                    domain,
+                    flags,
+                    tag,
+                    value,
+                    class,
+                    ttl,
+                })
+            }
+            _ => Ok(DnsRecord::UNKOWN {
+                domain,
+                qtype,
+                data: read_rdata(buf, qtype, data_len as usize)?,
+                class,
+                ttl,
+            }),
         }
     }
 
-    pub fn write(&self, buf: &mut PacketBuffer) -> Result<usize, &'static str> {
+    // write the record, compressing its names against those already in the
+    // packet
+    pub fn write(
+        &self,
+        buf: &mut PacketBuffer,
+        names: &mut NameWriter,
+    ) -> Result<usize, &'static str> {
         let start_pos = buf.pos();
 
         match self {
-            DnsRecord::A { domain, addr, ttl } => {
-                buf.write_qname(domain)?;
-                buf.write_u16(QueryType::A.to_u16())?;
-                buf.write_u16(1)?; // qclass (always 1)
+            DnsRecord::A {
+                domain,
+                addr,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::A.into())?;
+                buf.write_u16((*class).into())?;
                 buf.write_u32(*ttl)?;
                 buf.write_u16(4)?; // data_len
                 buf.write_slice(&addr.octets())?; // data
             }
-            DnsRecord::AAAA { domain, addr, ttl } => {
-                buf.write_qname(domain)?;
-                buf.write_u16(QueryType::AAAA.to_u16())?;
-                buf.write_u16(1)?; // qclass (always 1)
+            DnsRecord::AAAA {
+                domain,
+                addr,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::AAAA.into())?;
+                buf.write_u16((*class).into())?;
                 buf.write_u32(*ttl)?;
                 buf.write_u16(16)?; // data_len
                 buf.write_slice(&addr.octets())?; // data
             }
-            DnsRecord::NS { domain, ns, ttl } => {
-                buf.write_qname(domain)?;
-                buf.write_u16(QueryType::NS.to_u16())?;
-                buf.write_u16(1)?; // qclass (always 1)
+            DnsRecord::NS {
+                domain,
+                ns,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::NS.into())?;
+                buf.write_u16((*class).into())?;
                 buf.write_u32(*ttl)?;
 
                 let pos = buf.pos();
                 buf.write_u16(0)?; // data_len
 
-                buf.write_qname(ns)?;
+                names.write_name(buf, ns, true)?;
                 let len = buf.pos() - pos - 2;
                 buf.set_u16(pos, len as u16)?;
             }
-            DnsRecord::CNAME { domain, cname, ttl } => {
-                buf.write_qname(domain)?;
-                buf.write_u16(QueryType::CNAME.to_u16())?;
-                buf.write_u16(1)?; // qclass (always 1)
+            DnsRecord::CNAME {
+                domain,
+                cname,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::CNAME.into())?;
+                buf.write_u16((*class).into())?;
                 buf.write_u32(*ttl)?;
 
                 let pos = buf.pos();
                 buf.write_u16(0)?; // data_len
 
-                buf.write_qname(cname)?;
+                names.write_name(buf, cname, true)?;
+                let len = buf.pos() - pos - 2;
+                buf.set_u16(pos, len as u16)?;
+            }
+            DnsRecord::PTR {
+                domain,
+                host,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::PTR.into())?;
+                buf.write_u16((*class).into())?;
+                buf.write_u32(*ttl)?;
+
+                let pos = buf.pos();
+                buf.write_u16(0)?; // data_len
+
+                names.write_name(buf, host, true)?;
+                let len = buf.pos() - pos - 2;
+                buf.set_u16(pos, len as u16)?;
+            }
+            DnsRecord::HINFO {
+                domain,
+                cpu,
+                os,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::HINFO.into())?;
+                buf.write_u16((*class).into())?;
+                buf.write_u32(*ttl)?;
+                buf.write_u16((2 + cpu.len() + os.len()) as u16)?; // data_len
+                write_short_bytes(buf, cpu.as_bytes())?;
+                write_short_bytes(buf, os.as_bytes())?;
+            }
+            DnsRecord::SOA {
+                domain,
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::SOA.into())?;
+                buf.write_u16((*class).into())?;
+                buf.write_u32(*ttl)?;
+
+                let pos = buf.pos();
+                buf.write_u16(0)?; // data_len
+
+                names.write_name(buf, mname, true)?;
+                names.write_name(buf, rname, true)?;
+                buf.write_u32(*serial)?;
+                buf.write_u32(*refresh)?;
+                buf.write_u32(*retry)?;
+                buf.write_u32(*expire)?;
+                buf.write_u32(*minimum)?;
+
                 let len = buf.pos() - pos - 2;
                 buf.set_u16(pos, len as u16)?;
             }
@@ -143,28 +962,508 @@ impl DnsRecord {
                 domain,
                 preference,
                 exchange,
+                class,
                 ttl,
             } => {
-                buf.write_qname(domain)?;
-                buf.write_u16(QueryType::MX.to_u16())?;
-                buf.write_u16(1)?; // qclass (always 1)
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::MX.into())?;
+                buf.write_u16((*class).into())?;
                 buf.write_u32(*ttl)?;
 
                 let pos = buf.pos();
                 buf.write_u16(0)?; // data_len
 
                 buf.write_u16(*preference)?;
-                buf.write_qname(exchange)?;
+                names.write_name(buf, exchange, true)?;
+
+                let len = buf.pos() - pos - 2;
+                buf.set_u16(pos, len as u16)?;
+            }
+            DnsRecord::SRV {
+                domain,
+                priority,
+                weight,
+                port,
+                target,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::SRV.into())?;
+                buf.write_u16((*class).into())?;
+                buf.write_u32(*ttl)?;
+
+                let pos = buf.pos();
+                buf.write_u16(0)?; // data_len
+
+                buf.write_u16(*priority)?;
+                buf.write_u16(*weight)?;
+                buf.write_u16(*port)?;
+                // the target must not be compressed (RFC 2782)
+                names.write_name(buf, target, false)?;
 
                 let len = buf.pos() - pos - 2;
                 buf.set_u16(pos, len as u16)?;
             }
+            DnsRecord::DS {
+                domain,
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::DS.into())?;
+                buf.write_u16((*class).into())?;
+                buf.write_u32(*ttl)?;
+                buf.write_u16(4 + digest.len() as u16)?; // data_len
+
+                buf.write_u16(*key_tag)?;
+                buf.write_u8(*algorithm)?;
+                buf.write_u8(*digest_type)?;
+                for byte in digest {
+                    buf.write_u8(*byte)?;
+                }
+            }
+            DnsRecord::RRSIG {
+                domain,
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer,
+                signature,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::RRSIG.into())?;
+                buf.write_u16((*class).into())?;
+                buf.write_u32(*ttl)?;
+
+                let pos = buf.pos();
+                buf.write_u16(0)?; // data_len
+
+                buf.write_u16((*type_covered).into())?;
+                buf.write_u8(*algorithm)?;
+                buf.write_u8(*labels)?;
+                buf.write_u32(*original_ttl)?;
+                buf.write_u32(*expiration)?;
+                buf.write_u32(*inception)?;
+                buf.write_u16(*key_tag)?;
+                // the signer must not be compressed (RFC 4034 3.1.7)
+                names.write_name(buf, signer, false)?;
+                for byte in signature {
+                    buf.write_u8(*byte)?;
+                }
 
-            DnsRecord::UNKOWN { .. } => {
-                println!("write DnsRecord::UNKOWN not implemented");
+                let len = buf.pos() - pos - 2;
+                buf.set_u16(pos, len as u16)?;
+            }
+            DnsRecord::NSEC {
+                domain,
+                next_domain,
+                types,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::NSEC.into())?;
+                buf.write_u16((*class).into())?;
+                buf.write_u32(*ttl)?;
+
+                let pos = buf.pos();
+                buf.write_u16(0)?; // data_len
+
+                // the next name must not be compressed (RFC 4034 4.1.1)
+                names.write_name(buf, next_domain, false)?;
+                write_type_bitmap(buf, types)?;
+
+                let len = buf.pos() - pos - 2;
+                buf.set_u16(pos, len as u16)?;
+            }
+            DnsRecord::DNSKEY {
+                domain,
+                flags,
+                protocol,
+                algorithm,
+                key,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::DNSKEY.into())?;
+                buf.write_u16((*class).into())?;
+                buf.write_u32(*ttl)?;
+                buf.write_u16(4 + key.len() as u16)?; // data_len
+
+                buf.write_u16(*flags)?;
+                buf.write_u8(*protocol)?;
+                buf.write_u8(*algorithm)?;
+                for byte in key {
+                    buf.write_u8(*byte)?;
+                }
+            }
+            DnsRecord::NSEC3 {
+                domain,
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed,
+                types,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::NSEC3.into())?;
+                buf.write_u16((*class).into())?;
+                buf.write_u32(*ttl)?;
+
+                let pos = buf.pos();
+                buf.write_u16(0)?; // data_len
+
+                buf.write_u8(*hash_algorithm)?;
+                buf.write_u8(*flags)?;
+                buf.write_u16(*iterations)?;
+                write_short_bytes(buf, salt)?;
+                write_short_bytes(buf, next_hashed)?;
+                write_type_bitmap(buf, types)?;
+
+                let len = buf.pos() - pos - 2;
+                buf.set_u16(pos, len as u16)?;
+            }
+            DnsRecord::NSEC3PARAM {
+                domain,
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::NSEC3PARAM.into())?;
+                buf.write_u16((*class).into())?;
+                buf.write_u32(*ttl)?;
+                buf.write_u16(5 + salt.len() as u16)?; // data_len
+
+                buf.write_u8(*hash_algorithm)?;
+                buf.write_u8(*flags)?;
+                buf.write_u16(*iterations)?;
+                write_short_bytes(buf, salt)?;
+            }
+            DnsRecord::TLSA {
+                domain,
+                usage,
+                selector,
+                matching_type,
+                data,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::TLSA.into())?;
+                buf.write_u16((*class).into())?;
+                buf.write_u32(*ttl)?;
+                buf.write_u16(3 + data.len() as u16)?; // data_len
+
+                buf.write_u8(*usage)?;
+                buf.write_u8(*selector)?;
+                buf.write_u8(*matching_type)?;
+                for byte in data {
+                    buf.write_u8(*byte)?;
+                }
+            }
+            DnsRecord::SVCB {
+                domain,
+                priority,
+                target,
+                params,
+                class,
+                ttl,
+            }
+            | DnsRecord::HTTPS {
+                domain,
+                priority,
+                target,
+                params,
+                class,
+                ttl,
+            } => {
+                let qtype = match self {
+                    DnsRecord::HTTPS { .. } => QueryType::HTTPS,
+                    _ => QueryType::SVCB,
+                };
+
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(qtype.into())?;
+                buf.write_u16((*class).into())?;
+                buf.write_u32(*ttl)?;
+
+                let pos = buf.pos();
+                buf.write_u16(0)?; // data_len
+
+                buf.write_u16(*priority)?;
+                // the target must not be compressed (RFC 9460 2.2)
+                names.write_name(buf, target, false)?;
+                for param in params {
+                    let value = param.encode();
+                    buf.write_u16(param.key())?;
+                    buf.write_u16(value.len() as u16)?;
+                    for byte in value {
+                        buf.write_u8(byte)?;
+                    }
+                }
+
+                let len = buf.pos() - pos - 2;
+                buf.set_u16(pos, len as u16)?;
+            }
+            DnsRecord::CAA {
+                domain,
+                flags,
+                tag,
+                value,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::CAA.into())?;
+                buf.write_u16((*class).into())?;
+                buf.write_u32(*ttl)?;
+
+                let pos = buf.pos();
+                buf.write_u16(0)?; // data_len
+
+                if tag.is_empty() || tag.len() > 255 {
+                    return Err("CAA tag must be 1 to 255 bytes");
+                }
+
+                buf.write_u8(*flags)?;
+                buf.write_u8(tag.len() as u8)?;
+                for byte in tag.as_bytes().iter().chain(value) {
+                    buf.write_u8(*byte)?;
+                }
+
+                let len = buf.pos() - pos - 2;
+                buf.set_u16(pos, len as u16)?;
+            }
+            DnsRecord::DNAME {
+                domain,
+                target,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::DNAME.into())?;
+                buf.write_u16((*class).into())?;
+                buf.write_u32(*ttl)?;
+
+                let pos = buf.pos();
+                buf.write_u16(0)?; // data_len
+
+                // the target must not be compressed (RFC 6672 2.5)
+                names.write_name(buf, target, false)?;
+                let len = buf.pos() - pos - 2;
+                buf.set_u16(pos, len as u16)?;
+            }
+            DnsRecord::TXT {
+                domain,
+                data,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(QueryType::TXT.into())?;
+                buf.write_u16((*class).into())?;
+                buf.write_u32(*ttl)?;
+
+                let pos = buf.pos();
+                buf.write_u16(0)?; // data_len
+
+                for string in data {
+                    if string.len() > 255 {
+                        return Err("character string too long (max 255 bytes)");
+                    }
+
+                    buf.write_u8(string.len() as u8)?;
+                    for byte in string {
+                        buf.write_u8(*byte)?;
+                    }
+                }
+
+                let len = buf.pos() - pos - 2;
+                buf.set_u16(pos, len as u16)?;
+            }
+
+            DnsRecord::UNKOWN {
+                domain,
+                qtype,
+                data,
+                class,
+                ttl,
+            } => {
+                names.write_name(buf, domain, true)?;
+                buf.write_u16(*qtype)?;
+                buf.write_u16((*class).into())?;
+                buf.write_u32(*ttl)?;
+                buf.write_u16(data.len() as u16)?; // data_len
+
+                // names in the rdata were expanded when it was read, and
+                // types defined since RFC 3597 never compress them, so it
+                // can be copied byte for byte
+                for byte in data {
+                    buf.write_u8(*byte)?;
+                }
             }
         }
 
         Ok(buf.pos() - start_pos)
     }
 }
+
+// a part of the rdata of a type without a variant
+enum Field {
+    Name,         // a domain name, possibly compressed
+    Bytes(usize), // that many bytes
+    String,       // a character string
+}
+
+// the rdata of types without a variant that have names in it, up to the
+// last name. these are the older types whose names may be compressed
+// (RFC 3597 4), the others never have compressed names
+fn name_fields(qtype: u16) -> &'static [Field] {
+    use Field::*;
+
+    match qtype {
+        3 | 4 | 7 | 8 | 9 => &[Name],                    // MD, MF, MB, MG, MR
+        14 | 17 => &[Name, Name],                        // MINFO, RP
+        18 | 21 => &[Bytes(2), Name],                    // AFSDB, RT
+        24 => &[Bytes(18), Name],                        // SIG, the signature follows
+        26 => &[Bytes(2), Name, Name],                   // PX
+        30 => &[Name],                                   // NXT, the type bitmap follows
+        35 => &[Bytes(4), String, String, String, Name], // NAPTR
+        _ => &[],
+    }
+}
+
+// read the rdata of a type without a variant, expanding any compressed
+// names in it so it means the same wherever it's written
+fn read_rdata(buf: &mut PacketBuffer, qtype: u16, len: usize) -> Result<Vec<u8>, &'static str> {
+    let end = buf.pos() + len;
+    let mut data = PacketBuffer::with_len(PacketBuffer::MAX_LEN);
+
+    for field in name_fields(qtype) {
+        match field {
+            Field::Name => {
+                let mut name = String::new();
+                buf.read_qname(&mut name)?;
+                NameWriter::new().write_name(&mut data, &name, false)?;
+            }
+            Field::Bytes(len) => {
+                for byte in read_bytes(buf, *len)? {
+                    data.write_u8(byte)?;
+                }
+            }
+            Field::String => {
+                let len = buf.read_u8()?;
+                data.write_u8(len)?;
+                for byte in read_bytes(buf, len as usize)? {
+                    data.write_u8(byte)?;
+                }
+            }
+        }
+    }
+
+    let rest = end
+        .checked_sub(buf.pos())
+        .ok_or("rdata longer than its record")?;
+    let mut data = data.as_slice().to_vec();
+    data.extend(read_bytes(buf, rest)?);
+    Ok(data)
+}
+
+// read len raw bytes
+fn read_bytes(buf: &mut PacketBuffer, len: usize) -> Result<Vec<u8>, &'static str> {
+    let mut bytes = Vec::with_capacity(len);
+    for _ in 0..len {
+        bytes.push(buf.read_u8()?);
+    }
+
+    Ok(bytes)
+}
+
+// read len bytes of text, anything that isn't utf8 is replaced
+fn read_string(buf: &mut PacketBuffer, len: usize) -> Result<String, &'static str> {
+    Ok(String::from_utf8_lossy(&read_bytes(buf, len)?).into_owned())
+}
+
+// write bytes prefixed with their length as a u8
+fn write_short_bytes(buf: &mut PacketBuffer, bytes: &[u8]) -> Result<(), &'static str> {
+    if bytes.len() > 255 {
+        return Err("field too long (max 255 bytes)");
+    }
+
+    buf.write_u8(bytes.len() as u8)?;
+    for byte in bytes {
+        buf.write_u8(*byte)?;
+    }
+
+    Ok(())
+}
+
+// read the types in a type bitmap of len bytes (RFC 4034 4.1.2). types are
+// split into windows of 256, each a window number, a length and a bitmap
+// where the most significant bit of the first byte is the window's first type
+fn read_type_bitmap(buf: &mut PacketBuffer, len: usize) -> Result<Vec<QueryType>, &'static str> {
+    let mut types = Vec::new();
+    let end = buf.pos() + len;
+    while buf.pos() < end {
+        let window = buf.read_u8()? as u16;
+        let bitmap_len = buf.read_u8()? as usize;
+        if bitmap_len == 0 || bitmap_len > 32 {
+            return Err("invalid type bitmap length");
+        }
+
+        for (i, byte) in read_bytes(buf, bitmap_len)?.into_iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    types.push(QueryType::from(window << 8 | (i * 8 + bit) as u16));
+                }
+            }
+        }
+    }
+
+    Ok(types)
+}
+
+// write types as a type bitmap, in the order and windows RFC 4034 4.1.2 wants
+fn write_type_bitmap(buf: &mut PacketBuffer, types: &[QueryType]) -> Result<(), &'static str> {
+    let mut types: Vec<u16> = types.iter().map(|qtype| u16::from(*qtype)).collect();
+    types.sort();
+    types.dedup();
+
+    for window in types.chunk_by(|a, b| a >> 8 == b >> 8) {
+        // only as many bytes as it takes to reach the highest type
+        let mut bitmap = [0u8; 32];
+        let mut bitmap_len = 0;
+        for qtype in window {
+            let low = (qtype & 0xFF) as usize;
+            bitmap[low / 8] |= 0x80 >> (low % 8);
+            bitmap_len = low / 8 + 1;
+        }
+
+        buf.write_u8((window[0] >> 8) as u8)?;
+        buf.write_u8(bitmap_len as u8)?;
+        for byte in &bitmap[..bitmap_len] {
+            buf.write_u8(*byte)?;
+        }
+    }
+
+    Ok(())
+}
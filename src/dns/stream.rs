@@ -0,0 +1,56 @@
+use crate::buffer::PacketBuffer;
+
+use super::packet::DnsPacket;
+
+// reassembles length prefixed messages (RFC 1035 4.2.2) from stream bytes
+// fed in whatever chunks they arrive in, so a length prefix or message
+// split across reads is picked up where it left off
+#[derive(Debug, Default)]
+pub struct StreamDecoder {
+    pending: Vec<u8>, // bytes received but not yet returned as a message
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        StreamDecoder {
+            pending: Vec::new(),
+        }
+    }
+
+    // append bytes read from the stream
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+    }
+
+    // nothing buffered, i.e. the stream is between messages
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    // take the next complete message and its length, or None if more bytes
+    // are needed. any length a prefix can give fits a buffer of its own
+    pub fn next_message(&mut self) -> Result<Option<(PacketBuffer, usize)>, &'static str> {
+        if self.pending.len() < 2 {
+            return Ok(None);
+        }
+
+        let len = u16::from_be_bytes([self.pending[0], self.pending[1]]) as usize;
+        if self.pending.len() < 2 + len {
+            return Ok(None);
+        }
+
+        let mut buffer = PacketBuffer::with_len(len);
+        buffer.buf.copy_from_slice(&self.pending[2..2 + len]);
+        self.pending.drain(..2 + len);
+
+        Ok(Some((buffer, len)))
+    }
+
+    // take and parse the next complete message
+    pub fn next_packet(&mut self) -> Result<Option<DnsPacket>, &'static str> {
+        match self.next_message()? {
+            Some((mut buffer, _)) => DnsPacket::read(&mut buffer).map(Some),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream, UdpSocket},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crypto_box::{
+    aead::{rand_core::RngCore, Aead, OsRng},
+    ChaChaBox, Nonce, PublicKey, SalsaBox, SecretKey,
+};
+use ed25519_dalek::{Signature, VerifyingKey};
+
+use crate::{
+    buffer::PacketBuffer,
+    dns::{header::DnsHeader, packet::DnsPacket, question::QueryType, record::DnsRecord},
+};
+
+// certificates start with this, followed by the version of the construction
+// their key is used with
+const CERT_MAGIC: &[u8; 4] = b"DNSC";
+
+// encrypted responses start with this, queries with the certificate's magic
+const RESOLVER_MAGIC: &[u8; 8] = b"r6fnvWj8";
+
+// udp queries are padded to at least this, so responses can be as large
+// without amplifying anything (DNSCrypt v2 4.4)
+const MIN_QUERY_LEN: usize = 256;
+
+// a certificate's resolver key, with the box shared with it
+enum Cipher {
+    Salsa(SalsaBox),   // es-version 1, X25519-XSalsa20Poly1305
+    ChaCha(ChaChaBox), // es-version 2, X25519-XChacha20Poly1305
+}
+
+impl Cipher {
+    fn encrypt(&self, nonce: &Nonce, plaintext: &[u8]) -> Result<Vec<u8>, &'static str> {
+        match self {
+            Cipher::Salsa(cipher) => cipher.encrypt(nonce, plaintext),
+            Cipher::ChaCha(cipher) => cipher.encrypt(nonce, plaintext),
+        }
+        .map_err(|_| "failed to encrypt")
+    }
+
+    fn decrypt(&self, nonce: &Nonce, ciphertext: &[u8]) -> Result<Vec<u8>, &'static str> {
+        match self {
+            Cipher::Salsa(cipher) => cipher.decrypt(nonce, ciphertext),
+            Cipher::ChaCha(cipher) => cipher.decrypt(nonce, ciphertext),
+        }
+        .map_err(|_| "failed to decrypt")
+    }
+}
+
+// what queries to a server are encrypted with, from its certificate
+#[derive(Clone)]
+struct Cert {
+    cipher: Arc<Cipher>,   // computing it takes a key exchange, done once
+    client_magic: [u8; 8], // sent first in queries, picks the certificate
+    version: u16,
+    serial: u32,
+    expires: u32, // seconds since the epoch
+}
+
+// dnscrypt v2 to upstreams that support it, known by their provider name
+// and the provider's ed25519 public key. the server's certificate is
+// fetched with a TXT query for the provider name, verified with that key,
+// and kept until it expires. queries go over udp, and over tcp when the
+// answer doesn't fit
+pub struct DnsCryptClient {
+    secret: SecretKey, // ours, its public key is sent with every query
+    certs: Mutex<HashMap<(SocketAddr, String), Cert>>,
+    pub timeout: Duration, // for connecting and for each response
+}
+
+impl Default for DnsCryptClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the keys are nothing worth printing
+impl std::fmt::Debug for DnsCryptClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DnsCryptClient")
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DnsCryptClient {
+    // with a fresh key pair
+    pub fn new() -> Self {
+        DnsCryptClient {
+            secret: SecretKey::generate(&mut OsRng),
+            certs: Mutex::new(HashMap::new()),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    // send a raw query to server for provider, whose certificates are
+    // signed with provider_key, and receive the raw response
+    pub fn exchange(
+        &self,
+        query: &[u8],
+        server: SocketAddr,
+        provider: &str,
+        provider_key: &[u8; 32],
+    ) -> Result<(PacketBuffer, usize), &'static str> {
+        let cert = self.cert(server, provider, provider_key)?;
+
+        let result = self
+            .exchange_udp(query, server, &cert)
+            .and_then(|response| {
+                // too large for the padded query, asked again over tcp
+                match DnsHeader::read(&mut to_buffer(&response)) {
+                    Ok(header) if header.tc => self.exchange_tcp(query, server, &cert),
+                    _ => Ok(response),
+                }
+            });
+
+        // the server may have moved on to a new certificate, which is
+        // fetched again on the next query
+        if result.is_err() {
+            let mut certs = self.certs.lock().unwrap_or_else(|e| e.into_inner());
+            certs.remove(&(server, provider.to_string()));
+        }
+
+        let response = result?;
+        Ok((to_buffer(&response), response.len()))
+    }
+
+    // number of servers whose certificate is known
+    pub fn certs_len(&self) -> usize {
+        self.certs.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    // forget every certificate, they are fetched again on the next query.
+    // e.g. once the network changed, and with it maybe the servers behind
+    // the addresses
+    pub fn clear(&self) {
+        self.certs.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    // the certificate for provider at server, fetched unless one that
+    // hasn't expired is known
+    fn cert(
+        &self,
+        server: SocketAddr,
+        provider: &str,
+        provider_key: &[u8; 32],
+    ) -> Result<Cert, &'static str> {
+        let key = (server, provider.to_string());
+        let now = unix_time();
+        {
+            let certs = self.certs.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(cert) = certs.get(&key).filter(|cert| cert.expires > now) {
+                return Ok(cert.clone());
+            }
+        }
+
+        let cert = self.fetch_cert(server, provider, provider_key, now)?;
+        let mut certs = self.certs.lock().unwrap_or_else(|e| e.into_inner());
+        certs.insert(key, cert.clone());
+        Ok(cert)
+    }
+
+    // ask server for the certificates of provider, and pick the best one
+    // that is signed by provider_key and valid at now: the newest
+    // construction, then the highest serial
+    fn fetch_cert(
+        &self,
+        server: SocketAddr,
+        provider: &str,
+        provider_key: &[u8; 32],
+        now: u32,
+    ) -> Result<Cert, &'static str> {
+        let provider_key =
+            VerifyingKey::from_bytes(provider_key).map_err(|_| "invalid provider key")?;
+
+        let query = crate::query_buffer(provider.to_string(), QueryType::TXT, 0, &[])?;
+        let socket = self.socket(server)?;
+        socket
+            .send_to(query.as_slice(), server)
+            .map_err(|_| "failed to send")?;
+        let mut response = PacketBuffer::new();
+        socket
+            .recv(response.as_mut_slice())
+            .map_err(|_| "failed to recv")?;
+
+        // each TXT record is one certificate, split into character strings
+        DnsPacket::read(&mut response)?
+            .answers
+            .iter()
+            .filter_map(|answer| match answer {
+                DnsRecord::TXT { data, .. } => Some(data.concat()),
+                _ => None,
+            })
+            .filter_map(|cert| self.parse_cert(&cert, &provider_key, now))
+            .max_by_key(|cert| (cert.version, cert.serial))
+            .ok_or("no valid certificate")
+    }
+
+    // a certificate (DNSCrypt v2 5.2), if it is signed by provider_key,
+    // valid at now, and for a construction we support
+    fn parse_cert(&self, cert: &[u8], provider_key: &VerifyingKey, now: u32) -> Option<Cert> {
+        if cert.len() < 124 || &cert[..4] != CERT_MAGIC || cert[6..8] != [0, 0] {
+            return None;
+        }
+
+        let signature = Signature::from_bytes(cert[8..72].try_into().ok()?);
+        provider_key.verify_strict(&cert[72..], &signature).ok()?;
+
+        let u32_at = |pos: usize| u32::from_be_bytes(cert[pos..pos + 4].try_into().unwrap());
+        let (serial, starts, expires) = (u32_at(112), u32_at(116), u32_at(120));
+        if now < starts || now >= expires {
+            return None;
+        }
+
+        let resolver_key = PublicKey::from_slice(&cert[72..104]).ok()?;
+        let version = u16::from_be_bytes([cert[4], cert[5]]);
+        let cipher = match version {
+            1 => Cipher::Salsa(SalsaBox::new(&resolver_key, &self.secret)),
+            2 => Cipher::ChaCha(ChaChaBox::new(&resolver_key, &self.secret)),
+            _ => return None,
+        };
+
+        Some(Cert {
+            cipher: Arc::new(cipher),
+            client_magic: cert[104..112].try_into().ok()?,
+            version,
+            serial,
+            expires,
+        })
+    }
+
+    fn exchange_udp(
+        &self,
+        query: &[u8],
+        server: SocketAddr,
+        cert: &Cert,
+    ) -> Result<Vec<u8>, &'static str> {
+        let (message, client_nonce) = self.encrypt(query, cert, MIN_QUERY_LEN)?;
+
+        let socket = self.socket(server)?;
+        socket.send(&message).map_err(|_| "failed to send")?;
+        let mut response = vec![0; PacketBuffer::MAX_LEN];
+        let len = socket.recv(&mut response).map_err(|_| "failed to recv")?;
+
+        decrypt(&response[..len], cert, &client_nonce)
+    }
+
+    // the same over a tcp connection, each message prefixed with its length
+    fn exchange_tcp(
+        &self,
+        query: &[u8],
+        server: SocketAddr,
+        cert: &Cert,
+    ) -> Result<Vec<u8>, &'static str> {
+        let (message, client_nonce) = self.encrypt(query, cert, 0)?;
+        if message.len() > u16::MAX as usize {
+            return Err("invalid query");
+        }
+
+        let mut stream =
+            TcpStream::connect_timeout(&server, self.timeout).map_err(|_| "failed to connect")?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .map_err(|_| "failed to connect")?;
+        stream
+            .write_all(&(message.len() as u16).to_be_bytes())
+            .and_then(|_| stream.write_all(&message))
+            .map_err(|_| "failed to send")?;
+
+        let mut len = [0; 2];
+        stream.read_exact(&mut len).map_err(|_| "failed to recv")?;
+        let mut response = vec![0; u16::from_be_bytes(len) as usize];
+        stream
+            .read_exact(&mut response)
+            .map_err(|_| "failed to recv")?;
+
+        decrypt(&response, cert, &client_nonce)
+    }
+
+    // the encrypted query, padded to a multiple of 64 bytes and at least
+    // min_len (DNSCrypt v2 4.3), and the client half of its nonce
+    fn encrypt(
+        &self,
+        query: &[u8],
+        cert: &Cert,
+        min_len: usize,
+    ) -> Result<(Vec<u8>, [u8; 12]), &'static str> {
+        let mut client_nonce = [0; 12];
+        OsRng.fill_bytes(&mut client_nonce);
+        let mut nonce = [0; 24];
+        nonce[..12].copy_from_slice(&client_nonce);
+
+        let padded_len = ((query.len() + 1).div_ceil(64) * 64).max(min_len);
+        let mut padded = Vec::with_capacity(padded_len);
+        padded.extend_from_slice(query);
+        padded.push(0x80);
+        padded.resize(padded_len, 0);
+
+        let ciphertext = cert.cipher.encrypt(Nonce::from_slice(&nonce), &padded)?;
+
+        let mut message = Vec::with_capacity(52 + ciphertext.len());
+        message.extend_from_slice(&cert.client_magic);
+        message.extend_from_slice(self.secret.public_key().as_bytes());
+        message.extend_from_slice(&client_nonce);
+        message.extend_from_slice(&ciphertext);
+        Ok((message, client_nonce))
+    }
+
+    fn socket(&self, server: SocketAddr) -> Result<UdpSocket, &'static str> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0)).map_err(|_| "failed to bind")?;
+        socket
+            .set_read_timeout(Some(self.timeout))
+            .and_then(|_| socket.connect(server))
+            .map_err(|_| "failed to connect")?;
+        Ok(socket)
+    }
+}
+
+// the dns response in an encrypted one, whose nonce has to start with the
+// query's (DNSCrypt v2 4.5)
+fn decrypt(response: &[u8], cert: &Cert, client_nonce: &[u8; 12]) -> Result<Vec<u8>, &'static str> {
+    if response.len() < 32 || &response[..8] != RESOLVER_MAGIC || response[8..20] != *client_nonce {
+        return Err("invalid response");
+    }
+
+    let padded = cert
+        .cipher
+        .decrypt(Nonce::from_slice(&response[8..32]), &response[32..])?;
+
+    // the padding is a 0x80 byte followed by zeros
+    let end = padded
+        .iter()
+        .rposition(|byte| *byte != 0)
+        .filter(|end| padded[*end] == 0x80)
+        .ok_or("invalid padding")?;
+    Ok(padded[..end].to_vec())
+}
+
+fn to_buffer(message: &[u8]) -> PacketBuffer {
+    let mut buffer = PacketBuffer::with_len(message.len());
+    buffer.buf.copy_from_slice(message);
+    buffer
+}
+
+fn unix_time() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as u32)
+}
@@ -0,0 +1,228 @@
+use std::io::Write;
+
+// the path queries are served on (RFC 8484 4.1)
+pub const PATH: &str = "/dns-query";
+
+// media type of dns messages in requests and responses (RFC 8484 6)
+pub const CONTENT_TYPE: &str = "application/dns-message";
+
+// request heads longer than this are refused rather than buffered
+const MAX_HEAD: usize = 8192;
+
+// an http/1.1 request, as much of it as dns over https needs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub target: String,                 // path and query string
+    pub version: String,                // e.g. "HTTP/1.1"
+    pub headers: Vec<(String, String)>, // names lowercased
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    // value of the first header called name, which is matched lowercase
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // whether the client leaves the connection open for another request,
+    // by default from http/1.1 on
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("connection").unwrap_or("").to_ascii_lowercase();
+        match self.version.as_str() {
+            "HTTP/1.0" => connection == "keep-alive",
+            _ => connection != "close",
+        }
+    }
+
+    // the raw dns query carried by the request, or the status to refuse it
+    // with. GET sends it base64url encoded in the dns parameter, POST as
+    // the body (RFC 8484 4.1)
+    pub fn dns_query(&self) -> Result<Vec<u8>, u16> {
+        let (path, params) = match self.target.split_once('?') {
+            Some((path, params)) => (path, params),
+            None => (self.target.as_str(), ""),
+        };
+        if path != PATH {
+            return Err(404);
+        }
+
+        match self.method.as_str() {
+            "GET" => params
+                .split('&')
+                .find_map(|param| param.strip_prefix("dns="))
+                .and_then(base64url_decode)
+                .ok_or(400),
+            "POST" => {
+                let content_type = self.header("content-type").unwrap_or("");
+                if !content_type.eq_ignore_ascii_case(CONTENT_TYPE) {
+                    return Err(415);
+                }
+                Ok(self.body.clone())
+            }
+            _ => Err(405),
+        }
+    }
+}
+
+// reassembles http requests from stream bytes fed in whatever chunks they
+// arrive in, like StreamDecoder does for length prefixed messages
+#[derive(Debug, Default)]
+pub struct RequestDecoder {
+    pending: Vec<u8>, // bytes received but not yet returned as a request
+}
+
+impl RequestDecoder {
+    pub fn new() -> Self {
+        RequestDecoder {
+            pending: Vec::new(),
+        }
+    }
+
+    // append bytes read from the stream
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+    }
+
+    // nothing buffered, i.e. the stream is between requests
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    // take the next complete request, or None if more bytes are needed.
+    // a request that can't be parsed leaves the stream out of step, the
+    // connection should be closed after it
+    pub fn next_request(&mut self) -> Result<Option<Request>, &'static str> {
+        let head_len = match self.pending.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(end) => end + 4,
+            None if self.pending.len() > MAX_HEAD => return Err("request too long"),
+            None => return Ok(None),
+        };
+
+        let head =
+            std::str::from_utf8(&self.pending[..head_len - 4]).map_err(|_| "invalid request")?;
+        let mut lines = head.split("\r\n");
+
+        let mut request_line = lines.next().unwrap_or("").split(' ');
+        let (method, target, version) = match (
+            request_line.next(),
+            request_line.next(),
+            request_line.next(),
+        ) {
+            (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/") => {
+                (method, target, version)
+            }
+            _ => return Err("invalid request"),
+        };
+
+        let mut headers = Vec::new();
+        for line in lines {
+            let (name, value) = line.split_once(':').ok_or("invalid request")?;
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+
+        let mut request = Request {
+            method: method.to_string(),
+            target: target.to_string(),
+            version: version.to_string(),
+            headers,
+            body: Vec::new(),
+        };
+
+        // bodies only come with a length, queries are small enough for
+        // clients not to stream them
+        if request.header("transfer-encoding").is_some() {
+            return Err("chunked requests are not supported");
+        }
+        let body_len = match request.header("content-length") {
+            Some(len) => len.parse::<usize>().map_err(|_| "invalid request")?,
+            None => 0,
+        };
+        if body_len > u16::MAX as usize {
+            return Err("request too long");
+        }
+        if self.pending.len() < head_len + body_len {
+            return Ok(None);
+        }
+
+        self.pending.drain(..head_len);
+        request.body = self.pending.drain(..body_len).collect();
+        Ok(Some(request))
+    }
+}
+
+// the reason phrase sent with status
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Content Too Large",
+        415 => "Unsupported Media Type",
+        _ => "Internal Server Error",
+    }
+}
+
+// write a response with status and body, a dns message when the status is
+// 200. max_age says how long caches may keep it, which should not outlive
+// the ttls in it (RFC 8484 5.1)
+pub fn write_response<W: Write>(
+    stream: &mut W,
+    status: u16,
+    body: &[u8],
+    max_age: Option<u32>,
+    keep_alive: bool,
+) -> Result<(), &'static str> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", status, reason(status));
+    if status == 200 {
+        head.push_str(&format!("Content-Type: {}\r\n", CONTENT_TYPE));
+    }
+    if let Some(max_age) = max_age {
+        head.push_str(&format!("Cache-Control: max-age={}\r\n", max_age));
+    }
+    if !keep_alive {
+        head.push_str("Connection: close\r\n");
+    }
+    head.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+
+    stream
+        .write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(body))
+        .and_then(|_| stream.flush())
+        .map_err(|_| "failed to write")
+}
+
+// decode base64url without padding (RFC 4648 5), as the dns parameter is
+// sent, though padding is accepted too
+pub fn base64url_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut len = 0;
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None,
+        };
+        bits = bits << 6 | value as u32;
+        len += 6;
+        if len >= 8 {
+            len -= 8;
+            out.push((bits >> len) as u8);
+        }
+    }
+
+    // a single character left over can't be a whole byte
+    if len >= 6 {
+        return None;
+    }
+    Some(out)
+}
@@ -0,0 +1,59 @@
+use std::{
+    fmt::Display,
+    net::{IpAddr, UdpSocket},
+};
+
+use crate::{
+    dns::{packet::DnsPacket, question::DnsQuestion},
+    Transport,
+};
+
+// a query on its way through the layers
+pub struct Query<'a> {
+    pub question: &'a DnsQuestion,
+    pub src: &'a dyn Display, // client the query came from
+    pub addr: Option<IpAddr>, // its address, None on the unix socket
+    pub transport: Transport,
+    pub socket: &'a UdpSocket, // for any queries the layer sends upstream
+}
+
+// a stage of query handling, e.g. an ACL, a blocklist or a cache. a layer
+// sees every query before the layers after it, and either answers it
+// itself or hands it on with next.run, which ends in recursive resolution
+// from the root. layers are shared by all listener threads
+pub trait Layer: Send + Sync {
+    fn handle(&self, query: &Query, next: Next) -> Result<DnsPacket, &'static str>;
+
+    // the default route changed, drop whatever upstream state was built
+    // over the old one
+    fn network_changed(&self) {}
+}
+
+// the rest of the stack below a layer
+pub struct Next<'a> {
+    layers: &'a [Box<dyn Layer>],
+    resolve: &'a dyn Fn(&Query) -> Result<DnsPacket, &'static str>,
+}
+
+impl<'a> Next<'a> {
+    pub fn new(
+        layers: &'a [Box<dyn Layer>],
+        resolve: &'a dyn Fn(&Query) -> Result<DnsPacket, &'static str>,
+    ) -> Self {
+        Next { layers, resolve }
+    }
+
+    // pass the query to the next layer, or resolve it if there are none left
+    pub fn run(self, query: &Query) -> Result<DnsPacket, &'static str> {
+        match self.layers.split_first() {
+            Some((layer, layers)) => layer.handle(
+                query,
+                Next {
+                    layers,
+                    resolve: self.resolve,
+                },
+            ),
+            None => (self.resolve)(query),
+        }
+    }
+}
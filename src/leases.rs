@@ -0,0 +1,310 @@
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    dns::{
+        header::ResultCode,
+        packet::DnsPacket,
+        question::{DnsClass, DnsQuestion, QueryType},
+        record::DnsRecord,
+    },
+    layer::{Layer, Next, Query},
+};
+
+// ttl of answers from the lease file, short as leases come and go
+const TTL: u32 = 60;
+
+// hostname and leased address pairs
+type Hosts = Vec<(String, IpAddr)>;
+
+// answers for the hosts in a DHCP server's lease file, so a host that
+// leased an address as "printer" resolves as printer.<domain>, and its
+// address back to that name. dnsmasq and ISC dhcpd lease files are
+// understood, and the file is read again whenever it changes
+pub struct Leases {
+    pub path: PathBuf,
+    pub domain: String, // local domain the hosts are served under, e.g. lan
+    loaded: Mutex<(Option<SystemTime>, Hosts)>, // file mtime and the hosts in it
+}
+
+impl Leases {
+    pub fn new(path: PathBuf, domain: &str) -> Self {
+        Leases {
+            path,
+            domain: domain.trim_matches('.').to_ascii_lowercase(),
+            loaded: Mutex::new((None, Vec::new())),
+        }
+    }
+
+    // current hostname and address pairs, reloaded if the file changed
+    pub fn hosts(&self) -> Hosts {
+        // a query that panicked while holding the lock left it poisoned,
+        // the hosts are reloaded below if they were half written
+        let mut loaded = match self.loaded.lock() {
+            Ok(loaded) => loaded,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        let modified = fs::metadata(&self.path)
+            .and_then(|meta| meta.modified())
+            .ok();
+        if modified != loaded.0 {
+            let contents = fs::read_to_string(&self.path).unwrap_or_default();
+            *loaded = (modified, parse(&contents));
+        }
+
+        loaded.1.clone()
+    }
+
+    fn answer(&self, question: &DnsQuestion) -> Option<DnsPacket> {
+        if question.qclass != DnsClass::IN {
+            return None;
+        }
+
+        let mut packet = DnsPacket::new();
+        packet.header.qr = true;
+        packet.header.aa = true;
+        packet.add_question(question.clone());
+
+        let qname = question.qname.to_ascii_lowercase();
+        if let Some(host) = qname.strip_suffix(&self.domain) {
+            let host = host.strip_suffix('.')?;
+            let addrs: Vec<IpAddr> = self
+                .hosts()
+                .into_iter()
+                .filter(|(name, _)| name == host)
+                .map(|(_, addr)| addr)
+                .collect();
+
+            // the local domain is ours, names nobody leased don't exist
+            if addrs.is_empty() {
+                packet.header.rcode = ResultCode::NXDOMAIN;
+            }
+
+            for addr in addrs {
+                let domain = question.qname.clone();
+                match (addr, question.qtype) {
+                    (IpAddr::V4(addr), QueryType::A) => packet.add_answer(DnsRecord::A {
+                        domain,
+                        addr,
+                        class: DnsClass::IN,
+                        ttl: TTL,
+                    }),
+                    (IpAddr::V6(addr), QueryType::AAAA) => packet.add_answer(DnsRecord::AAAA {
+                        domain,
+                        addr,
+                        class: DnsClass::IN,
+                        ttl: TTL,
+                    }),
+                    _ => {}
+                }
+            }
+
+            return Some(packet);
+        }
+
+        // reverse lookups of leased addresses, anything else goes upstream
+        if question.qtype != QueryType::PTR {
+            return None;
+        }
+
+        let addr = reverse_addr(&qname)?;
+        let (host, _) = self
+            .hosts()
+            .into_iter()
+            .find(|(_, leased)| *leased == addr)?;
+        packet.add_answer(DnsRecord::PTR {
+            domain: question.qname.clone(),
+            host: format!("{}.{}", host, self.domain),
+            class: DnsClass::IN,
+            ttl: TTL,
+        });
+
+        Some(packet)
+    }
+}
+
+impl Layer for Leases {
+    fn handle(&self, query: &Query, next: Next) -> Result<DnsPacket, &'static str> {
+        match self.answer(query.question) {
+            Some(packet) => Ok(packet),
+            None => next.run(query),
+        }
+    }
+}
+
+// read the hostname and address pairs out of a lease file
+fn parse(contents: &str) -> Hosts {
+    let mut hosts = Hosts::new();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+
+    // ISC dhcpd appends a block per lease event, later ones win. a block
+    // only names a host while it's active and hasn't ended, a later one
+    // freeing the address takes the name away again
+    // lease 192.168.1.20 { ... ends 4 2024/01/04 22:00:00; ...
+    // binding state active; ... client-hostname "printer"; }
+    let mut lease: Option<IscLease> = None;
+    for line in contents.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("lease ") {
+            lease = rest
+                .trim_end_matches('{')
+                .trim()
+                .parse()
+                .ok()
+                .map(IscLease::new);
+            continue;
+        }
+
+        if let Some(block) = &mut lease {
+            if line == "}" {
+                hosts.retain(|(_, leased)| *leased != block.addr);
+                if let Some(name) = block.live_hostname(now) {
+                    hosts.push((name, block.addr));
+                }
+                lease = None;
+            } else {
+                block.read(line);
+            }
+            continue;
+        }
+
+        // dnsmasq: <expiry> <mac> <ip> <hostname> <client id>
+        // an expiry of 0 never expires, a hostname of * is unknown
+        if let [expiry, _, addr, name, ..] = line.split_whitespace().collect::<Vec<_>>()[..] {
+            let expiry: u64 = match expiry.parse() {
+                Ok(expiry) => expiry,
+                Err(_) => continue,
+            };
+
+            match addr.parse() {
+                Ok(addr) if name != "*" && (expiry == 0 || expiry > now) => {
+                    hosts.push((name.to_ascii_lowercase(), addr))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    hosts
+}
+
+// an ISC dhcpd lease block, as far as it has been read
+struct IscLease {
+    addr: IpAddr,
+    hostname: Option<String>,
+    ends: Option<u64>, // unix time, None for leases that never end
+    active: bool,      // binding state, dhcpd before 3.0 didn't write one
+}
+
+impl IscLease {
+    fn new(addr: IpAddr) -> Self {
+        IscLease {
+            addr,
+            hostname: None,
+            ends: None,
+            active: true,
+        }
+    }
+
+    // take in a statement of the block, e.g. binding state active;
+    fn read(&mut self, line: &str) {
+        // the epoch form carries a comment after the semicolon
+        let value = |prefix: &str| {
+            line.strip_prefix(prefix)
+                .map(|rest| rest.split(';').next().unwrap_or("").trim())
+        };
+
+        if let Some(name) = value("client-hostname ") {
+            self.hostname = Some(name.trim_matches('"').to_ascii_lowercase());
+        } else if let Some(state) = value("binding state ") {
+            self.active = state == "active";
+        } else if let Some(ends) = value("ends ") {
+            // a time that can't be read is taken as already past
+            self.ends = match ends {
+                "never" => None,
+                ends => Some(lease_time(ends).unwrap_or(0)),
+            };
+        }
+    }
+
+    // the hostname, if the lease still holds it at now
+    fn live_hostname(&self, now: u64) -> Option<String> {
+        let live = self.active && self.ends.is_none_or(|ends| ends > now);
+        self.hostname.clone().filter(|_| live)
+    }
+}
+
+// a lease time as unix time, written either as weekday and UTC date and
+// time, 4 2024/01/04 22:00:00, or with db-time-format local as epoch
+// 1704405600
+fn lease_time(time: &str) -> Option<u64> {
+    let fields: Vec<&str> = time.split_whitespace().collect();
+    let (date, clock) = match fields[..] {
+        ["epoch", secs] => return secs.parse().ok(),
+        [_, date, clock] => (date, clock),
+        _ => return None,
+    };
+
+    let parse = |text: &str, sep: char| -> Option<Vec<u64>> {
+        text.split(sep).map(|n| n.parse().ok()).collect()
+    };
+    let (date, clock) = (parse(date, '/')?, parse(clock, ':')?);
+    let (&[year, month, day], &[hour, minute, second]) = (&date[..], &clock[..]) else {
+        return None;
+    };
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // days since 1970-01-01 of the civil date, counting years from march
+    // so the leap day comes last
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let days = year * 365 + year / 4 - year / 100 + year / 400 + day_of_year - 719_468;
+
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+// the address a reverse name like 20.1.168.192.in-addr.arpa stands for
+fn reverse_addr(qname: &str) -> Option<IpAddr> {
+    if let Some(octets) = qname.strip_suffix(".in-addr.arpa") {
+        let mut octets: Vec<u8> = octets
+            .split('.')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .ok()?;
+        octets.reverse();
+
+        let octets: [u8; 4] = octets.try_into().ok()?;
+        return Some(IpAddr::V4(Ipv4Addr::from(octets)));
+    }
+
+    // one label per nibble, least significant first
+    let nibbles = qname.strip_suffix(".ip6.arpa")?;
+    let mut addr: u128 = 0;
+    let mut count = 0;
+    for nibble in nibbles.split('.').rev() {
+        let nibble = u8::from_str_radix(nibble, 16).ok()?;
+        if count == 32 || nibble > 0xF {
+            return None;
+        }
+        addr = addr << 4 | nibble as u128;
+        count += 1;
+    }
+
+    match count {
+        32 => Some(IpAddr::V6(Ipv6Addr::from(addr))),
+        _ => None,
+    }
+}
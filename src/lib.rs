@@ -1,14 +1,49 @@
-use std::net::{Ipv4Addr, UdpSocket};
+use std::{
+    fmt,
+    fmt::Display,
+    io::{ErrorKind, Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    panic::{self, AssertUnwindSafe},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, ScopedJoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
+use capture::Capture;
 use dns::{
-    packet::DnsPacket,
-    question::{DnsQuestion, QueryType},
+    packet::{DnsPacket, ResponseKind, OPTION_NSID, OPTION_TCP_KEEPALIVE},
+    question::{DnsClass, DnsQuestion, QueryType},
+    record::DnsRecord,
+    stream::StreamDecoder,
 };
+use layer::{Layer, Next, Query};
+use neighbor::Client;
 
-use crate::dns::header::ResultCode;
+use crate::dns::header::{DnsHeader, Opcode, ResultCode};
 
 pub mod buffer;
+pub mod capture;
+#[cfg(feature = "chaos")]
+pub mod chaos;
 pub mod dns;
+#[cfg(feature = "dnscrypt")]
+pub mod dnscrypt;
+pub mod doh;
+pub mod layer;
+pub mod leases;
+pub mod neighbor;
+pub mod network;
+#[cfg(feature = "pcap")]
+pub mod pcap;
+pub mod policy;
+pub mod pool;
+pub mod router;
+#[cfg(feature = "tls")]
+pub mod tls;
 
 pub fn lookup<S: Into<String>>(
     qname: S,
@@ -16,15 +51,83 @@ pub fn lookup<S: Into<String>>(
     server: (Ipv4Addr, u16),
     socket: &UdpSocket,
 ) -> Result<DnsPacket, &'static str> {
-    // query packet
-    let mut packet = DnsPacket::new();
-    packet.header.id = 1234;
-    packet.header.rd = true;
-    packet.add_question(DnsQuestion::new(qname.into(), qtype));
+    let (mut res_buf, _, _) = exchange_raw(qname.into(), qtype, server, 0, &[], socket)?;
 
-    // write our packet to a buffer
-    let mut req_buf = buffer::PacketBuffer::new();
-    packet.write(&mut req_buf)?;
+    // parse the response
+    DnsPacket::read(&mut res_buf)
+}
+
+// the targets of the SRV records among answers in RFC 2782 order, with the
+// draws by weight made by a xorshift seeded with seed
+pub fn srv_targets(answers: Vec<DnsRecord>, seed: u64) -> Vec<(String, u16)> {
+    let mut records: Vec<(u16, u16, String, u16)> = answers
+        .into_iter()
+        .filter_map(|answer| match answer {
+            DnsRecord::SRV {
+                priority,
+                weight,
+                port,
+                target,
+                ..
+            } => Some((priority, weight, target, port)),
+            _ => None,
+        })
+        // a target of "." means the service is not offered
+        .filter(|(_, _, target, _)| !target.is_empty())
+        .collect();
+    records.sort_by_key(|(priority, ..)| *priority);
+
+    // xorshift never leaves a state of 0
+    let mut state = seed | 1;
+    let mut random = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let mut targets = Vec::with_capacity(records.len());
+    for group in records.chunk_by(|a, b| a.0 == b.0) {
+        // pick targets with a chance proportional to their weight until
+        // the priority is used up. zero weights go first, so they are
+        // only picked on a draw of 0
+        let mut group = group.to_vec();
+        group.sort_by_key(|(_, weight, ..)| *weight != 0);
+        while !group.is_empty() {
+            let total: u64 = group.iter().map(|(_, weight, ..)| *weight as u64).sum();
+            let pick = random() % (total + 1);
+
+            let mut sum = 0;
+            let index = group
+                .iter()
+                .position(|(_, weight, ..)| {
+                    sum += *weight as u64;
+                    sum >= pick
+                })
+                .unwrap_or(0);
+
+            let (_, _, target, port) = group.remove(index);
+            targets.push((target, port));
+        }
+    }
+
+    targets
+}
+
+// send a query and receive the raw response, along with its length and source.
+// a non zero payload advertises that size with EDNS, along with options, and
+// accepts responses up to it, otherwise they are limited to 512 bytes
+fn exchange_raw(
+    qname: String,
+    qtype: QueryType,
+    server: (Ipv4Addr, u16),
+    payload: u16,
+    options: &[(u16, Vec<u8>)],
+    socket: &UdpSocket,
+) -> Result<(buffer::PacketBuffer, usize, SocketAddr), &'static str> {
+    // never advertise more than a buffer can hold
+    let payload = payload.min(buffer::PacketBuffer::MAX_LEN as u16);
+    let req_buf = query_buffer(qname, qtype, payload, options)?;
 
     // send our query packet
     socket
@@ -32,125 +135,1273 @@ pub fn lookup<S: Into<String>>(
         .map_err(|_| "failed to send")?;
 
     // receive the response
-    let mut res_buf = buffer::PacketBuffer::new();
-    socket
-        .recv_from(&mut res_buf.buf)
+    let mut res_buf = buffer::PacketBuffer::with_len((payload as usize).max(512));
+    let (len, src) = socket
+        .recv_from(res_buf.as_mut_slice())
         .map_err(|_| "failed to recv")?;
 
-    // parse the response
-    DnsPacket::read(&mut res_buf)
+    // like responses over tcp the buffer ends where the response does, a
+    // response claiming more than it carries then fails to parse rather
+    // than reading on into zeros
+    res_buf.buf.truncate(len);
+
+    Ok((res_buf, len, src))
 }
 
-pub fn recursive_lookup<S: AsRef<str>>(
-    qname: S,
+// write a recursion desired query, with an EDNS record carrying options if
+// payload isn't 0
+fn query_buffer(
+    qname: String,
     qtype: QueryType,
-    ns: Ipv4Addr,
-    socket: &UdpSocket,
-) -> Result<DnsPacket, &'static str> {
-    let qname = qname.as_ref();
-    let mut ns = ns;
-    loop {
-        println!("Looking up {} {:?} from {}", qname, qtype, ns);
+    payload: u16,
+    options: &[(u16, Vec<u8>)],
+) -> Result<buffer::PacketBuffer, &'static str> {
+    let mut packet = DnsPacket::new();
+    packet.header.id = 1234;
+    packet.header.rd = true;
+    packet.add_question(DnsQuestion::new(qname, qtype));
+    if payload > 0 {
+        packet.add_edns(payload);
+        for (code, value) in options {
+            packet.add_edns_option(*code, value);
+        }
+    }
+
+    let mut req_buf = buffer::PacketBuffer::new();
+    packet.write(&mut req_buf)?;
+    Ok(req_buf)
+}
+
+// whether a read failed because the socket's read timeout passed, which
+// shows up as either kind depending on the platform
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+// write a message to a stream, prefixed with its length as a u16
+fn write_message<W: Write>(stream: &mut W, message: &[u8]) -> Result<(), &'static str> {
+    stream
+        .write_all(&(message.len() as u16).to_be_bytes())
+        .and_then(|_| stream.write_all(message))
+        .map_err(|_| "failed to write")
+}
+
+// an NSID as text when it is printable, as most are, otherwise in hex
+fn nsid_text(nsid: &[u8]) -> String {
+    if !nsid.is_empty()
+        && nsid
+            .iter()
+            .all(|byte| byte.is_ascii_graphic() || *byte == b' ')
+    {
+        return String::from_utf8_lossy(nsid).into_owned();
+    }
+    nsid.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// read the header of a raw message, leaving the buffer where it was
+fn peek_header(buf: &mut buffer::PacketBuffer) -> Option<DnsHeader> {
+    let pos = buf.pos();
+    let header = DnsHeader::read(buf).ok();
+    buf.seek(pos);
+    header
+}
+
+// transport a query arrived on
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Transport {
+    Udp,   // the udp listener
+    Tcp,   // the tcp listener, messages are length prefixed
+    Unix,  // a length prefixed unix stream socket
+    Tls,   // dns over tls, length prefixed like tcp
+    Https, // dns over https, one query per http request
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Transport::Udp => "udp",
+            Transport::Tcp => "tcp",
+            Transport::Unix => "unix",
+            Transport::Tls => "tls",
+            Transport::Https => "https",
+        })
+    }
+}
 
-        let server = (ns, 53);
-        let response = lookup(qname, qtype, server, socket)?;
+// the names the transports are displayed with
+impl FromStr for Transport {
+    type Err = &'static str;
 
-        // check if we have any answers and no errors
-        if response.header.rcode == ResultCode::NOERROR && !response.answers.is_empty() {
-            return Ok(response);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "udp" => Ok(Transport::Udp),
+            "tcp" => Ok(Transport::Tcp),
+            "unix" => Ok(Transport::Unix),
+            "tls" => Ok(Transport::Tls),
+            "https" => Ok(Transport::Https),
+            _ => Err("unknown transport"),
         }
+    }
+}
+
+// queries received on each transport
+#[derive(Debug, Default)]
+pub struct IngressCounts {
+    pub udp: AtomicU64,
+    pub tcp: AtomicU64,
+    pub unix: AtomicU64,
+    pub tls: AtomicU64,
+    pub https: AtomicU64,
+}
 
-        // check for NXDOMAIN (Name Error)
-        if response.header.rcode == ResultCode::NXDOMAIN {
-            return Ok(response);
+impl IngressCounts {
+    pub fn get(&self, transport: Transport) -> u64 {
+        self.counter(transport).load(Ordering::Relaxed)
+    }
+
+    fn counter(&self, transport: Transport) -> &AtomicU64 {
+        match transport {
+            Transport::Udp => &self.udp,
+            Transport::Tcp => &self.tcp,
+            Transport::Unix => &self.unix,
+            Transport::Tls => &self.tls,
+            Transport::Https => &self.https,
         }
+    }
+
+    fn increment(&self, transport: Transport) {
+        self.counter(transport).fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// responses that arrived after the one accepted for a query, while
+// Resolver::spoof_window kept listening for them
+#[derive(Debug, Default)]
+pub struct SpoofCounts {
+    pub duplicates: AtomicU64, // copies of the accepted response, usually harmless
+    pub mismatched_ids: AtomicU64, // for another query id, blind spoofing guesses ids
+    pub mismatched_data: AtomicU64, // same id but different contents, one of the two is forged
+    pub wrong_source: AtomicU64, // from an address the query wasn't sent to
+}
 
-        // find the next nameserver to query
-        if let Some(nsaddr) = response.get_resolved_ns(qname) {
-            ns = nsaddr;
-            continue;
+// connections to the dns over tls listener. with ingress' count of queries
+// over tls, this tells how many queries clients send per connection, and
+// how many handshakes session tickets save
+#[derive(Debug, Default)]
+pub struct DotCounts {
+    pub connections: AtomicU64, // completed handshakes
+    pub resumed: AtomicU64,     // of which resumed a session from a ticket
+}
+
+// resolver state shared by every query the server handles
+// all methods take &self and mutable state sits behind locks, so one
+// Resolver can serve many threads, e.g. through shared(). each thread
+// needs its own query socket though, responses are read off whichever
+// socket the query went out on
+pub struct Resolver {
+    pub root: Ipv4Addr,                 // root server recursion starts from
+    pub ns_port: u16,                   // port nameservers are queried on, 53 but in tests
+    pub loopback_ns: bool,              // follow referrals to loopback, only for local test zones
+    pub capture: Option<Capture>,       // where packets that fail to parse are dumped
+    pub listen_addrs: Vec<SocketAddr>,  // addresses we serve on, never queried
+    pub paranoid: bool,                 // only accept answers a second nameserver agrees with
+    pub search: Vec<String>,            // search domains tried by lookup_ip
+    pub ndots: usize,                   // dots needed for lookup_ip to try a name as is first
+    pub resolve_targets: bool,          // resolve MX/NS targets missing from the additionals
+    pub ingress: IngressCounts,         // queries received per transport
+    pub layers: Vec<Box<dyn Layer>>,    // stages each client query passes before resolution
+    pub identify_clients: bool,         // look up the MAC address of udp clients on the LAN
+    pub panics: AtomicU64,              // queries whose handling panicked
+    pub edns_payload: u16,              // udp response size advertised upstream, 0 to not use EDNS
+    pub tcp: pool::TcpPool,             // upstream connections truncated responses are retried on
+    pub spoof_window: Option<Duration>, // keep listening this long after a response for spoofed ones
+    pub spoofs: SpoofCounts,            // what was heard in that window
+    pub dot: DotCounts,                 // dns over tls connections, and how many were resumed
+    pub request_nsid: bool,             // ask upstreams to identify themselves, and log it
+    pub nsid: Option<String>,           // identity sent to clients that ask for it with NSID
+    pub tcp_idle_timeout: Duration,     // how long tcp and tls clients may leave connections idle
+    pub padding_block: usize, // tls and https responses are padded to multiples of it, 0 not
+    pub network: network::Watch, // default route, upstream state is dropped when it changes
+
+    #[cfg(feature = "chaos")]
+    pub chaos: Option<chaos::Chaos>, // faults injected into upstream responses
+}
+
+// listeners on other threads borrow the same Resolver, keep it that way
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Resolver>();
+};
+
+impl Resolver {
+    pub fn new(root: Ipv4Addr) -> Self {
+        Resolver {
+            root,
+            ns_port: 53,
+            loopback_ns: false,
+            capture: None,
+            listen_addrs: Vec::new(),
+            paranoid: false,
+            search: Vec::new(),
+            ndots: 1,
+            resolve_targets: false,
+            ingress: IngressCounts::default(),
+            layers: Vec::new(),
+            identify_clients: false,
+            panics: AtomicU64::new(0),
+            edns_payload: 1232,
+            tcp: pool::TcpPool::new(),
+            spoof_window: None,
+            spoofs: SpoofCounts::default(),
+            dot: DotCounts::default(),
+            request_nsid: false,
+            nsid: None,
+            tcp_idle_timeout: Duration::from_secs(10),
+            padding_block: 468,
+            network: network::Watch::new(),
+
+            #[cfg(feature = "chaos")]
+            chaos: None,
         }
+    }
+
+    // wrap a configured resolver for handing out to other threads or tasks
+    pub fn shared(self) -> Arc<Self> {
+        Arc::new(self)
+    }
 
-        // check if we have any errors
-        let new_ns = match response.get_unresolved_ns(qname) {
-            Some(nsname) => nsname,
-            None => return Ok(response),
+    // resolve a name from the root servers down, answering address
+    // literals directly like getaddrinfo does
+    pub fn resolve(
+        &self,
+        qname: &str,
+        qtype: QueryType,
+        socket: &UdpSocket,
+    ) -> Result<DnsPacket, &'static str> {
+        let addr = match qname.parse::<IpAddr>() {
+            Ok(addr) => addr,
+            Err(_) => {
+                let mut response = self.recursive_lookup(qname, qtype, self.root, socket)?;
+                if self.resolve_targets {
+                    self.add_target_addresses(&mut response, socket);
+                }
+                return Ok(response);
+            }
         };
 
-        // recurse to find the next nameserver
-        let recursive_response = recursive_lookup(new_ns, QueryType::A, ns, socket)?;
+        // synthesize the answer, the literal is its own address
+        let mut packet = DnsPacket::new();
+        packet.header.qr = true;
+        packet.header.ra = true;
+        packet.add_question(DnsQuestion::new(qname.to_string(), qtype));
+
+        let domain = qname.to_string();
+        match (addr, qtype) {
+            (IpAddr::V4(addr), QueryType::A) => packet.add_answer(DnsRecord::A {
+                domain,
+                addr,
+                class: DnsClass::IN,
+                ttl: 0,
+            }),
+            (IpAddr::V6(addr), QueryType::AAAA) => packet.add_answer(DnsRecord::AAAA {
+                domain,
+                addr,
+                class: DnsClass::IN,
+                ttl: 0,
+            }),
+            // any other type exists but has no data
+            _ => {}
+        }
+
+        Ok(packet)
+    }
+
+    // resolve the addresses of MX/NS targets the response has no glue for
+    // and add them to its additionals, failures are left out
+    fn add_target_addresses(&self, response: &mut DnsPacket, socket: &UdpSocket) {
+        let mut targets: Vec<String> = response
+            .answers
+            .iter()
+            .filter_map(|answer| answer.target())
+            .filter(|target| {
+                !response
+                    .additionals
+                    .iter()
+                    .any(|additional| additional.is_address_of(target))
+            })
+            .map(String::from)
+            .collect();
+        targets.sort();
+        targets.dedup();
+
+        let mut known = Vec::new();
+        for target in targets {
+            for qtype in [QueryType::A, QueryType::AAAA] {
+                if let Ok(result) = self.recursive_lookup(&target, qtype, self.root, socket) {
+                    known.extend(result.answers);
+                }
+            }
+        }
+
+        response.add_target_glue(&known);
+    }
+
+    // get every ipv4 and ipv6 address of a host, trying the search domains
+    // like a stub resolver would
+    pub fn lookup_ip(&self, host: &str, socket: &UdpSocket) -> Result<Vec<IpAddr>, &'static str> {
+        // literals never go through the search list
+        if let Ok(addr) = host.parse::<IpAddr>() {
+            return Ok(vec![addr]);
+        }
+
+        for qname in self.search_names(host) {
+            let addrs = self.lookup_ip_exact(&qname, socket)?;
+            if !addrs.is_empty() {
+                return Ok(addrs);
+            }
+        }
+
+        Ok(Vec::new())
+    }
+
+    // the names tried for a host, in order
+    // names with at least ndots dots are tried as is before the search
+    // domains, shorter ones after, and names ending in a dot never search
+    pub fn search_names(&self, host: &str) -> Vec<String> {
+        if let Some(absolute) = host.strip_suffix('.') {
+            return vec![absolute.to_string()];
+        }
+
+        let mut names: Vec<String> = self
+            .search
+            .iter()
+            .map(|domain| format!("{}.{}", host, domain.trim_matches('.')))
+            .collect();
 
-        // check if we have any answers
-        if let Some(nsaddr) = recursive_response.get_any_a() {
-            ns = nsaddr;
+        if host.matches('.').count() >= self.ndots {
+            names.insert(0, host.to_string());
         } else {
-            return Ok(recursive_response);
+            names.push(host.to_string());
         }
+
+        names
     }
-}
 
-pub fn handle_query(
-    ns: Ipv4Addr,
-    listen_socket: &UdpSocket,
-    query_socket: &UdpSocket,
-) -> Result<(), &'static str> {
-    // receive a query packet
-    let mut req_buffer = buffer::PacketBuffer::new();
-    let (_, src) = listen_socket
-        .recv_from(&mut req_buffer.buf)
-        .map_err(|_| "failed to recv")?;
+    // get every ipv4 and ipv6 address of exactly this name, following cnames
+    fn lookup_ip_exact(&self, host: &str, socket: &UdpSocket) -> Result<Vec<IpAddr>, &'static str> {
+        // guards against cname loops
+        const MAX_CNAMES: usize = 8;
+
+        let mut addrs = Vec::new();
+        for qtype in [QueryType::A, QueryType::AAAA] {
+            let mut qname = host.to_string();
+            let found = addrs.len();
+            for _ in 0..=MAX_CNAMES {
+                let response = self.resolve(&qname, qtype, socket)?;
+
+                let mut cname = None;
+                for answer in response.answers {
+                    match answer {
+                        DnsRecord::A { addr, .. } => addrs.push(IpAddr::V4(addr)),
+                        DnsRecord::AAAA { addr, .. } => addrs.push(IpAddr::V6(addr)),
+                        DnsRecord::CNAME { cname: target, .. } => cname = Some(target),
+                        _ => {}
+                    }
+                }
+
+                // only chase the alias when the server didn't do it for us
+                match cname {
+                    Some(target) if addrs.len() == found => qname = target,
+                    _ => break,
+                }
+            }
+        }
+
+        Ok(addrs)
+    }
+
+    // get the targets of a service (e.g. _imaps._tcp.gmail.com) as host and
+    // port pairs, in the order clients should try them: by priority, and
+    // shuffled by weight within a priority (RFC 2782)
+    pub fn lookup_srv(
+        &self,
+        service: &str,
+        socket: &UdpSocket,
+    ) -> Result<Vec<(String, u16)>, &'static str> {
+        let response = self.resolve(service, QueryType::SRV, socket)?;
+
+        // xorshift seeded from the clock, only used to spread the load
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |elapsed| elapsed.as_nanos() as u64);
+        Ok(srv_targets(response.answers, seed))
+    }
+
+    // send a single query upstream, every step of a recursion goes through here
+    fn exchange(
+        &self,
+        qname: &str,
+        qtype: QueryType,
+        server: (Ipv4Addr, u16),
+        socket: &UdpSocket,
+    ) -> Result<DnsPacket, &'static str> {
+        let options = self.edns_options();
+        let mut payload = self.edns_payload;
+        let (mut res_buf, mut len, mut src) =
+            exchange_raw(qname.to_string(), qtype, server, payload, &options, socket)?;
+
+        // faults are injected into what came over udp, so the checks below
+        // handle them like they would real ones, truncation included
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &self.chaos {
+            chaos.inject(&mut res_buf, len)?;
+        }
+
+        // servers that predate EDNS answer queries with an OPT record with
+        // FORMERR, ask them again without it (RFC 6891 7)
+        let header = peek_header(&mut res_buf);
+        if payload > 0 && header.is_some_and(|h| h.rcode == ResultCode::FORMERR) {
+            payload = 0;
+            (res_buf, len, src) = exchange_raw(qname.to_string(), qtype, server, 0, &[], socket)?;
+        }
+
+        if let Some(window) = self.spoof_window {
+            self.watch_for_spoofs(&res_buf.buf[..len], server.into(), window, socket);
+        }
+
+        // a truncated response may be missing records we need, like the
+        // glue of a referral, so the whole of it is fetched over tcp
+        if peek_header(&mut res_buf).is_some_and(|h| h.tc) {
+            // and we'd like to keep the connection for the next one
+            let mut options = options.clone();
+            options.push((OPTION_TCP_KEEPALIVE, Vec::new()));
+            let req_buf = query_buffer(qname.to_string(), qtype, payload, &options)?;
+            src = SocketAddr::from(server);
+            (res_buf, len) = self.tcp.exchange(req_buf.as_slice(), src)?;
+        }
+
+        // parse the response
+        let response = DnsPacket::read(&mut res_buf);
+        if let (Err(e), Some(capture)) = (&response, &self.capture) {
+            capture.record(src, &res_buf.buf[..len], e);
+        }
+
+        // servers only identify themselves when asked to with request_nsid
+        if let Some(nsid) = response
+            .as_ref()
+            .ok()
+            .and_then(|r| r.edns_option(OPTION_NSID))
+        {
+            println!("Nameserver {} identifies as {}", server.0, nsid_text(&nsid));
+        }
+
+        response
+    }
+
+    // the EDNS options sent with every query upstream
+    fn edns_options(&self) -> Vec<(u16, Vec<u8>)> {
+        let mut options = Vec::new();
+        if self.request_nsid {
+            // asked for with an empty value (RFC 5001 2.1)
+            options.push((OPTION_NSID, Vec::new()));
+        }
+        options
+    }
 
-    // parse the query packet
-    let mut req_packet = DnsPacket::read(&mut req_buffer)?;
+    // listen on socket for window after accepting response from server, and
+    // count whatever else arrives. a forged response racing the real one
+    // shows up as a second answer to the same question (RFC 5452 9.1)
+    fn watch_for_spoofs(
+        &self,
+        response: &[u8],
+        server: SocketAddr,
+        window: Duration,
+        socket: &UdpSocket,
+    ) {
+        let timeout = match socket.read_timeout() {
+            Ok(timeout) => timeout,
+            Err(_) => return,
+        };
+
+        let deadline = Instant::now() + window;
+        let mut buf = [0; buffer::PacketBuffer::MAX_LEN];
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() || socket.set_read_timeout(Some(left)).is_err() {
+                break;
+            }
+
+            let (len, src) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            let late = &buf[..len];
+
+            let (counter, kind) = if src != server {
+                (&self.spoofs.wrong_source, "from the wrong source")
+            } else if late.get(..2) != response.get(..2) {
+                (&self.spoofs.mismatched_ids, "with a mismatched id")
+            } else if late != response {
+                (&self.spoofs.mismatched_data, "with mismatched data")
+            } else {
+                self.spoofs.duplicates.fetch_add(1, Ordering::Relaxed);
+                continue;
+            };
+
+            counter.fetch_add(1, Ordering::Relaxed);
+            eprintln!(
+                "Possible spoofing: late response {} from {} after querying {}",
+                kind, src, server
+            );
+        }
+
+        let _ = socket.set_read_timeout(timeout);
+    }
 
-    // check if we have any questions
-    if req_packet.questions.is_empty() {
-        return Err("received packet with no questions");
+    pub fn recursive_lookup<S: AsRef<str>>(
+        &self,
+        qname: S,
+        qtype: QueryType,
+        ns: Ipv4Addr,
+        socket: &UdpSocket,
+    ) -> Result<DnsPacket, &'static str> {
+        self.lookup_from(qname.as_ref(), qtype, ns, self.paranoid, socket)
     }
 
-    // create a response packet
-    let mut res_packet = DnsPacket::new();
-    res_packet.header.id = req_packet.header.id; // copy the request id
-    res_packet.header.rd = true; // set recursion desired
-    res_packet.header.ra = true; // set recursion available
-    res_packet.header.qr = true; // set response flag
+    // recursive_lookup, verifying final responses with a second nameserver
+    // when verify is set
+    fn lookup_from(
+        &self,
+        qname: &str,
+        qtype: QueryType,
+        ns: Ipv4Addr,
+        verify: bool,
+        socket: &UdpSocket,
+    ) -> Result<DnsPacket, &'static str> {
+        let mut ns = ns;
+
+        // the other nameservers of the zone ns serves, used to verify
+        // answers: the addresses known for them, and the names of those
+        // that came without glue
+        let mut alternates = Vec::new();
+        let mut unresolved = Vec::new();
+        loop {
+            println!("Looking up {} {} from {}", qname, qtype, ns);
+
+            let server = (ns, self.ns_port);
+
+            // a referral or a misconfigured root pointing back at us would
+            // have us answer our own queries forever
+            if self.is_self(server.into()) {
+                eprintln!(
+                    "Refusing to query {}:{}, it is one of our listen addresses",
+                    ns, server.1
+                );
+                return Err("resolution loops back to this server");
+            }
+
+            let mut response = self.exchange(qname, qtype, server, socket)?;
+
+            match response.classify() {
+                // final responses for the question
+                ResponseKind::Answer | ResponseKind::NoData | ResponseKind::NxDomain => {
+                    if verify {
+                        let others = (&alternates[..], &unresolved[..]);
+                        self.verify(&response, qname, qtype, others, socket)?;
+                    }
+                    response.synthesize_dname(qname);
+                    return Ok(response);
+                }
+                // nothing to follow, let the caller decide what to do with it
+                ResponseKind::Lame | ResponseKind::Error => return Ok(response),
+                ResponseKind::Referral => {}
+            }
+
+            // nameservers of the referral without glue, whichever one is
+            // followed the rest can still verify the answer
+            unresolved = response
+                .iter_ns(qname)
+                .map(|(_, host)| host)
+                .filter(|host| {
+                    !response
+                        .additionals
+                        .iter()
+                        .any(|additional| additional.is_address_of(host))
+                })
+                .map(String::from)
+                .collect();
+
+            // find the next nameserver to query
+            let candidates: Vec<Ipv4Addr> = response
+                .iter_resolved_ns(qname)
+                .filter(|addr| self.is_valid_ns(*addr))
+                .collect();
+            if let Some((&nsaddr, rest)) = candidates.split_first() {
+                ns = nsaddr;
+                alternates = rest
+                    .iter()
+                    .copied()
+                    .filter(|addr| *addr != nsaddr)
+                    .collect();
+                continue;
+            }
 
-    // check question
-    if let Some(question) = req_packet.questions.pop() {
-        println!("Received query for {} {:?}", question.qname, question.qtype);
+            // no glue, so the nameserver has to be resolved first
+            let new_ns = match unresolved.first().map(String::as_str) {
+                Some(nsname) => nsname.to_string(),
+                None => match response.get_unresolved_ns(qname) {
+                    Some(nsname) => nsname.to_string(),
+                    None => return Ok(response),
+                },
+            };
+            unresolved.retain(|host| *host != new_ns);
 
-        if let Ok(result) = recursive_lookup(&question.qname, question.qtype, ns, query_socket) {
-            res_packet.add_question(question);
-            res_packet.header.rcode = result.header.rcode;
+            // recurse to find the next nameserver
+            let recursive_response = self.lookup_from(&new_ns, QueryType::A, ns, verify, socket)?;
 
-            for answer in result.answers {
-                res_packet.add_answer(answer);
+            // check if we have any answers
+            let candidates: Vec<Ipv4Addr> = recursive_response
+                .iter_a()
+                .filter(|addr| self.is_valid_ns(*addr))
+                .collect();
+            match candidates.split_first() {
+                Some((&nsaddr, rest)) => {
+                    ns = nsaddr;
+                    alternates = rest.to_vec();
+                }
+                None => return Ok(recursive_response),
             }
+        }
+    }
+
+    // ask a second nameserver the same question, and fail unless it gives
+    // the same kind of response with the same answers. others are the
+    // addresses and unresolved names of the zone's other nameservers, one
+    // name is resolved when no address is left. an answer no second
+    // nameserver can be found for fails too, an attacker on the path could
+    // otherwise just make that lookup fail
+    fn verify(
+        &self,
+        response: &DnsPacket,
+        qname: &str,
+        qtype: QueryType,
+        others: (&[Ipv4Addr], &[String]),
+        socket: &UdpSocket,
+    ) -> Result<(), &'static str> {
+        let (alternates, unresolved) = others;
+        let usable = |addr: &Ipv4Addr| !self.is_self((*addr, self.ns_port).into());
+
+        let other = match alternates.iter().copied().find(usable) {
+            Some(other) => Some(other),
+            None => unresolved
+                .first()
+                .and_then(|host| {
+                    self.lookup_from(host, QueryType::A, self.root, false, socket)
+                        .ok()
+                })
+                .and_then(|found| {
+                    found
+                        .iter_a()
+                        .find(|addr| self.is_valid_ns(*addr) && usable(addr))
+                }),
+        };
 
-            for authority in result.authorities {
-                res_packet.add_authority(authority);
+        let other = match other {
+            Some(other) => other,
+            None => {
+                eprintln!("No second nameserver to verify {} {} with", qname, qtype);
+                return Err("no second nameserver to verify with");
             }
+        };
+
+        println!("Verifying {} {} with {}", qname, qtype, other);
+        let second = self.exchange(qname, qtype, (other, self.ns_port), socket)?;
+
+        let agree = second.classify() == response.classify()
+            && second.answers.len() == response.answers.len()
+            && response
+                .answers
+                .iter()
+                .all(|answer| second.answers.iter().any(|other| answer.same_data(other)));
+
+        if !agree {
+            eprintln!("Nameservers disagree on {} {}", qname, qtype);
+            return Err("nameservers disagree on the answer");
+        }
+
+        Ok(())
+    }
+
+    // referrals can point anywhere, so refuse addresses that would make us
+    // query ourselves or hosts that are never real nameservers
+    pub fn is_valid_ns(&self, addr: Ipv4Addr) -> bool {
+        let bogus = addr.octets()[0] == 0 // this network, 0.0.0.0/8
+            || (addr.is_loopback() && !self.loopback_ns)
+            || addr.is_link_local() // includes cloud metadata services
+            || addr.is_multicast()
+            || addr.is_broadcast()
+            || addr.octets()[0] >= 240; // reserved, 240.0.0.0/4
 
-            for additional in result.additionals {
-                res_packet.add_additional(additional);
+        if bogus {
+            println!("Ignoring bogus nameserver address {}", addr);
+        }
+
+        !bogus
+    }
+
+    // check whether sending to addr would reach one of our own listeners
+    pub fn is_self(&self, addr: SocketAddr) -> bool {
+        self.listen_addrs.iter().any(|listen| {
+            if listen.port() != addr.port() {
+                return false;
             }
+
+            // a wildcard listener answers on every local address, of which
+            // loopback and the unspecified address are the ones we know
+            listen.ip() == addr.ip()
+                || (listen.ip().is_unspecified()
+                    && (addr.ip().is_loopback() || addr.ip().is_unspecified()))
+        })
+    }
+
+    pub fn handle_query(
+        &self,
+        listen_socket: &UdpSocket,
+        query_socket: &UdpSocket,
+    ) -> Result<(), &'static str> {
+        // receive a query packet
+        let mut req_buffer = buffer::PacketBuffer::new();
+        let (len, src) = listen_socket
+            .recv_from(req_buffer.as_mut_slice())
+            .map_err(|_| "failed to recv")?;
+
+        let client = if self.identify_clients {
+            Client::identify(src)
         } else {
-            res_packet.header.rcode = ResultCode::SERVFAIL;
+            Client {
+                addr: src,
+                mac: None,
+            }
+        };
+        let res_buffer = self.answer(
+            &mut req_buffer,
+            len,
+            &client,
+            Some(src.ip()),
+            Transport::Udp,
+            query_socket,
+        )?;
+
+        // send our response packet
+        listen_socket
+            .send_to(res_buffer.as_slice(), src)
+            .map_err(|_| "failed to send")?;
+
+        Ok(())
+    }
+
+    // serve length prefixed queries from a stream (e.g. a tcp connection or
+    // a unix socket) until the peer closes it, or leaves it idle past the
+    // stream's read timeout. addr is the peer's address, None on sockets
+    // without one
+    pub fn handle_stream<S: Read + Write, A: Display>(
+        &self,
+        stream: &mut S,
+        src: A,
+        addr: Option<IpAddr>,
+        transport: Transport,
+        query_socket: &UdpSocket,
+    ) -> Result<(), &'static str> {
+        let mut decoder = StreamDecoder::new();
+        let mut chunk = [0; 4096];
+        loop {
+            // answer every message that has fully arrived
+            while let Some((mut req_buffer, len)) = decoder.next_message()? {
+                let res_buffer =
+                    self.answer(&mut req_buffer, len, &src, addr, transport, query_socket)?;
+                write_message(stream, res_buffer.as_slice())?;
+            }
+
+            let read = match stream.read(&mut chunk) {
+                Ok(read) => read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if is_timeout(&e) && decoder.is_empty() => return Ok(()),
+                // tls peers that hang up without a close_notify
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof && decoder.is_empty() => {
+                    return Ok(())
+                }
+                Err(_) => return Err("failed to read"),
+            };
+
+            // the peer may only close the stream between messages
+            if read == 0 {
+                if decoder.is_empty() {
+                    return Ok(());
+                }
+                return Err("failed to read");
+            }
+
+            decoder.feed(&chunk[..read]);
         }
-    } else {
-        res_packet.header.rcode = ResultCode::FORMERR;
     }
 
-    // write our response packet to a buffer
-    let mut res_buffer = buffer::PacketBuffer::new();
-    res_packet.write(&mut res_buffer)?;
+    // serve dns over https requests (RFC 8484) from a stream until the peer
+    // closes it, asks to close it, or leaves it idle past the stream's read
+    // timeout. requests on one connection are answered in order
+    pub fn handle_https<S: Read + Write, A: Display>(
+        &self,
+        stream: &mut S,
+        src: A,
+        addr: Option<IpAddr>,
+        query_socket: &UdpSocket,
+    ) -> Result<(), &'static str> {
+        let mut decoder = doh::RequestDecoder::new();
+        let mut chunk = [0; 4096];
+        loop {
+            loop {
+                let request = match decoder.next_request() {
+                    Ok(Some(request)) => request,
+                    Ok(None) => break,
+                    Err(e) => {
+                        let _ = doh::write_response(stream, 400, &[], None, false);
+                        return Err(e);
+                    }
+                };
+                let keep_alive = request.keep_alive();
 
-    // send our response packet
-    listen_socket
-        .send_to(res_buffer.as_slice(), src)
-        .map_err(|_| "failed to send")?;
+                let query = match request.dns_query() {
+                    Ok(query) => query,
+                    Err(status) => {
+                        doh::write_response(stream, status, &[], None, keep_alive)?;
+                        if !keep_alive {
+                            return Ok(());
+                        }
+                        continue;
+                    }
+                };
+
+                let mut req_buffer = buffer::PacketBuffer::new();
+                if query.len() > req_buffer.capacity() {
+                    doh::write_response(stream, 413, &[], None, keep_alive)?;
+                    if !keep_alive {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                req_buffer.buf[..query.len()].copy_from_slice(&query);
+
+                let mut res_buffer = match self.answer(
+                    &mut req_buffer,
+                    query.len(),
+                    &src,
+                    addr,
+                    Transport::Https,
+                    query_socket,
+                ) {
+                    Ok(res_buffer) => res_buffer,
+                    Err(e) => {
+                        let _ = doh::write_response(stream, 400, &[], None, false);
+                        return Err(e);
+                    }
+                };
+
+                // caches may keep the response as long as the shortest ttl
+                // in it, the whole response written from the start
+                let len = res_buffer.pos();
+                res_buffer.seek(0);
+                let max_age = DnsPacket::read(&mut res_buffer).ok().and_then(|packet| {
+                    packet
+                        .answers
+                        .iter()
+                        .chain(&packet.authorities)
+                        .map(DnsRecord::ttl)
+                        .min()
+                });
+                res_buffer.seek(len);
+
+                doh::write_response(stream, 200, res_buffer.as_slice(), max_age, keep_alive)?;
+                if !keep_alive {
+                    return Ok(());
+                }
+            }
+
+            let read = match stream.read(&mut chunk) {
+                Ok(read) => read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if is_timeout(&e) && decoder.is_empty() => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof && decoder.is_empty() => {
+                    return Ok(())
+                }
+                Err(_) => return Err("failed to read"),
+            };
+
+            // the peer may only close the stream between requests
+            if read == 0 {
+                if decoder.is_empty() {
+                    return Ok(());
+                }
+                return Err("failed to read");
+            }
+
+            decoder.feed(&chunk[..read]);
+        }
+    }
+
+    // like handle_stream, but answers up to MAX_IN_FLIGHT queries at once
+    // and writes each response as soon as it is ready, so a slow resolution
+    // doesn't hold up the queries pipelined behind it. clients match the
+    // responses to their queries by id (RFC 7766 6.2.1.1)
+    pub fn handle_stream_concurrent<R, W, A>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        src: A,
+        addr: Option<IpAddr>,
+        transport: Transport,
+    ) -> Result<(), &'static str>
+    where
+        R: Read,
+        W: Write + Send,
+        A: Display + Sync,
+    {
+        const MAX_IN_FLIGHT: usize = 16;
 
-    Ok(())
+        let writer = Mutex::new(writer);
+        // the first query that failed, which ends the stream like it would
+        // in handle_stream
+        let failed = Mutex::new(None);
+
+        let result = thread::scope(|scope| {
+            let mut decoder = StreamDecoder::new();
+            let mut chunk = [0; 4096];
+            let mut in_flight: Vec<ScopedJoinHandle<()>> = Vec::new();
+            loop {
+                if let Some(e) = *failed.lock().unwrap_or_else(|e| e.into_inner()) {
+                    return Err(e);
+                }
+
+                while let Some((mut req_buffer, len)) = decoder.next_message()? {
+                    in_flight.retain(|query| !query.is_finished());
+                    if in_flight.len() >= MAX_IN_FLIGHT {
+                        // answer panics are caught, nothing to get from join
+                        let _ = in_flight.remove(0).join();
+                    }
+
+                    // each query reads its upstream responses off its own socket
+                    let (writer, failed, src) = (&writer, &failed, &src);
+                    in_flight.push(scope.spawn(move || {
+                        let result = UdpSocket::bind(("0.0.0.0", 0))
+                            .map_err(|_| "failed to bind")
+                            .and_then(|query_socket| {
+                                self.answer(
+                                    &mut req_buffer,
+                                    len,
+                                    src,
+                                    addr,
+                                    transport,
+                                    &query_socket,
+                                )
+                            })
+                            .and_then(|res_buffer| {
+                                let mut writer = writer.lock().unwrap_or_else(|e| e.into_inner());
+                                write_message(&mut **writer, res_buffer.as_slice())
+                            });
+
+                        if let Err(e) = result {
+                            let mut failed = failed.lock().unwrap_or_else(|e| e.into_inner());
+                            failed.get_or_insert(e);
+                        }
+                    }));
+                }
+
+                let read = match reader.read(&mut chunk) {
+                    Ok(read) => read,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) if is_timeout(&e) && decoder.is_empty() => return Ok(()),
+                    Err(e) if e.kind() == ErrorKind::UnexpectedEof && decoder.is_empty() => {
+                        return Ok(())
+                    }
+                    Err(_) => return Err("failed to read"),
+                };
+
+                if read == 0 {
+                    if decoder.is_empty() {
+                        return Ok(());
+                    }
+                    return Err("failed to read");
+                }
+
+                decoder.feed(&chunk[..read]);
+            }
+        });
+
+        // queries still in flight when the reading stopped are answered
+        // before the scope ends, and may have failed too
+        result.and(
+            match failed.into_inner().unwrap_or_else(|e| e.into_inner()) {
+                Some(e) => Err(e),
+                None => Ok(()),
+            },
+        )
+    }
+
+    // resolve a raw query packet of len bytes from src into a raw response,
+    // whichever transport it arrived on. addr is the client's address, which
+    // the layers may check, None on transports without one
+    pub fn answer<A: Display>(
+        &self,
+        req_buffer: &mut buffer::PacketBuffer,
+        len: usize,
+        src: A,
+        addr: Option<IpAddr>,
+        transport: Transport,
+        query_socket: &UdpSocket,
+    ) -> Result<buffer::PacketBuffer, &'static str> {
+        // a packet that makes us panic only loses its own answer, the
+        // listeners carry on with the next one
+        let answer = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.answer_query(req_buffer, len, &src, addr, transport, query_socket)
+        }));
+
+        answer.unwrap_or_else(|_| {
+            self.panics.fetch_add(1, Ordering::Relaxed);
+            Err("panicked while answering")
+        })
+    }
+
+    // drop the upstream connections and keys made over the old network,
+    // i.e. the pooled tcp connections and whatever the layers keep. called
+    // when the default route changes, or by whoever notices otherwise
+    pub fn network_changed(&self) {
+        self.tcp.clear();
+        for layer in &self.layers {
+            layer.network_changed();
+        }
+    }
+
+    // look at the default route, handling a change of it
+    fn check_network(&self) {
+        if self.network.poll() {
+            match self.network.route() {
+                Some(route) => println!("Default route changed to {}", route),
+                None => println!("Default route is gone"),
+            }
+            self.network_changed();
+        }
+    }
+
+    fn answer_query<A: Display>(
+        &self,
+        req_buffer: &mut buffer::PacketBuffer,
+        len: usize,
+        src: A,
+        addr: Option<IpAddr>,
+        transport: Transport,
+        query_socket: &UdpSocket,
+    ) -> Result<buffer::PacketBuffer, &'static str> {
+        // parse the query packet
+        let mut req_packet = match DnsPacket::read(req_buffer) {
+            Ok(packet) => packet,
+            Err(e) => {
+                if let Some(capture) = &self.capture {
+                    capture.record(src, &req_buffer.buf[..len], e);
+                }
+                return Err(e);
+            }
+        };
+
+        self.ingress.increment(transport);
+
+        // check if we have any questions
+        if req_packet.questions.is_empty() {
+            return Err("received packet with no questions");
+        }
+
+        // create a response packet
+        let mut res_packet = DnsPacket::new();
+        res_packet.header.id = req_packet.header.id; // copy the request id
+        res_packet.header.rd = true; // set recursion desired
+        res_packet.header.ra = true; // set recursion available
+        res_packet.header.qr = true; // set response flag
+
+        // a client asking for our identity gets it back in an OPT record of
+        // its own (RFC 5001 2.2). it advertises 512 bytes, all we send
+        // over udp
+        if let Some(nsid) = &self.nsid {
+            if req_packet.edns_option(OPTION_NSID).is_some() {
+                res_packet.add_edns(512);
+                res_packet.add_edns_option(OPTION_NSID, nsid.as_bytes());
+            }
+        }
+
+        // tell tcp clients that ask how long they may keep the connection
+        // idle (RFC 7828 3.3.2), in units of 100 milliseconds
+        let streamed = matches!(transport, Transport::Tcp | Transport::Tls);
+        if streamed && req_packet.edns_option(OPTION_TCP_KEEPALIVE).is_some() {
+            let timeout = (self.tcp_idle_timeout.as_millis() / 100).min(u16::MAX as u128) as u16;
+            if res_packet.edns_payload().is_none() {
+                res_packet.add_edns(512);
+            }
+            res_packet.add_edns_option(OPTION_TCP_KEEPALIVE, &timeout.to_be_bytes());
+        }
+
+        // only standard queries are served
+        if req_packet.header.opcode != Opcode::Query {
+            res_packet.header.opcode = req_packet.header.opcode;
+            res_packet.header.rcode = ResultCode::NOTIMP;
+        }
+        // check question
+        else if let Some(question) = req_packet.questions.pop() {
+            println!(
+                "Received query for {} {} over {} from {}",
+                question.qname, question.qtype, transport, src
+            );
+
+            // client queries go through the layers, the last of which
+            // hands them to the recursive resolution
+            let query = Query {
+                question: &question,
+                src: &src,
+                addr,
+                transport,
+                socket: query_socket,
+            };
+            let resolve = |query: &Query| {
+                let question = query.question;
+
+                // only the internet class has a hierarchy to resolve through,
+                // other classes are left to the layers
+                if question.qclass != DnsClass::IN {
+                    let mut packet = DnsPacket::new();
+                    packet.header.rcode = ResultCode::NOTIMP;
+                    return Ok(packet);
+                }
+
+                // ANY is mostly used to amplify attacks, rather than collect
+                // everything at the name it's answered with a lone HINFO
+                // record (RFC 8482 4.2). a layer can still route it upstream
+                if question.qtype == QueryType::ANY {
+                    let mut packet = DnsPacket::new();
+                    packet.add_answer(DnsRecord::HINFO {
+                        domain: question.qname.clone(),
+                        cpu: "RFC8482".to_string(),
+                        os: String::new(),
+                        class: DnsClass::IN,
+                        ttl: 3600,
+                    });
+                    return Ok(packet);
+                }
+
+                self.recursive_lookup(&question.qname, question.qtype, self.root, query_socket)
+            };
+
+            self.check_network();
+            let changes = self.network.changes();
+            let mut result = Next::new(&self.layers, &resolve).run(&query);
+
+            // a resolution the network changed under most likely failed
+            // because of that, it gets another go over the new one rather
+            // than the client getting SERVFAIL
+            if result.is_err() {
+                self.check_network();
+                if self.network.changes() != changes {
+                    println!(
+                        "Retrying {} {} after the network changed",
+                        question.qname, question.qtype
+                    );
+                    result = Next::new(&self.layers, &resolve).run(&query);
+                }
+            }
+
+            if let Ok(result) = result {
+                res_packet.add_question(question);
+                res_packet.header.rcode = result.header.rcode;
+
+                // records of unknown types are passed on as they came, except
+                // OPT which only describes the hop it arrived on
+                let forwardable = |record: &DnsRecord| match record {
+                    DnsRecord::UNKOWN { qtype, .. } => QueryType::from(*qtype) != QueryType::OPT,
+                    _ => true,
+                };
+
+                // upstream may have put target addresses in any section,
+                // copy them to the additionals below
+                let known: Vec<DnsRecord> = result
+                    .answers
+                    .iter()
+                    .chain(&result.authorities)
+                    .filter(|record| matches!(record, DnsRecord::A { .. } | DnsRecord::AAAA { .. }))
+                    .cloned()
+                    .collect();
+
+                for answer in result.answers.into_iter().filter(forwardable) {
+                    res_packet.add_answer(answer);
+                }
+
+                for authority in result.authorities.into_iter().filter(forwardable) {
+                    res_packet.add_authority(authority);
+                }
+
+                for additional in result.additionals.into_iter().filter(forwardable) {
+                    res_packet.add_additional(additional);
+                }
+
+                res_packet.add_target_glue(&known);
+                if self.resolve_targets {
+                    self.add_target_addresses(&mut res_packet, query_socket);
+                }
+
+                for (domain, qtype, ttl) in res_packet.harmonize_ttls() {
+                    println!("Harmonized the TTLs of {} {} to {}", domain, qtype, ttl);
+                }
+            } else {
+                res_packet.header.rcode = ResultCode::SERVFAIL;
+            }
+        } else {
+            res_packet.header.rcode = ResultCode::FORMERR;
+        }
+
+        // encrypted responses to clients using EDNS are padded, so their
+        // length says less about what was asked (RFC 7830). 468 bytes is
+        // the block size RFC 8467 4.1 recommends for responses
+        let encrypted = matches!(transport, Transport::Tls | Transport::Https);
+        if encrypted && self.padding_block > 0 && req_packet.edns_payload().is_some() {
+            res_packet.pad(self.padding_block)?;
+        }
+
+        // write our response packet to a buffer, streams take messages of
+        // any size while udp is limited to 512 bytes
+        let mut res_buffer = match transport {
+            Transport::Udp => buffer::PacketBuffer::new(),
+            Transport::Tcp | Transport::Unix | Transport::Tls | Transport::Https => {
+                buffer::PacketBuffer::with_len(buffer::PacketBuffer::MAX_LEN)
+            }
+        };
+        match res_packet.write(&mut res_buffer) {
+            Ok(()) => {}
+            Err(_) if transport == Transport::Udp => {
+                // too large, send just the question with tc set so the
+                // client asks again over tcp
+                let mut truncated = DnsPacket::new();
+                truncated.header = res_packet.header;
+                truncated.header.tc = true;
+                truncated.header.qdcount = 0;
+                truncated.header.ancount = 0;
+                truncated.header.nscount = 0;
+                truncated.header.arcount = 0;
+                for question in res_packet.questions {
+                    truncated.add_question(question);
+                }
+
+                res_buffer = buffer::PacketBuffer::new();
+                truncated.write(&mut res_buffer)?;
+            }
+            Err(e) => return Err(e),
+        }
+
+        Ok(res_buffer)
+    }
 }
 
 // root servers
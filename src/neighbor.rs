@@ -0,0 +1,61 @@
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+};
+
+// a client address, with the MAC address it was last seen at when known.
+// on a LAN gateway the MAC identifies a device even as DHCP moves it
+// between addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Client {
+    pub addr: SocketAddr,
+    pub mac: Option<String>,
+}
+
+impl Client {
+    // look the client up in the neighbor table
+    pub fn identify(addr: SocketAddr) -> Self {
+        Client {
+            addr,
+            mac: mac_of(addr.ip()),
+        }
+    }
+}
+
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.mac {
+            Some(mac) => write!(f, "{} ({})", self.addr, mac),
+            None => write!(f, "{}", self.addr),
+        }
+    }
+}
+
+// the MAC address of a host on a directly attached network, read from the
+// kernel's ARP table. only ipv4 neighbors are in /proc, ipv6 ones (NDP)
+// would need netlink, so those come back as None
+#[cfg(target_os = "linux")]
+pub fn mac_of(ip: IpAddr) -> Option<String> {
+    let ip = match ip {
+        IpAddr::V4(ip) => ip,
+        IpAddr::V6(ip) => ip.to_ipv4_mapped()?,
+    };
+
+    // IP address  HW type  Flags  HW address  Mask  Device
+    let table = std::fs::read_to_string("/proc/net/arp").ok()?;
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields[..] {
+            // flags of 0 mark an entry that never resolved
+            [addr, _, flags, mac, ..] if flags != "0x0" && addr.parse() == Ok(ip) => {
+                Some(mac.to_string())
+            }
+            _ => None,
+        }
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn mac_of(_: IpAddr) -> Option<String> {
+    None
+}
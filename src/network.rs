@@ -0,0 +1,149 @@
+use std::{
+    fmt,
+    net::Ipv4Addr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+// the route packets leave by when nothing more specific matches. when it
+// changes, e.g. a laptop moving between networks or an interface going
+// down, connections and keys negotiated with upstreams over the old one
+// are likely dead
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefaultRoute {
+    pub interface: String,
+    pub gateway: Ipv4Addr,
+}
+
+impl fmt::Display for DefaultRoute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} via {}", self.gateway, self.interface)
+    }
+}
+
+// notices changes of the default route by reading it again once interval
+// has passed since the last look. every thread polls the same Watch, the
+// one that sees the change gets told
+#[derive(Debug)]
+pub struct Watch {
+    state: Mutex<WatchState>,
+    changes: AtomicU64,
+    pub interval: Duration, // how often the routing table is read at most
+}
+
+#[derive(Debug, Default)]
+struct WatchState {
+    route: Option<DefaultRoute>,
+    seen: bool, // observed before, the first route is not a change
+    checked: Option<Instant>,
+}
+
+impl Default for Watch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Watch {
+    pub fn new() -> Self {
+        Watch {
+            state: Mutex::new(WatchState::default()),
+            changes: AtomicU64::new(0),
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    // read the default route if it's time to, and whether it changed
+    // since the last read
+    pub fn poll(&self) -> bool {
+        {
+            let mut state = self.lock();
+            if state
+                .checked
+                .is_some_and(|checked| checked.elapsed() < self.interval)
+            {
+                return false;
+            }
+            state.checked = Some(Instant::now());
+        }
+        self.observe(default_route())
+    }
+
+    // record route as the current default route, and whether that differs
+    // from the one before
+    pub fn observe(&self, route: Option<DefaultRoute>) -> bool {
+        let mut state = self.lock();
+        let seen = std::mem::replace(&mut state.seen, true);
+        if state.route == route {
+            return false;
+        }
+
+        state.route = route;
+        if seen {
+            self.changes.fetch_add(1, Ordering::Relaxed);
+        }
+        seen
+    }
+
+    // the default route last observed
+    pub fn route(&self) -> Option<DefaultRoute> {
+        self.lock().route.clone()
+    }
+
+    // number of changes seen so far, comparing two readings tells whether
+    // the network changed in between
+    pub fn changes(&self) -> u64 {
+        self.changes.load(Ordering::Relaxed)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, WatchState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// the default route with the lowest metric, None without one, e.g. when
+// every interface is down
+#[cfg(target_os = "linux")]
+pub fn default_route() -> Option<DefaultRoute> {
+    let table = std::fs::read_to_string("/proc/net/route").ok()?;
+    parse_default_route(&table)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn default_route() -> Option<DefaultRoute> {
+    None
+}
+
+// the default route in a routing table as /proc/net/route lists it, with
+// addresses in hex as the kernel holds them
+pub fn parse_default_route(table: &str) -> Option<DefaultRoute> {
+    // Iface  Destination  Gateway  Flags  RefCnt  Use  Metric  Mask  ...
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                [interface, "00000000", gateway, flags, _, _, metric, "00000000", ..] => {
+                    // RTF_UP, routes of interfaces being brought down linger
+                    let flags = u16::from_str_radix(flags, 16).ok()?;
+                    if flags & 1 == 0 {
+                        return None;
+                    }
+                    let gateway = u32::from_str_radix(gateway, 16).ok()?;
+                    let metric = metric.parse::<u32>().ok()?;
+                    let route = DefaultRoute {
+                        interface: interface.to_string(),
+                        gateway: Ipv4Addr::from(gateway.to_ne_bytes()),
+                    };
+                    Some((metric, route))
+                }
+                _ => None,
+            }
+        })
+        .min_by_key(|(metric, _)| *metric)
+        .map(|(_, route)| route)
+}
@@ -0,0 +1,172 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+// reads the udp datagrams out of a pcap capture (the libpcap format
+// tcpdump -w writes), so the dns messages in them can be parsed offline.
+// pcapng files, tcp streams and fragmented datagrams are not supported
+
+// a udp datagram from a capture, with when and between whom it was sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    pub secs: u32,  // capture time, seconds since the epoch
+    pub nanos: u32, // and the fraction of the second
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+// link layers frames are captured with
+const LINKTYPE_NULL: u32 = 0; // bsd loopback, the address family in host order
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101; // bare ip packets
+const LINKTYPE_LINUX_SLL: u32 = 113; // tcpdump -i any
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+// every udp datagram in a capture, frames of other protocols are skipped
+pub fn datagrams(data: &[u8]) -> Result<Vec<Datagram>, &'static str> {
+    if data.len() < 24 {
+        return Err("not a pcap file");
+    }
+
+    // the magic number tells the byte order and timestamp resolution
+    let magic = [data[0], data[1], data[2], data[3]];
+    let (big_endian, nano) = match magic {
+        [0xA1, 0xB2, 0xC3, 0xD4] => (true, false),
+        [0xD4, 0xC3, 0xB2, 0xA1] => (false, false),
+        [0xA1, 0xB2, 0x3C, 0x4D] => (true, true),
+        [0x4D, 0x3C, 0xB2, 0xA1] => (false, true),
+        [0x0A, 0x0D, 0x0D, 0x0A] => return Err("pcapng is not supported, convert it to pcap"),
+        _ => return Err("not a pcap file"),
+    };
+    let u32_at = |bytes: &[u8], pos: usize| {
+        let word = [bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]];
+        match big_endian {
+            true => u32::from_be_bytes(word),
+            false => u32::from_le_bytes(word),
+        }
+    };
+    let linktype = u32_at(data, 20) & 0x0FFF_FFFF;
+
+    let mut datagrams = Vec::new();
+    let mut pos = 24;
+    while pos + 16 <= data.len() {
+        let secs = u32_at(data, pos);
+        let frac = u32_at(data, pos + 4);
+        let len = u32_at(data, pos + 8) as usize;
+        pos += 16;
+
+        let frame = data.get(pos..pos + len).ok_or("capture ends mid frame")?;
+        pos += len;
+
+        let Some((src, dst, payload)) = link(linktype, frame, big_endian) else {
+            continue;
+        };
+        datagrams.push(Datagram {
+            secs,
+            nanos: if nano { frac } else { frac * 1000 },
+            src,
+            dst,
+            payload: payload.to_vec(),
+        });
+    }
+
+    Ok(datagrams)
+}
+
+// strip the link layer header, None for frames that aren't udp over ip
+fn link(linktype: u32, frame: &[u8], big_endian: bool) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+            let mut start = 14;
+            // skip 802.1Q vlan tags
+            while ethertype == 0x8100 || ethertype == 0x88A8 {
+                ethertype = u16::from_be_bytes([*frame.get(start + 2)?, *frame.get(start + 3)?]);
+                start += 4;
+            }
+            ip(ethertype, frame.get(start..)?)
+        }
+        LINKTYPE_RAW => match frame.first()? >> 4 {
+            4 => ip(0x0800, frame),
+            6 => ip(0x86DD, frame),
+            _ => None,
+        },
+        LINKTYPE_LINUX_SLL => {
+            let protocol = u16::from_be_bytes([*frame.get(14)?, *frame.get(15)?]);
+            ip(protocol, frame.get(16..)?)
+        }
+        LINKTYPE_LINUX_SLL2 => {
+            let protocol = u16::from_be_bytes([*frame.first()?, *frame.get(1)?]);
+            ip(protocol, frame.get(20..)?)
+        }
+        LINKTYPE_NULL => {
+            // written in the byte order of the capturing host
+            let family = [
+                *frame.first()?,
+                *frame.get(1)?,
+                *frame.get(2)?,
+                *frame.get(3)?,
+            ];
+            let family = match big_endian {
+                true => u32::from_be_bytes(family),
+                false => u32::from_le_bytes(family),
+            };
+            match family {
+                2 => ip(0x0800, frame.get(4..)?),
+                24 | 28 | 30 => ip(0x86DD, frame.get(4..)?),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn ip(ethertype: u16, packet: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let (src, dst, segment) = match ethertype {
+        0x0800 => {
+            let header_len = (*packet.first()? & 0x0F) as usize * 4;
+            let total_len = u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]) as usize;
+            let fragment = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]);
+            // only udp, and only whole datagrams
+            if *packet.get(9)? != 17 || fragment & 0x3FFF != 0 {
+                return None;
+            }
+
+            let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            let segment = packet.get(header_len..total_len.min(packet.len()))?;
+            (
+                IpAddr::V4(Ipv4Addr::from(src)),
+                IpAddr::V4(Ipv4Addr::from(dst)),
+                segment,
+            )
+        }
+        0x86DD => {
+            // extension headers are not followed
+            if *packet.get(6)? != 17 {
+                return None;
+            }
+
+            let payload_len = u16::from_be_bytes([*packet.get(4)?, *packet.get(5)?]) as usize;
+            let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            let segment = packet.get(40..(40 + payload_len).min(packet.len()))?;
+            (
+                IpAddr::V6(Ipv6Addr::from(src)),
+                IpAddr::V6(Ipv6Addr::from(dst)),
+                segment,
+            )
+        }
+        _ => return None,
+    };
+
+    let src_port = u16::from_be_bytes([*segment.first()?, *segment.get(1)?]);
+    let dst_port = u16::from_be_bytes([*segment.get(2)?, *segment.get(3)?]);
+    let len = u16::from_be_bytes([*segment.get(4)?, *segment.get(5)?]) as usize;
+    let payload = segment.get(8..len.clamp(8, segment.len()))?;
+
+    Some((
+        SocketAddr::new(src, src_port),
+        SocketAddr::new(dst, dst_port),
+        payload,
+    ))
+}
@@ -0,0 +1,160 @@
+use std::{collections::HashMap, net::IpAddr, str::FromStr, sync::Mutex, time::Instant};
+
+use crate::{
+    dns::{header::ResultCode, packet::DnsPacket},
+    layer::{Layer, Next, Query},
+    Transport,
+};
+
+// clients tracked for rate limits before the ones that went quiet are
+// forgotten
+const MAX_CLIENTS: usize = 10000;
+
+// who may query over a transport, and how fast
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policy {
+    pub allow: Vec<(IpAddr, u8)>, // networks served, everyone if empty
+    pub rate: Option<u32>,        // queries a second each client may send
+}
+
+impl Policy {
+    pub fn new() -> Self {
+        Policy::default()
+    }
+
+    fn allows(&self, addr: IpAddr) -> bool {
+        self.allow.is_empty()
+            || self
+                .allow
+                .iter()
+                .any(|&(network, len)| in_network(addr, network, len))
+    }
+}
+
+// a client's allowance, refilled at the policy's rate up to a second's worth
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// access control and rate limits per transport, e.g. dns over https open
+// to the internet but held to a few queries a second per client, while udp
+// only answers the LAN. refused queries get REFUSED. clients without an
+// address, i.e. on the unix socket, are refused by an ACL and have no
+// rate to be limited to
+#[derive(Debug, Default)]
+pub struct Policies {
+    pub rules: Vec<(Transport, Policy)>,
+    buckets: Mutex<HashMap<(Transport, IpAddr), Bucket>>,
+}
+
+impl Policies {
+    pub fn new() -> Self {
+        Policies::default()
+    }
+
+    // the policy for a transport, created empty if it has none yet
+    pub fn policy(&mut self, transport: Transport) -> &mut Policy {
+        let index = match self.rules.iter().position(|(t, _)| *t == transport) {
+            Some(index) => index,
+            None => {
+                self.rules.push((transport, Policy::new()));
+                self.rules.len() - 1
+            }
+        };
+        &mut self.rules[index].1
+    }
+
+    // whether the client may send another query over transport now
+    pub fn admits(&self, transport: Transport, addr: IpAddr) -> bool {
+        let policy = match self.rules.iter().find(|(t, _)| *t == transport) {
+            Some((_, policy)) => policy,
+            None => return true,
+        };
+        if !policy.allows(addr) {
+            return false;
+        }
+        let rate = match policy.rate {
+            Some(rate) => rate as f64,
+            None => return true,
+        };
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_CLIENTS {
+            buckets.retain(|_, bucket| now.duration_since(bucket.updated).as_secs() < 1);
+        }
+
+        let bucket = buckets.entry((transport, addr)).or_insert(Bucket {
+            tokens: rate,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+impl Layer for Policies {
+    fn handle(&self, query: &Query, next: Next) -> Result<DnsPacket, &'static str> {
+        let admitted = match query.addr {
+            Some(addr) => self.admits(query.transport, addr),
+            None => self.rules.iter().all(|(transport, policy)| {
+                *transport != query.transport || policy.allow.is_empty()
+            }),
+        };
+
+        if !admitted {
+            println!(
+                "Refused {} {} over {} from {}",
+                query.question.qname, query.question.qtype, query.transport, query.src
+            );
+            let mut packet = DnsPacket::new();
+            packet.header.rcode = ResultCode::REFUSED;
+            return Ok(packet);
+        }
+
+        next.run(query)
+    }
+}
+
+// a network written as <ip>/<prefix length>, or a lone address
+pub fn parse_network(network: &str) -> Option<(IpAddr, u8)> {
+    let (addr, len) = match network.split_once('/') {
+        Some((addr, len)) => (IpAddr::from_str(addr).ok()?, len.parse().ok()?),
+        None => {
+            let addr = IpAddr::from_str(network).ok()?;
+            (addr, if addr.is_ipv4() { 32 } else { 128 })
+        }
+    };
+
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    (len <= max).then_some((addr, len))
+}
+
+fn in_network(addr: IpAddr, network: IpAddr, len: u8) -> bool {
+    // clients on a dual stack socket show up as v4 mapped v6 addresses
+    let addr = match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+        v4 => v4,
+    };
+
+    match (addr, network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+            u32::from(addr) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(addr), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+            u128::from(addr) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
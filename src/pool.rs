@@ -0,0 +1,298 @@
+use std::{
+    collections::HashMap,
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{buffer::PacketBuffer, dns::packet::DnsPacket};
+
+// tcp connections to upstream servers, kept open between queries and shared
+// by every thread querying the same server (RFC 7766 6.2.1). queries are
+// sent as soon as they are made and responses matched to them by id, in
+// whatever order the server sends them
+pub struct TcpPool {
+    conns: Mutex<HashMap<SocketAddr, Arc<Conn>>>,
+    pub idle_timeout: Duration, // connections unused this long are reopened
+    pub timeout: Duration,      // for connecting and for each response
+}
+
+struct Conn {
+    stream: TcpStream, // a clone of it is used for writing
+    writer: Mutex<TcpStream>,
+    state: Mutex<ConnState>,
+    changed: Condvar, // a response arrived or the connection broke
+}
+
+#[derive(Default)]
+struct ConnState {
+    next_id: u16,
+    waiting: HashMap<u16, Option<PacketBuffer>>, // in flight, and their responses once read
+    reading: bool,                               // a thread is reading responses for all of them
+    broken: bool, // a read or write failed, the connection is not reused
+    last_used: Option<Instant>,
+    keepalive: Option<Duration>, // idle timeout the server announced, if shorter than ours
+}
+
+impl Default for TcpPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TcpPool {
+    pub fn new() -> Self {
+        TcpPool {
+            conns: Mutex::new(HashMap::new()),
+            idle_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    // send a query to server and receive its raw response, over an open
+    // connection when there is one. the query id is replaced with one unique
+    // on the connection, and restored in the response
+    pub fn exchange(
+        &self,
+        query: &[u8],
+        server: SocketAddr,
+    ) -> Result<(PacketBuffer, usize), &'static str> {
+        let (conn, reused) = self.conn(server)?;
+        match conn.exchange(query, self.timeout) {
+            // the server may have closed a connection we kept, which we
+            // only notice when using it, so that is retried on a fresh one
+            Err(_) if reused => {
+                self.discard(server, &conn);
+                let (conn, _) = self.conn(server)?;
+                conn.exchange(query, self.timeout)
+            }
+            result => result,
+        }
+    }
+
+    // number of connections currently kept open
+    pub fn len(&self) -> usize {
+        self.conns().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // close every connection, e.g. once the network they were opened over
+    // is gone. queries in flight on them still get their responses or fail
+    pub fn clear(&self) {
+        self.conns().clear();
+    }
+
+    // the open connection to server and whether it was used before, or a
+    // new one. connections left idle are closed on the way
+    fn conn(&self, server: SocketAddr) -> Result<(Arc<Conn>, bool), &'static str> {
+        let mut conns = self.conns();
+        conns.retain(|_, conn| conn.usable(self.idle_timeout));
+        if let Some(conn) = conns.get(&server) {
+            return Ok((conn.clone(), true));
+        }
+
+        let stream =
+            TcpStream::connect_timeout(&server, self.timeout).map_err(|_| "failed to connect")?;
+        let writer = stream.try_clone().map_err(|_| "failed to connect")?;
+        let conn = Arc::new(Conn {
+            stream,
+            writer: Mutex::new(writer),
+            state: Mutex::new(ConnState::default()),
+            changed: Condvar::new(),
+        });
+        conns.insert(server, conn.clone());
+        Ok((conn, false))
+    }
+
+    // forget conn, unless it was already replaced by another thread
+    fn discard(&self, server: SocketAddr, conn: &Arc<Conn>) {
+        let mut conns = self.conns();
+        if conns
+            .get(&server)
+            .is_some_and(|open| Arc::ptr_eq(open, conn))
+        {
+            conns.remove(&server);
+        }
+    }
+
+    // a thread that panicked holding the lock can't leave the map in a
+    // state worth refusing
+    fn conns(&self) -> MutexGuard<'_, HashMap<SocketAddr, Arc<Conn>>> {
+        self.conns.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Conn {
+    fn lock(&self) -> MutexGuard<'_, ConnState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // neither broken nor idle past idle_timeout, or what the server asked for
+    fn usable(&self, idle_timeout: Duration) -> bool {
+        let state = self.lock();
+        let idle_timeout = state
+            .keepalive
+            .map_or(idle_timeout, |k| k.min(idle_timeout));
+        let idle = state.waiting.is_empty()
+            && state
+                .last_used
+                .is_some_and(|used| used.elapsed() > idle_timeout);
+        !state.broken && !idle
+    }
+
+    fn exchange(
+        &self,
+        query: &[u8],
+        timeout: Duration,
+    ) -> Result<(PacketBuffer, usize), &'static str> {
+        if query.len() < 2 || query.len() > u16::MAX as usize {
+            return Err("invalid query");
+        }
+
+        let id = {
+            let mut state = self.lock();
+            if state.broken {
+                return Err("connection closed");
+            }
+            if state.waiting.len() > u16::MAX as usize {
+                return Err("too many queries in flight");
+            }
+            while state.waiting.contains_key(&state.next_id) {
+                state.next_id = state.next_id.wrapping_add(1);
+            }
+            let id = state.next_id;
+            state.next_id = id.wrapping_add(1);
+            state.waiting.insert(id, None);
+            state.last_used = Some(Instant::now());
+            id
+        };
+
+        let result = self.send(query, id).and_then(|_| self.receive(id, timeout));
+
+        let mut state = self.lock();
+        state.waiting.remove(&id);
+        state.last_used = Some(Instant::now());
+        drop(state);
+
+        let (mut buffer, len) = result?;
+
+        // servers say how long they keep idle connections with the
+        // keepalive option, in any response (RFC 7828 3.3.2)
+        if let Some(keepalive) = DnsPacket::read(&mut buffer)
+            .ok()
+            .and_then(|r| r.tcp_keepalive())
+        {
+            self.lock().keepalive = Some(keepalive);
+        }
+        buffer.seek(0);
+
+        // hand back the id the caller asked with
+        buffer.buf[..2].copy_from_slice(&query[..2]);
+        Ok((buffer, len))
+    }
+
+    // write the length prefixed query with id in place of its own, whole
+    // messages at a time so concurrent queries don't interleave
+    fn send(&self, query: &[u8], id: u16) -> Result<(), &'static str> {
+        let mut message = Vec::with_capacity(2 + query.len());
+        message.extend_from_slice(&(query.len() as u16).to_be_bytes());
+        message.extend_from_slice(&id.to_be_bytes());
+        message.extend_from_slice(&query[2..]);
+
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        writer.write_all(&message).map_err(|_| {
+            // part of the message may have gone out, nothing after it would
+            // be read right by the server
+            self.lock().broken = true;
+            self.changed.notify_all();
+            "failed to send"
+        })
+    }
+
+    // wait for the response to id. one waiting thread at a time reads off
+    // the stream and files what it reads under the id it carries, the
+    // others sleep until their response is filed
+    fn receive(&self, id: u16, timeout: Duration) -> Result<(PacketBuffer, usize), &'static str> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock();
+        loop {
+            if let Some(response) = state.waiting.get_mut(&id).and_then(Option::take) {
+                let len = response.buf.len();
+                return Ok((response, len));
+            }
+            if state.broken {
+                return Err("connection closed");
+            }
+
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err("failed to recv");
+            }
+
+            if state.reading {
+                state = self
+                    .changed
+                    .wait_timeout(state, left)
+                    .unwrap_or_else(|e| e.into_inner())
+                    .0;
+                continue;
+            }
+
+            state.reading = true;
+            drop(state);
+            let read = self.read_message(left);
+            state = self.lock();
+            state.reading = false;
+
+            match read {
+                Ok(Some(response)) if response.buf.len() >= 2 => {
+                    let id = u16::from_be_bytes([response.buf[0], response.buf[1]]);
+                    // responses to queries that gave up waiting are dropped
+                    if let Some(slot) = state.waiting.get_mut(&id) {
+                        *slot = Some(response);
+                    }
+                }
+                // nothing arrived in time, the stream is still in step for
+                // the other queries on it
+                Ok(None) => {}
+                // a message cut short leaves the stream out of step, so
+                // nothing more can be read off it
+                _ => state.broken = true,
+            }
+            self.changed.notify_all();
+        }
+    }
+
+    // the next message, or None if timeout passed before any of it came.
+    // an error means part of a message may have been read, or the server
+    // closed the connection
+    fn read_message(&self, timeout: Duration) -> Result<Option<PacketBuffer>, &'static str> {
+        let mut stream = &self.stream;
+        stream
+            .set_read_timeout(Some(timeout))
+            .map_err(|_| "failed to recv")?;
+
+        let mut len = [0; 2];
+        let mut read = 0;
+        while read < len.len() {
+            match stream.read(&mut len[read..]) {
+                Ok(0) => return Err("connection closed"),
+                Ok(n) => read += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if read == 0 && crate::is_timeout(&e) => return Ok(None),
+                Err(_) => return Err("failed to recv"),
+            }
+        }
+        let len = u16::from_be_bytes(len) as usize;
+
+        let mut buffer = PacketBuffer::with_len(len);
+        stream
+            .read_exact(buffer.as_mut_slice())
+            .map_err(|_| "failed to recv")?;
+        Ok(Some(buffer))
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    fmt,
+    net::{Ipv4Addr, SocketAddr},
+};
+
+#[cfg(any(feature = "tls", feature = "dnscrypt"))]
+use crate::buffer::PacketBuffer;
+#[cfg(feature = "tls")]
+use crate::dns::packet::OPTION_TCP_KEEPALIVE;
+use crate::{
+    dns::{
+        packet::DnsPacket,
+        question::{DnsClass, QueryType},
+    },
+    layer::{Layer, Next, Query},
+    lookup,
+};
+
+// a server routed queries are forwarded to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Upstream {
+    Udp(Ipv4Addr, u16), // plain dns
+    #[cfg(feature = "tls")]
+    Tls(Ipv4Addr, u16, String), // dns over tls, its certificate verified for the name
+    #[cfg(feature = "dnscrypt")]
+    DnsCrypt(Ipv4Addr, u16, String, [u8; 32]), // dnscrypt, with the provider name and key
+}
+
+impl From<(Ipv4Addr, u16)> for Upstream {
+    fn from((ip, port): (Ipv4Addr, u16)) -> Self {
+        Upstream::Udp(ip, port)
+    }
+}
+
+impl Upstream {
+    // where queries to the upstream are sent
+    pub fn addr(&self) -> SocketAddr {
+        match self {
+            Upstream::Udp(ip, port) => (*ip, *port).into(),
+            #[cfg(feature = "tls")]
+            Upstream::Tls(ip, port, _) => (*ip, *port).into(),
+            #[cfg(feature = "dnscrypt")]
+            Upstream::DnsCrypt(ip, port, ..) => (*ip, *port).into(),
+        }
+    }
+}
+
+impl fmt::Display for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Upstream::Udp(ip, port) => write!(f, "{}:{}", ip, port),
+            #[cfg(feature = "tls")]
+            Upstream::Tls(ip, port, name) => write!(f, "{}:{}#{}", ip, port, name),
+            #[cfg(feature = "dnscrypt")]
+            Upstream::DnsCrypt(ip, port, provider, _) => {
+                write!(f, "{}:{}@{}", ip, port, provider)
+            }
+        }
+    }
+}
+
+// sends queries of some types to fixed upstreams instead of resolving them
+// from the root, e.g. HTTPS to one resolver and PTR to a local forwarder.
+// the first rule for the query's type wins, its upstreams are tried in
+// order and everything else continues down the stack
+#[derive(Debug, Default)]
+pub struct Router {
+    pub rules: Vec<(QueryType, Vec<Upstream>)>,
+
+    #[cfg(feature = "tls")]
+    pub tls: crate::tls::TlsClient, // connections to the tls upstreams
+
+    #[cfg(feature = "dnscrypt")]
+    pub dnscrypt: crate::dnscrypt::DnsCryptClient, // keys and certificates of the dnscrypt upstreams
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router {
+            rules: Vec::new(),
+
+            #[cfg(feature = "tls")]
+            tls: crate::tls::TlsClient::new(),
+
+            #[cfg(feature = "dnscrypt")]
+            dnscrypt: crate::dnscrypt::DnsCryptClient::new(),
+        }
+    }
+
+    // add an upstream for qtype, after any it already has
+    pub fn route<U: Into<Upstream>>(&mut self, qtype: QueryType, upstream: U) {
+        let upstream = upstream.into();
+        match self.rules.iter_mut().find(|(rule, _)| *rule == qtype) {
+            Some((_, upstreams)) => upstreams.push(upstream),
+            None => self.rules.push((qtype, vec![upstream])),
+        }
+    }
+
+    // drop the upstreams is_self takes for one of our own listeners, whose
+    // queries would only wait on themselves until the read timeout, and
+    // return them
+    pub fn drop_self<F: Fn(SocketAddr) -> bool>(&mut self, is_self: F) -> Vec<Upstream> {
+        let mut dropped = Vec::new();
+        for (_, upstreams) in &mut self.rules {
+            upstreams.retain(|upstream| {
+                let own = is_self(upstream.addr());
+                if own {
+                    dropped.push(upstream.clone());
+                }
+                !own
+            });
+        }
+        self.rules.retain(|(_, upstreams)| !upstreams.is_empty());
+        dropped
+    }
+
+    fn forward(&self, query: &Query, upstream: &Upstream) -> Result<DnsPacket, &'static str> {
+        let question = query.question;
+        match upstream {
+            Upstream::Udp(ip, port) => lookup(
+                question.qname.as_str(),
+                question.qtype,
+                (*ip, *port),
+                query.socket,
+            ),
+            #[cfg(feature = "tls")]
+            Upstream::Tls(ip, port, name) => {
+                // the payload size only applies to udp, the option asks
+                // how long the connection may be kept for reuse
+                let keepalive = [(OPTION_TCP_KEEPALIVE, Vec::new())];
+                let req_buf = crate::query_buffer(
+                    question.qname.clone(),
+                    question.qtype,
+                    PacketBuffer::MAX_LEN as u16,
+                    &keepalive,
+                )?;
+                let (mut res_buf, _) =
+                    self.tls
+                        .exchange(req_buf.as_slice(), (*ip, *port).into(), name)?;
+                DnsPacket::read(&mut res_buf)
+            }
+            #[cfg(feature = "dnscrypt")]
+            Upstream::DnsCrypt(ip, port, provider, key) => {
+                // responses larger than the padded query come over tcp
+                let req_buf = crate::query_buffer(
+                    question.qname.clone(),
+                    question.qtype,
+                    PacketBuffer::MAX_LEN as u16,
+                    &[],
+                )?;
+                let (mut res_buf, _) = self.dnscrypt.exchange(
+                    req_buf.as_slice(),
+                    (*ip, *port).into(),
+                    provider,
+                    key,
+                )?;
+                DnsPacket::read(&mut res_buf)
+            }
+        }
+    }
+}
+
+impl Layer for Router {
+    fn handle(&self, query: &Query, next: Next) -> Result<DnsPacket, &'static str> {
+        let question = query.question;
+
+        // lookup only asks in the internet class
+        if question.qclass != DnsClass::IN {
+            return next.run(query);
+        }
+
+        let upstreams = match self
+            .rules
+            .iter()
+            .find(|(qtype, _)| *qtype == question.qtype)
+        {
+            Some((_, upstreams)) => upstreams,
+            None => return next.run(query),
+        };
+
+        let mut result = Err("no upstream for route");
+        for upstream in upstreams {
+            println!(
+                "Forwarding {} {} to {}",
+                question.qname, question.qtype, upstream
+            );
+
+            result = self.forward(query, upstream);
+            if result.is_ok() {
+                break;
+            }
+        }
+
+        result
+    }
+
+    fn network_changed(&self) {
+        #[cfg(feature = "tls")]
+        self.tls.clear();
+
+        #[cfg(feature = "dnscrypt")]
+        self.dnscrypt.clear();
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream, UdpSocket},
+    path::Path,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rustls::{
+    crypto::ring,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+    ClientConfig, ClientConnection, HandshakeKind, RootCertStore, ServerConfig, ServerConnection,
+    StreamOwned, SupportedCipherSuite,
+};
+
+use crate::{buffer::PacketBuffer, dns::packet::DnsPacket, Resolver, Transport};
+
+type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+// open connections by server and name, with when they should be closed
+type Idle = HashMap<(SocketAddr, String), Vec<(TlsStream, Instant)>>;
+
+// dns over tls (RFC 7858) to upstreams that support it, on port 853 by
+// default. the server's certificate has to be valid for the name it is
+// configured with. connections are kept open between queries and reused,
+// one query at a time each, concurrent queries open more of them
+pub struct TlsClient {
+    config: Arc<ClientConfig>,
+    idle: Mutex<Idle>,
+    pub idle_timeout: Duration, // connections unused this long are closed
+    pub timeout: Duration,      // for connecting and for each response
+}
+
+impl Default for TlsClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the config holds nothing worth printing
+impl std::fmt::Debug for TlsClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("TlsClient")
+            .field("idle_timeout", &self.idle_timeout)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TlsClient {
+    // trusting the mozilla root certificates
+    pub fn new() -> Self {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        Self::with_roots(roots)
+    }
+
+    // trusting only roots, e.g. the ca of a private resolver
+    pub fn with_roots(roots: RootCertStore) -> Self {
+        let config = ClientConfig::builder_with_provider(ring::default_provider().into())
+            .with_safe_default_protocol_versions()
+            .expect("ring supports the default protocol versions")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        TlsClient {
+            config: Arc::new(config),
+            idle: Mutex::new(HashMap::new()),
+            idle_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    // send a raw query to server, verified as name, and receive the raw
+    // response
+    pub fn exchange(
+        &self,
+        query: &[u8],
+        server: SocketAddr,
+        name: &str,
+    ) -> Result<(PacketBuffer, usize), &'static str> {
+        if query.len() > u16::MAX as usize {
+            return Err("invalid query");
+        }
+
+        let key = (server, name.to_string());
+        if let Some(mut stream) = self.take_idle(&key) {
+            // the server may have closed a connection we kept, which we
+            // only notice when using it, so that is retried on a fresh one
+            if let Ok(mut response) = exchange_on(&mut stream, query) {
+                self.put_idle(key, stream, &mut response.0);
+                return Ok(response);
+            }
+        }
+
+        let mut stream = self.connect(server, name)?;
+        let mut response = exchange_on(&mut stream, query)?;
+        self.put_idle(key, stream, &mut response.0);
+        Ok(response)
+    }
+
+    // number of connections currently kept open
+    pub fn idle_len(&self) -> usize {
+        let idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        idle.values().map(Vec::len).sum()
+    }
+
+    // close every idle connection, e.g. once the network they were opened
+    // over is gone
+    pub fn clear(&self) {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    fn connect(&self, server: SocketAddr, name: &str) -> Result<TlsStream, &'static str> {
+        let name = ServerName::try_from(name.to_string()).map_err(|_| "invalid tls name")?;
+        let conn =
+            ClientConnection::new(self.config.clone(), name).map_err(|_| "failed to set up tls")?;
+
+        let tcp =
+            TcpStream::connect_timeout(&server, self.timeout).map_err(|_| "failed to connect")?;
+        tcp.set_read_timeout(Some(self.timeout))
+            .and_then(|_| tcp.set_write_timeout(Some(self.timeout)))
+            .map_err(|_| "failed to connect")?;
+
+        // handshake up front rather than on the first write, so a server
+        // with a bad certificate is told apart from one that hung up
+        let mut stream = StreamOwned::new(conn, tcp);
+        while stream.conn.is_handshaking() {
+            stream
+                .conn
+                .complete_io(&mut stream.sock)
+                .map_err(|_| "tls handshake failed")?;
+        }
+        Ok(stream)
+    }
+
+    // the most recently used connection to key that hasn't timed out,
+    // closing those that have
+    fn take_idle(&self, key: &(SocketAddr, String)) -> Option<TlsStream> {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        for streams in idle.values_mut() {
+            streams.retain(|(_, expires)| *expires > now);
+        }
+        idle.retain(|_, streams| !streams.is_empty());
+        idle.get_mut(key)?.pop().map(|(stream, _)| stream)
+    }
+
+    // keep stream for reuse after response arrived on it, for the idle
+    // timeout or however long less the server said it keeps it open with
+    // the keepalive option (RFC 7828 3.3.2)
+    fn put_idle(&self, key: (SocketAddr, String), stream: TlsStream, response: &mut PacketBuffer) {
+        let keepalive = DnsPacket::read(response)
+            .ok()
+            .and_then(|r| r.tcp_keepalive());
+        response.seek(0);
+        let timeout = keepalive.map_or(self.idle_timeout, |k| k.min(self.idle_timeout));
+
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        idle.entry(key)
+            .or_default()
+            .push((stream, Instant::now() + timeout));
+    }
+}
+
+// one length prefixed query and its response, as over plain tcp
+fn exchange_on(
+    stream: &mut TlsStream,
+    query: &[u8],
+) -> Result<(PacketBuffer, usize), &'static str> {
+    let mut message = Vec::with_capacity(2 + query.len());
+    message.extend_from_slice(&(query.len() as u16).to_be_bytes());
+    message.extend_from_slice(query);
+    stream
+        .write_all(&message)
+        .and_then(|_| stream.flush())
+        .map_err(|_| "failed to send")?;
+
+    let mut len = [0; 2];
+    stream.read_exact(&mut len).map_err(|_| "failed to recv")?;
+    let len = u16::from_be_bytes(len) as usize;
+
+    let mut buffer = PacketBuffer::with_len(len);
+    stream
+        .read_exact(buffer.as_mut_slice())
+        .map_err(|_| "failed to recv")?;
+
+    Ok((buffer, len))
+}
+
+// the config for serving dns over tls with the certificate chain and
+// private key in the PEM files at cert and key, negotiating the cipher
+// suites named in ciphers (see cipher_suites)
+pub fn server_config(
+    cert: &Path,
+    key: &Path,
+    ciphers: &[String],
+) -> Result<Arc<ServerConfig>, &'static str> {
+    load_server_config(cert, key, ciphers, b"dot")
+}
+
+// the config for serving dns over https with them instead, which only
+// speaks http/1.1
+pub fn https_server_config(
+    cert: &Path,
+    key: &Path,
+    ciphers: &[String],
+) -> Result<Arc<ServerConfig>, &'static str> {
+    load_server_config(cert, key, ciphers, b"http/1.1")
+}
+
+// the suites named like TLS13_AES_256_GCM_SHA384, in the order given, which
+// is the order of preference. none keeps ring's defaults. naming only TLS 1.3
+// suites turns TLS 1.2 off
+pub fn cipher_suites(names: &[String]) -> Result<Vec<SupportedCipherSuite>, &'static str> {
+    let supported = ring::default_provider().cipher_suites;
+    if names.is_empty() {
+        return Ok(supported);
+    }
+
+    names
+        .iter()
+        .map(|name| {
+            supported
+                .iter()
+                .find(|suite| {
+                    suite
+                        .suite()
+                        .as_str()
+                        .is_some_and(|suite| suite.eq_ignore_ascii_case(name))
+                })
+                .copied()
+                .ok_or("unknown cipher suite")
+        })
+        .collect()
+}
+
+fn load_server_config(
+    cert: &Path,
+    key: &Path,
+    ciphers: &[String],
+    alpn: &[u8],
+) -> Result<Arc<ServerConfig>, &'static str> {
+    let chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|_| "failed to read certificate")?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|_| "failed to read private key")?;
+
+    let provider = rustls::crypto::CryptoProvider {
+        cipher_suites: cipher_suites(ciphers)?,
+        ..ring::default_provider()
+    };
+    let mut config = ServerConfig::builder_with_provider(provider.into())
+        .with_safe_default_protocol_versions()
+        .expect("every ring suite has a default protocol version")
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .map_err(|_| "certificate does not match private key")?;
+    config.alpn_protocols = vec![alpn.to_vec()];
+
+    // clients reconnecting, e.g. phones whose network came back, resume
+    // their session from a ticket rather than doing the whole handshake.
+    // tickets are encrypted with keys rotated every 6 hours and kept in
+    // memory only, so they don't outlive the process
+    config.ticketer = ring::Ticketer::new().map_err(|_| "failed to set up session tickets")?;
+
+    Ok(Arc::new(config))
+}
+
+// answer the queries on an accepted dns over tls connection in order,
+// counting it in the resolver's DotCounts, and whether it resumed a session
+pub fn serve(
+    resolver: &Resolver,
+    mut tcp: TcpStream,
+    config: Arc<ServerConfig>,
+    query_socket: &UdpSocket,
+) -> Result<(), &'static str> {
+    let peer = tcp.peer_addr().map_err(|_| "failed to set up connection")?;
+    // the handshake included
+    tcp.set_read_timeout(Some(resolver.tcp_idle_timeout))
+        .map_err(|_| "failed to set up connection")?;
+    let mut conn = ServerConnection::new(config).map_err(|_| "failed to set up connection")?;
+
+    // handshake up front, so the connection is counted once it's known
+    // whether the client resumed
+    while conn.is_handshaking() {
+        conn.complete_io(&mut tcp)
+            .map_err(|_| "tls handshake failed")?;
+    }
+    resolver.dot.connections.fetch_add(1, Ordering::Relaxed);
+    if conn.handshake_kind() == Some(HandshakeKind::Resumed) {
+        resolver.dot.resumed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let mut stream = StreamOwned::new(conn, tcp);
+    resolver.handle_stream(
+        &mut stream,
+        peer,
+        Some(peer.ip()),
+        Transport::Tls,
+        query_socket,
+    )
+}
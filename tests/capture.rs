@@ -0,0 +1,39 @@
+// malformed packets written to a capture directory
+use std::{env, fs, path::PathBuf};
+
+use recursor::capture::Capture;
+
+#[test]
+fn writes_rate_limited_files() {
+    let dir: PathBuf = env::temp_dir()
+        .join(format!("recursor-capture-{}", std::process::id()))
+        .join("nested");
+    let _ = fs::remove_dir_all(&dir);
+
+    let capture = Capture::new(Some(dir.clone()), 3);
+    // the same source within a millisecond, with a MAC address too
+    for _ in 0..5 {
+        capture.record(
+            "10.0.0.2:5353 (aa:bb:cc:dd:ee:ff)",
+            &[0xab, 0x41],
+            "bad label",
+        );
+    }
+
+    let mut names: Vec<String> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names.len(), 3);
+    for (i, name) in names.iter().enumerate() {
+        let (_, rest) = name.split_once('-').unwrap();
+        assert_eq!(rest, format!("{}-10.0.0.2_5353__aa_bb_cc_dd_ee_ff_.txt", i));
+    }
+
+    let dump = fs::read_to_string(dir.join(&names[0])).unwrap();
+    assert!(dump.starts_with("source: 10.0.0.2:5353 (aa:bb:cc:dd:ee:ff)\nerror: bad label\n"));
+    assert!(dump.contains("0000  ab 41"));
+
+    fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+}
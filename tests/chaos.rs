@@ -0,0 +1,109 @@
+// resilience to faults injected into upstream responses, against a local
+// nameserver answering every question itself over both udp and tcp
+#![cfg(feature = "chaos")]
+
+use std::{
+    net::{Ipv4Addr, TcpListener, UdpSocket},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use common::{authoritative, query, serve_tcp, serve_udp, ADDR};
+use recursor::{
+    buffer::PacketBuffer,
+    chaos::Chaos,
+    dns::{header::ResultCode, packet::DnsPacket, question::QueryType},
+    Resolver, Transport,
+};
+
+mod common;
+
+// a nameserver on a local port, serving udp and tcp on it, and the number
+// of tcp connections it accepted
+fn nameserver() -> (u16, Arc<AtomicUsize>) {
+    // the first free udp port whose tcp twin is free too
+    let (udp, tcp) = loop {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        if let Ok(tcp) = TcpListener::bind(udp.local_addr().unwrap()) {
+            break (udp, tcp);
+        }
+    };
+    let port = udp.local_addr().unwrap().port();
+
+    let respond = |query: &DnsPacket| authoritative(query, ADDR);
+    serve_udp(udp, respond);
+    (port, serve_tcp(tcp, respond))
+}
+
+// a resolver whose root is the local nameserver, with chaos's faults
+fn resolver(chaos: Chaos) -> (Resolver, Arc<AtomicUsize>) {
+    let (port, accepted) = nameserver();
+    let mut resolver = Resolver::new(Ipv4Addr::LOCALHOST);
+    resolver.ns_port = port;
+    resolver.chaos = Some(chaos);
+    (resolver, accepted)
+}
+
+#[test]
+fn lost_responses_fail_the_lookup() {
+    let mut chaos = Chaos::with_seed(1);
+    chaos.loss = 1.0;
+    let (resolver, _) = resolver(chaos);
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let result = resolver.resolve("example.com", QueryType::A, &socket);
+    assert_eq!(result.err(), Some("failed to recv"));
+}
+
+#[test]
+fn truncated_responses_are_retried_over_tcp() {
+    let mut chaos = Chaos::with_seed(2);
+    chaos.truncate = 1.0;
+    let (resolver, accepted) = resolver(chaos);
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for _ in 0..2 {
+        let response = resolver
+            .resolve("example.com", QueryType::A, &socket)
+            .unwrap();
+        assert_eq!(response.get_any_a(), Some(ADDR));
+    }
+
+    // on one connection, kept for the second query
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn malformed_responses_fail_without_panicking() {
+    let mut chaos = Chaos::with_seed(3);
+    chaos.malform = 1.0;
+    let (resolver, _) = resolver(chaos);
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut failed = 0;
+    for id in 0..20 {
+        let query = query(id);
+        let mut buffer = PacketBuffer::new();
+        buffer.buf[..query.len()].copy_from_slice(&query);
+        let len = query.len();
+
+        let mut answer = resolver
+            .answer(&mut buffer, len, "client", None, Transport::Udp, &socket)
+            .unwrap();
+        answer.seek(0);
+        let answer = DnsPacket::read(&mut answer).unwrap();
+
+        // a corrupted byte may still parse, e.g. in a ttl, but one the
+        // response can't be read past is answered with SERVFAIL
+        match answer.header.rcode {
+            ResultCode::NOERROR => {}
+            ResultCode::SERVFAIL | ResultCode::FORMERR => failed += 1,
+            rcode => panic!("unexpected rcode {:?}", rcode),
+        }
+    }
+
+    assert!(failed > 0);
+    assert_eq!(resolver.panics.load(Ordering::Relaxed), 0);
+}
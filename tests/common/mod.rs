@@ -0,0 +1,120 @@
+// fixtures shared by the integration tests, each of which uses some of them
+#![allow(dead_code)]
+
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, TcpListener, UdpSocket},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use recursor::{
+    buffer::PacketBuffer,
+    dns::{
+        packet::DnsPacket,
+        question::{DnsClass, DnsQuestion, QueryType},
+        record::DnsRecord,
+    },
+};
+
+// the address the fake nameservers answer with
+pub const ADDR: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+
+// a query for example.com A with id, on the wire
+pub fn query(id: u16) -> Vec<u8> {
+    let mut packet = DnsPacket::new();
+    packet.header.id = id;
+    packet.header.rd = true;
+    packet.add_question(DnsQuestion::new("example.com".to_string(), QueryType::A));
+
+    let mut buffer = PacketBuffer::new();
+    packet.write(&mut buffer).unwrap();
+    buffer.as_slice().to_vec()
+}
+
+// the authoritative answer giving addr for every question in query
+pub fn authoritative(query: &DnsPacket, addr: Ipv4Addr) -> DnsPacket {
+    let mut response = DnsPacket::new();
+    response.header.aa = true;
+    for question in &query.questions {
+        response.add_answer(DnsRecord::A {
+            domain: question.qname.clone(),
+            addr,
+            class: DnsClass::IN,
+            ttl: 300,
+        });
+        response.add_question(question.clone());
+    }
+    response
+}
+
+// respond's response to a raw query, as a reply to it on the wire
+fn reply<F: Fn(&DnsPacket) -> DnsPacket>(query: &[u8], respond: &F) -> Vec<u8> {
+    let mut buffer = PacketBuffer::with_len(query.len());
+    buffer.buf.copy_from_slice(query);
+    let query = DnsPacket::read(&mut buffer).unwrap();
+
+    let mut response = respond(&query);
+    response.header.id = query.header.id;
+    response.header.qr = true;
+
+    let mut buffer = PacketBuffer::new();
+    response.write(&mut buffer).unwrap();
+    buffer.as_slice().to_vec()
+}
+
+// answer the queries arriving on socket with respond
+pub fn serve_udp<F>(socket: UdpSocket, respond: F)
+where
+    F: Fn(&DnsPacket) -> DnsPacket + Send + 'static,
+{
+    thread::spawn(move || loop {
+        let mut query = [0; 512];
+        let (len, src) = socket.recv_from(&mut query).unwrap();
+        socket
+            .send_to(&reply(&query[..len], &respond), src)
+            .unwrap();
+    });
+}
+
+// answer the connections to listener with respond, and count them
+pub fn serve_tcp<F>(listener: TcpListener, respond: F) -> Arc<AtomicUsize>
+where
+    F: Fn(&DnsPacket) -> DnsPacket + Clone + Send + 'static,
+{
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let count = accepted.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            count.fetch_add(1, Ordering::SeqCst);
+            let mut stream = stream.unwrap();
+            let respond = respond.clone();
+            thread::spawn(move || loop {
+                let mut len = [0; 2];
+                if stream.read_exact(&mut len).is_err() {
+                    return;
+                }
+                let mut query = vec![0; u16::from_be_bytes(len) as usize];
+                stream.read_exact(&mut query).unwrap();
+
+                let response = reply(&query, &respond);
+                stream
+                    .write_all(&(response.len() as u16).to_be_bytes())
+                    .unwrap();
+                stream.write_all(&response).unwrap();
+            });
+        }
+    });
+    accepted
+}
+
+// a udp nameserver on a local port answering every question with ADDR
+pub fn nameserver() -> u16 {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = socket.local_addr().unwrap().port();
+    serve_udp(socket, |query| authoritative(query, ADDR));
+    port
+}
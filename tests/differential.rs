@@ -0,0 +1,494 @@
+// parses the same packets with this crate and hickory-proto and flags every
+// place the two disagree. the corpus is a set of messages hickory encodes,
+// the same messages as this crate writes them back out, and any packets in
+// the directory named by RECURSOR_CORPUS, either raw or as the hex dumps
+// --capture writes
+//
+// RECURSOR_CORPUS=captures cargo test --test differential
+
+use std::{
+    env, fs,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use hickory_proto::{
+    op::{Edns, Message, MessageType, OpCode, Query},
+    rr::{
+        dnssec::{
+            rdata::{DNSSECRData, DNSKEY, DS, NSEC, NSEC3, NSEC3PARAM, RRSIG},
+            Algorithm, DigestType, Nsec3HashAlgorithm,
+        },
+        rdata::{
+            svcb::{Alpn, SvcParamKey, SvcParamValue, SVCB},
+            tlsa::{CertUsage, Matching, Selector},
+            A, AAAA, CAA, CNAME, HINFO, HTTPS, MX, NS, NULL, PTR, SOA, SRV, TLSA, TXT,
+        },
+        DNSClass, Name, RData, Record, RecordType,
+    },
+    serialize::binary::{BinEncodable, BinEncoder, EncodeMode},
+};
+use recursor::{
+    buffer::{NameWriter, PacketBuffer},
+    dns::{packet::DnsPacket, question::QueryType, record::DnsRecord},
+};
+
+// owner, type, class, ttl and rdata in canonical form (RFC 4034 6.2):
+// names uncompressed and lowercased
+type Rr = (String, u16, u16, u32, Vec<u8>);
+
+#[test]
+fn matches_hickory() {
+    let mut divergences = Vec::new();
+    for (source, packet) in corpus() {
+        if let Err(e) = compare(&packet) {
+            divergences.push(format!("{}: {}", source, e));
+        }
+    }
+
+    assert!(
+        divergences.is_empty(),
+        "{} packets parsed differently:\n{}",
+        divergences.len(),
+        divergences.join("\n")
+    );
+}
+
+fn compare(packet: &[u8]) -> Result<(), String> {
+    let theirs = Message::from_vec(packet);
+    let mut buf = PacketBuffer::with_len(packet.len());
+    buf.buf.copy_from_slice(packet);
+    let ours = DnsPacket::read(&mut buf);
+
+    let (ours, theirs) = match (ours, theirs) {
+        (Ok(ours), Ok(theirs)) => (ours, theirs),
+        (Err(_), Err(_)) => return Ok(()),
+        (Ok(_), Err(e)) => return Err(format!("only hickory rejects it: {}", e)),
+        (Err(e), Ok(_)) => return Err(format!("only we reject it: {}", e)),
+    };
+
+    let header = &ours.header;
+    let expected = (
+        theirs.id(),
+        theirs.message_type() == MessageType::Response,
+        u8::from(theirs.op_code()),
+        theirs.authoritative(),
+        theirs.truncated(),
+        theirs.recursion_desired(),
+        theirs.recursion_available(),
+        (u16::from(theirs.response_code()) & 0x0F) as u8,
+    );
+    let actual = (
+        header.id,
+        header.qr,
+        u8::from(header.opcode),
+        header.aa,
+        header.tc,
+        header.rd,
+        header.ra,
+        u8::from(header.rcode),
+    );
+    if actual != expected {
+        return Err(format!("header {:?}, hickory {:?}", actual, expected));
+    }
+
+    let questions: Vec<_> = ours
+        .questions
+        .iter()
+        .map(|q| (q.qname.clone(), u16::from(q.qtype), u16::from(q.qclass)))
+        .collect();
+    let expected: Vec<_> = theirs
+        .queries()
+        .iter()
+        .map(|q| {
+            let class = u16::from(q.query_class());
+            (name(q.name()), u16::from(q.query_type()), class)
+        })
+        .collect();
+    if questions != expected {
+        return Err(format!("questions {:?}, hickory {:?}", questions, expected));
+    }
+
+    // hickory takes the OPT record out of the additionals
+    let payload = ours.edns_payload().map(|size| size.max(512));
+    let expected = theirs.extensions().as_ref().map(Edns::max_payload);
+    if payload != expected {
+        return Err(format!(
+            "edns payload {:?}, hickory {:?}",
+            payload, expected
+        ));
+    }
+
+    let sections = [
+        ("answer", &ours.answers, theirs.answers()),
+        ("authority", &ours.authorities, theirs.name_servers()),
+        ("additional", &ours.additionals, theirs.additionals()),
+    ];
+    for (section, ours, theirs) in sections {
+        let ours: Vec<Rr> = ours
+            .iter()
+            .filter(|record| match record {
+                DnsRecord::UNKOWN { qtype, .. } => QueryType::from(*qtype) != QueryType::OPT,
+                _ => true,
+            })
+            .map(ours_rr)
+            .collect::<Result<_, _>>()?;
+        let theirs: Vec<Rr> = theirs.iter().map(theirs_rr).collect::<Result<_, _>>()?;
+
+        if ours.len() != theirs.len() {
+            return Err(format!(
+                "{} records in the {}, hickory has {}",
+                ours.len(),
+                section,
+                theirs.len()
+            ));
+        }
+
+        for (ours, theirs) in ours.iter().zip(&theirs) {
+            if ours != theirs {
+                return Err(format!("{} {:?}, hickory {:?}", section, ours, theirs));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// write the record out and read it back field by field, expanding the
+// names records of the original types compress
+fn ours_rr(record: &DnsRecord) -> Result<Rr, String> {
+    let mut buf = PacketBuffer::with_len(PacketBuffer::MAX_LEN);
+    record.write(&mut buf, &mut NameWriter::new())?;
+    buf.seek(0);
+
+    let mut owner = String::new();
+    buf.read_qname(&mut owner)?;
+    let qtype = buf.read_u16()?;
+    let class = buf.read_u16()?;
+    let ttl = buf.read_u32()?;
+    let len = buf.read_u16()? as usize;
+    let start = buf.pos();
+
+    let mut rdata = Vec::new();
+    match QueryType::from(qtype) {
+        QueryType::NS | QueryType::CNAME | QueryType::PTR => read_name(&mut buf, &mut rdata)?,
+        QueryType::MX => {
+            rdata.extend(buf.read_u16()?.to_be_bytes());
+            read_name(&mut buf, &mut rdata)?;
+        }
+        QueryType::SOA => {
+            read_name(&mut buf, &mut rdata)?;
+            read_name(&mut buf, &mut rdata)?;
+            rdata.extend(buf.get_range(buf.pos(), buf.pos() + 20)?);
+        }
+        _ => rdata.extend(buf.get_range(start, start + len)?),
+    }
+
+    Ok((owner, qtype, class, ttl, rdata))
+}
+
+// read a possibly compressed name and add it to rdata uncompressed
+fn read_name(buf: &mut PacketBuffer, rdata: &mut Vec<u8>) -> Result<(), &'static str> {
+    let mut name = String::new();
+    buf.read_qname(&mut name)?;
+    rdata.extend(wire_name(&name));
+    Ok(())
+}
+
+// hickory still compresses the names of a SOA in canonical form, so the
+// rdata of the original types is put together from their fields
+fn theirs_rr(record: &Record) -> Result<Rr, String> {
+    let mut rdata = Vec::new();
+    match record.data() {
+        Some(RData::NS(NS(host)) | RData::CNAME(CNAME(host)) | RData::PTR(PTR(host))) => {
+            rdata.extend(wire_name(&name(host)));
+        }
+        Some(RData::MX(mx)) => {
+            rdata.extend(mx.preference().to_be_bytes());
+            rdata.extend(wire_name(&name(mx.exchange())));
+        }
+        Some(RData::SOA(soa)) => {
+            rdata.extend(wire_name(&name(soa.mname())));
+            rdata.extend(wire_name(&name(soa.rname())));
+            rdata.extend(soa.serial().to_be_bytes());
+            rdata.extend(soa.refresh().to_be_bytes());
+            rdata.extend(soa.retry().to_be_bytes());
+            rdata.extend(soa.expire().to_be_bytes());
+            rdata.extend(soa.minimum().to_be_bytes());
+        }
+        Some(data) => {
+            let mut encoder = BinEncoder::with_mode(&mut rdata, EncodeMode::Signing);
+            data.emit(&mut encoder).map_err(|e| e.to_string())?;
+        }
+        None => {}
+    }
+
+    Ok((
+        name(record.name()),
+        u16::from(record.record_type()),
+        u16::from(record.dns_class()),
+        record.ttl(),
+        rdata,
+    ))
+}
+
+// names the way this crate keeps them, lowercase without the trailing dot
+fn name(name: &Name) -> String {
+    name.to_ascii().trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn wire_name(name: &str) -> Vec<u8> {
+    let mut wire = Vec::new();
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        wire.push(label.len() as u8);
+        wire.extend(label.to_ascii_lowercase().as_bytes());
+    }
+    wire.push(0);
+    wire
+}
+
+fn corpus() -> Vec<(String, Vec<u8>)> {
+    let mut corpus = Vec::new();
+    for (i, message) in messages().into_iter().enumerate() {
+        let packet = message.to_vec().expect("hickory failed to encode");
+
+        // the same message as we write it, if we can read it at all
+        let mut buf = PacketBuffer::with_len(packet.len());
+        buf.buf.copy_from_slice(&packet);
+        if let Ok(mut ours) = DnsPacket::read(&mut buf) {
+            let mut out = PacketBuffer::with_len(PacketBuffer::MAX_LEN);
+            if ours.write(&mut out).is_ok() {
+                corpus.push((format!("message {} rewritten", i), out.as_slice().to_vec()));
+            }
+        }
+
+        corpus.push((format!("message {}", i), packet));
+    }
+
+    if let Ok(dir) = env::var("RECURSOR_CORPUS") {
+        let entries = fs::read_dir(&dir).expect("RECURSOR_CORPUS is not a directory");
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(data) = fs::read(&path) else {
+                continue;
+            };
+            let packet = match String::from_utf8(data.clone()) {
+                Ok(text) if text.starts_with("source:") => unhexdump(&text),
+                _ => data,
+            };
+            corpus.push((path.display().to_string(), packet));
+        }
+    }
+
+    corpus
+}
+
+// the bytes of a --capture hex dump, which follow the offset on each line
+fn unhexdump(dump: &str) -> Vec<u8> {
+    dump.lines()
+        .filter_map(|line| line.split_once("  "))
+        .filter(|(offset, _)| u32::from_str_radix(offset, 16).is_ok())
+        .flat_map(|(_, rest)| {
+            rest.split(' ')
+                .take_while(|byte| !byte.is_empty())
+                .filter_map(|byte| u8::from_str_radix(byte, 16).ok())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn messages() -> Vec<Message> {
+    let n = |name: &str| Name::from_ascii(name).unwrap();
+    let owner = n("example.com.");
+    let record = |rdata: RData| Record::from_rdata(owner.clone(), 3600, rdata);
+    let dnssec = |rdata: DNSSECRData| record(RData::DNSSEC(rdata));
+
+    let answers = vec![
+        record(RData::A(A(Ipv4Addr::new(93, 184, 216, 34)))),
+        record(RData::AAAA(AAAA(Ipv6Addr::new(
+            0x2606, 0x2800, 0x220, 1, 0, 0, 0, 0x248,
+        )))),
+        record(RData::NS(NS(n("a.iana-servers.net.")))),
+        record(RData::CNAME(CNAME(n("www.example.com.")))),
+        record(RData::PTR(PTR(n("host.example.com.")))),
+        record(RData::MX(MX::new(10, n("mail.example.com.")))),
+        record(RData::SOA(SOA::new(
+            n("ns.icann.org."),
+            n("noc.dns.icann.org."),
+            2024010101,
+            7200,
+            3600,
+            1209600,
+            3600,
+        ))),
+        record(RData::TXT(TXT::new(vec![
+            "v=spf1 -all".to_string(),
+            String::new(),
+            "x".repeat(255),
+        ]))),
+        record(RData::SRV(SRV::new(10, 60, 5060, n("sip.example.com.")))),
+        record(RData::HINFO(HINFO::new(
+            "RFC8482".to_string(),
+            String::new(),
+        ))),
+        record(RData::CAA(CAA::new_issue(
+            false,
+            Some(n("letsencrypt.org")),
+            Vec::new(),
+        ))),
+        record(RData::TLSA(TLSA::new(
+            CertUsage::DomainIssued,
+            Selector::Spki,
+            Matching::Sha256,
+            vec![0xAB; 32],
+        ))),
+        record(RData::SVCB(SVCB::new(
+            1,
+            n("svc.example.com."),
+            vec![
+                (
+                    SvcParamKey::Alpn,
+                    SvcParamValue::Alpn(Alpn(vec!["h2".to_string()])),
+                ),
+                (SvcParamKey::Port, SvcParamValue::Port(8443)),
+            ],
+        ))),
+        record(RData::HTTPS(HTTPS(SVCB::new(
+            1,
+            Name::root(),
+            vec![(
+                SvcParamKey::Alpn,
+                SvcParamValue::Alpn(Alpn(vec!["h3".to_string(), "h2".to_string()])),
+            )],
+        )))),
+        record(RData::Unknown {
+            code: RecordType::Unknown(65280),
+            rdata: NULL::with(vec![1, 2, 3, 4]),
+        }),
+    ];
+
+    let signed = vec![
+        dnssec(DNSSECRData::DS(DS::new(
+            60485,
+            Algorithm::RSASHA256,
+            DigestType::SHA256,
+            vec![0x2B; 32],
+        ))),
+        dnssec(DNSSECRData::DNSKEY(DNSKEY::new(
+            true,
+            true,
+            false,
+            Algorithm::ECDSAP256SHA256,
+            vec![0x11; 64],
+        ))),
+        dnssec(DNSSECRData::RRSIG(RRSIG::new(
+            RecordType::A,
+            Algorithm::ECDSAP256SHA256,
+            2,
+            3600,
+            1_700_000_000,
+            1_690_000_000,
+            60485,
+            n("example.com."),
+            vec![0x22; 64],
+        ))),
+        dnssec(DNSSECRData::NSEC(NSEC::new(
+            n("a.example.com."),
+            vec![
+                RecordType::A,
+                RecordType::MX,
+                RecordType::RRSIG,
+                RecordType::CAA,
+            ],
+        ))),
+        dnssec(DNSSECRData::NSEC3(NSEC3::new(
+            Nsec3HashAlgorithm::SHA1,
+            true,
+            10,
+            vec![0xAA, 0xBB],
+            vec![0x33; 20],
+            vec![RecordType::A, RecordType::AAAA, RecordType::RRSIG],
+        ))),
+        dnssec(DNSSECRData::NSEC3PARAM(NSEC3PARAM::new(
+            Nsec3HashAlgorithm::SHA1,
+            false,
+            0,
+            Vec::new(),
+        ))),
+    ];
+
+    let mut messages = Vec::new();
+
+    // a query with EDNS, as we send them upstream
+    let mut query = Message::new();
+    query
+        .set_id(1234)
+        .set_recursion_desired(true)
+        .add_query(Query::query(owner.clone(), RecordType::A));
+    let mut edns = Edns::new();
+    edns.set_max_payload(1232);
+    query.set_edns(edns);
+    messages.push(query);
+
+    // every record type we know in the answers
+    let mut response = Message::new();
+    response
+        .set_id(4321)
+        .set_message_type(MessageType::Response)
+        .set_op_code(OpCode::Query)
+        .set_authoritative(true)
+        .set_recursion_desired(true)
+        .set_recursion_available(true)
+        .add_query(Query::query(owner.clone(), RecordType::ANY))
+        .add_answers(answers);
+    messages.push(response);
+
+    // the DNSSEC types, in the authorities
+    let mut response = Message::new();
+    response
+        .set_id(7)
+        .set_message_type(MessageType::Response)
+        .add_query(Query::query(owner.clone(), RecordType::DS))
+        .add_name_servers(signed);
+    messages.push(response);
+
+    // a referral with glue, names compressed against each other
+    let mut referral = Message::new();
+    referral
+        .set_id(8)
+        .set_message_type(MessageType::Response)
+        .add_query(Query::query(n("www.example.com."), RecordType::A))
+        .add_name_servers(["a", "b", "c"].map(|ns| {
+            Record::from_rdata(
+                n("com."),
+                172800,
+                RData::NS(NS(n(&format!("{}.gtld-servers.net.", ns)))),
+            )
+        }))
+        .add_additionals(["a", "b", "c"].map(|ns| {
+            Record::from_rdata(
+                n(&format!("{}.gtld-servers.net.", ns)),
+                172800,
+                RData::A(A(Ipv4Addr::new(192, 5, 6, 30))),
+            )
+        }));
+    messages.push(referral);
+
+    // a chaos class question and answer
+    let mut chaos = Message::new();
+    let mut question = Query::query(n("version.bind."), RecordType::TXT);
+    question.set_query_class(DNSClass::CH);
+    let mut version = Record::from_rdata(
+        n("version.bind."),
+        0,
+        RData::TXT(TXT::new(vec!["recursor".to_string()])),
+    );
+    version.set_dns_class(DNSClass::CH);
+    chaos
+        .set_id(9)
+        .set_message_type(MessageType::Response)
+        .add_query(question)
+        .add_answer(version);
+    messages.push(chaos);
+
+    messages
+}
@@ -0,0 +1,152 @@
+// dnscrypt upstreams, against a local server that signs its own
+// certificate and answers every query with 127.0.0.1
+#![cfg(feature = "dnscrypt")]
+
+use std::{
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use common::query;
+use crypto_box::{aead::Aead, ChaChaBox, Nonce, PublicKey, SalsaBox, SecretKey};
+use ed25519_dalek::{Signer, SigningKey};
+use recursor::{dns::packet::DnsPacket, dnscrypt::DnsCryptClient};
+
+mod common;
+
+const PROVIDER: &str = "2.dnscrypt-cert.dns.test";
+const CLIENT_MAGIC: &[u8; 8] = b"testmagc";
+
+fn provider_key() -> SigningKey {
+    SigningKey::from_bytes(&[1; 32])
+}
+
+// a server using es-version version, signed with signer, and the number
+// of certificate queries it answered
+fn server(version: u16, signer: SigningKey) -> (SocketAddr, Arc<AtomicUsize>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    let fetches = Arc::new(AtomicUsize::new(0));
+
+    let resolver_key = SecretKey::from_bytes([7; 32]);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32;
+
+    let mut signed = Vec::new();
+    signed.extend_from_slice(resolver_key.public_key().as_bytes());
+    signed.extend_from_slice(CLIENT_MAGIC);
+    signed.extend_from_slice(&1u32.to_be_bytes()); // serial
+    signed.extend_from_slice(&(now - 60).to_be_bytes());
+    signed.extend_from_slice(&(now + 3600).to_be_bytes());
+
+    let mut cert = b"DNSC".to_vec();
+    cert.extend_from_slice(&version.to_be_bytes());
+    cert.extend_from_slice(&[0, 0]);
+    cert.extend_from_slice(&signer.sign(&signed).to_bytes());
+    cert.extend_from_slice(&signed);
+
+    let count = fetches.clone();
+    thread::spawn(move || loop {
+        let mut packet = [0; 4096];
+        let (len, src) = socket.recv_from(&mut packet).unwrap();
+        let packet = &packet[..len];
+
+        let response = if packet.starts_with(CLIENT_MAGIC) {
+            answer(packet, version, &resolver_key)
+        } else {
+            count.fetch_add(1, Ordering::SeqCst);
+            cert_response(packet, &cert)
+        };
+        socket.send_to(&response, src).unwrap();
+    });
+
+    (addr, fetches)
+}
+
+// the certificate as the one TXT record answering query
+fn cert_response(query: &[u8], cert: &[u8]) -> Vec<u8> {
+    let mut response = query[..2].to_vec();
+    response.extend_from_slice(&[0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0]);
+    response.extend_from_slice(&query[12..]);
+    response.extend_from_slice(&[0xc0, 0x0c, 0, 16, 0, 1, 0, 0, 0x0e, 0x10]);
+    response.extend_from_slice(&(cert.len() as u16 + 1).to_be_bytes());
+    response.push(cert.len() as u8);
+    response.extend_from_slice(cert);
+    response
+}
+
+// decrypt a query, and encrypt an answer with 127.0.0.1 for it
+fn answer(packet: &[u8], version: u16, resolver_key: &SecretKey) -> Vec<u8> {
+    let client_key = PublicKey::from_slice(&packet[8..40]).unwrap();
+    let mut nonce = [0; 24];
+    nonce[..12].copy_from_slice(&packet[40..52]);
+
+    let crypt = |nonce: &[u8; 24], data: &[u8], encrypt: bool| {
+        let nonce = Nonce::from_slice(nonce);
+        match (version, encrypt) {
+            (1, true) => SalsaBox::new(&client_key, resolver_key).encrypt(nonce, data),
+            (1, false) => SalsaBox::new(&client_key, resolver_key).decrypt(nonce, data),
+            (_, true) => ChaChaBox::new(&client_key, resolver_key).encrypt(nonce, data),
+            (_, false) => ChaChaBox::new(&client_key, resolver_key).decrypt(nonce, data),
+        }
+        .unwrap()
+    };
+
+    let mut query = crypt(&nonce, &packet[52..], false);
+    assert_eq!(query.len() % 64, 0);
+    assert!(query.len() >= 256);
+    let end = query.iter().rposition(|byte| *byte == 0x80).unwrap();
+    query.truncate(end);
+
+    let mut response = query;
+    response[2] |= 0x80; // qr
+    response[7] = 1; // ancount
+    response.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 127, 0, 0, 1]);
+    response.push(0x80);
+    response.resize(response.len().div_ceil(64) * 64, 0);
+
+    nonce[12..].copy_from_slice(&[9; 12]);
+    let mut message = b"r6fnvWj8".to_vec();
+    message.extend_from_slice(&nonce);
+    message.extend_from_slice(&crypt(&nonce, &response, true));
+    message
+}
+
+#[test]
+fn exchanges_with_both_constructions() {
+    let key = provider_key().verifying_key().to_bytes();
+
+    for version in [1, 2] {
+        let (server, fetches) = server(version, provider_key());
+        let client = DnsCryptClient::new();
+
+        for id in 0..2 {
+            let (mut buffer, _) = client.exchange(&query(id), server, PROVIDER, &key).unwrap();
+            let response = DnsPacket::read(&mut buffer).unwrap();
+            assert_eq!(response.header.id, id);
+            assert_eq!(response.get_any_a(), Some(Ipv4Addr::LOCALHOST));
+        }
+
+        // the certificate is kept between queries
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        assert_eq!(client.certs_len(), 1);
+    }
+}
+
+#[test]
+fn rejects_certificates_signed_by_others() {
+    let (server, _) = server(2, SigningKey::from_bytes(&[2; 32]));
+    let client = DnsCryptClient::new();
+
+    let key = provider_key().verifying_key().to_bytes();
+    let result = client.exchange(&query(1), server, PROVIDER, &key);
+    assert_eq!(result.err(), Some("no valid certificate"));
+    assert_eq!(client.certs_len(), 0);
+}
@@ -0,0 +1,164 @@
+// dns over https requests, served over a plain local tcp connection by a
+// resolver whose only layer answers every query itself
+
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream, UdpSocket},
+    thread,
+};
+
+use common::query;
+use recursor::{
+    buffer::PacketBuffer,
+    dns::{packet::DnsPacket, question::DnsClass, record::DnsRecord},
+    layer::{Layer, Next, Query},
+    rootserver, Resolver, Transport,
+};
+
+mod common;
+
+// answers every question with 127.0.0.1
+struct Localhost;
+
+impl Layer for Localhost {
+    fn handle(&self, query: &Query, _: Next) -> Result<DnsPacket, &'static str> {
+        let mut packet = DnsPacket::new();
+        packet.add_answer(DnsRecord::A {
+            domain: query.question.qname.clone(),
+            addr: Ipv4Addr::LOCALHOST,
+            class: DnsClass::IN,
+            ttl: 300,
+        });
+        Ok(packet)
+    }
+}
+
+// a connection to a resolver serving dns over https on it
+fn connect() -> (TcpStream, thread::JoinHandle<Resolver>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+
+    let server = thread::spawn(move || {
+        let mut resolver = Resolver::new(rootserver::A);
+        resolver.layers.push(Box::new(Localhost));
+
+        let (mut stream, peer) = listener.accept().unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        resolver
+            .handle_https(&mut stream, peer, Some(peer.ip()), &socket)
+            .unwrap();
+        resolver
+    });
+
+    (client, server)
+}
+
+// the status, head and body of the next response
+fn read_response(stream: &mut TcpStream) -> (u16, String, Vec<u8>) {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8(head).unwrap();
+
+    let status = head[9..12].parse().unwrap();
+    let len = head
+        .lines()
+        .find_map(|line| line.strip_prefix("Content-Length: "))
+        .unwrap()
+        .parse()
+        .unwrap();
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).unwrap();
+
+    (status, head, body)
+}
+
+fn answer(body: Vec<u8>) -> DnsPacket {
+    let mut buffer = PacketBuffer::with_len(body.len());
+    buffer.buf.copy_from_slice(&body);
+    DnsPacket::read(&mut buffer).unwrap()
+}
+
+#[test]
+fn answers_get_and_post() {
+    let (mut stream, server) = connect();
+
+    // id 0xfbff, whose encoding needs the url safe alphabet
+    let encoded: String = query(0xfbff)
+        .chunks(3)
+        .flat_map(|chunk| {
+            const ALPHABET: &[u8] =
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+            let mut bits = [0; 3];
+            bits[..chunk.len()].copy_from_slice(chunk);
+            let bits = u32::from_be_bytes([0, bits[0], bits[1], bits[2]]);
+            (0..=chunk.len()).map(move |i| ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char)
+        })
+        .collect();
+    write!(
+        stream,
+        "GET /dns-query?dns={} HTTP/1.1\r\nHost: dns.test\r\n\r\n",
+        encoded
+    )
+    .unwrap();
+
+    let (status, head, body) = read_response(&mut stream);
+    assert_eq!(status, 200);
+    assert!(head.contains("Content-Type: application/dns-message\r\n"));
+    assert!(head.contains("Cache-Control: max-age=300\r\n"));
+    let response = answer(body);
+    assert_eq!(response.header.id, 0xfbff);
+    assert_eq!(response.get_any_a(), Some(Ipv4Addr::LOCALHOST));
+
+    // on the same connection, which the client then closes
+    let query = query(7);
+    write!(
+        stream,
+        "POST /dns-query HTTP/1.1\r\nContent-Type: application/dns-message\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        query.len()
+    )
+    .unwrap();
+    stream.write_all(&query).unwrap();
+
+    let (status, head, body) = read_response(&mut stream);
+    assert_eq!(status, 200);
+    assert!(head.contains("Connection: close\r\n"));
+    assert_eq!(answer(body).header.id, 7);
+
+    let resolver = server.join().unwrap();
+    assert_eq!(resolver.ingress.get(Transport::Https), 2);
+}
+
+#[test]
+fn refuses_other_requests() {
+    let (mut stream, server) = connect();
+
+    stream
+        .write_all(b"GET /resolve?name=example.com HTTP/1.1\r\n\r\n")
+        .unwrap();
+    assert_eq!(read_response(&mut stream).0, 404);
+
+    stream
+        .write_all(b"GET /dns-query?dns=not*base64 HTTP/1.1\r\n\r\n")
+        .unwrap();
+    assert_eq!(read_response(&mut stream).0, 400);
+
+    stream
+        .write_all(
+            b"POST /dns-query HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 2\r\n\r\nhi",
+        )
+        .unwrap();
+    assert_eq!(read_response(&mut stream).0, 415);
+
+    stream
+        .write_all(b"DELETE /dns-query HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    assert_eq!(read_response(&mut stream).0, 405);
+
+    let resolver = server.join().unwrap();
+    assert_eq!(resolver.ingress.get(Transport::Https), 0);
+}
@@ -0,0 +1,97 @@
+// hosts read from ISC dhcpd lease files, which log every lease event
+
+use std::{
+    env, fs,
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+};
+
+use recursor::leases::Leases;
+
+// the hosts in a lease file with contents
+fn hosts(name: &str, contents: &str) -> Vec<(String, IpAddr)> {
+    let path: PathBuf = env::temp_dir().join(format!("recursor-{}-{}", name, std::process::id()));
+    fs::write(&path, contents).unwrap();
+    let hosts = Leases::new(path.clone(), "lan").hosts();
+    fs::remove_file(path).unwrap();
+    hosts
+}
+
+fn host(name: &str, last: u8) -> (String, IpAddr) {
+    (
+        name.to_string(),
+        IpAddr::V4(Ipv4Addr::new(192, 168, 1, last)),
+    )
+}
+
+#[test]
+fn serves_active_leases_until_they_end() {
+    let leases = r#"
+lease 192.168.1.20 {
+  starts 4 2024/01/04 10:00:00;
+  ends 3 2999/01/01 00:00:00;
+  binding state active;
+  next binding state free;
+  client-hostname "printer";
+}
+lease 192.168.1.21 {
+  ends never;
+  binding state active;
+  client-hostname "nas";
+}
+lease 192.168.1.22 {
+  ends epoch 32503680000; # Tue Jan 01 00:00:00 3000
+  binding state active;
+  client-hostname "tv";
+}
+lease 192.168.1.23 {
+  ends 4 2024/01/04 22:00:00;
+  binding state active;
+  client-hostname "laptop";
+}
+lease 192.168.1.24 {
+  ends epoch 1704405600; # Thu Jan 04 22:00:00 2024
+  binding state active;
+  client-hostname "phone";
+}
+"#;
+
+    assert_eq!(
+        hosts("ends", leases),
+        [host("printer", 20), host("nas", 21), host("tv", 22)]
+    );
+}
+
+#[test]
+fn forgets_freed_and_abandoned_leases() {
+    let leases = r#"
+lease 192.168.1.20 {
+  ends never;
+  binding state active;
+  client-hostname "printer";
+}
+lease 192.168.1.30 {
+  ends never;
+  binding state abandoned;
+  client-hostname "ghost";
+}
+lease 192.168.1.20 {
+  ends never;
+  binding state free;
+}
+lease 192.168.1.40 {
+  ends never;
+  binding state active;
+  client-hostname "old-laptop";
+}
+lease 192.168.1.40 {
+  ends never;
+  binding state active;
+  client-hostname "new-laptop";
+}
+"#;
+
+    // printer's address was freed, and 192.168.1.40 went to another
+    // client since, which doesn't inherit the old name
+    assert_eq!(hosts("states", leases), [host("new-laptop", 40)]);
+}
@@ -0,0 +1,204 @@
+// regression net for the resolution algorithm, resolving a curated list of
+// real names from the root servers down
+#![cfg(feature = "net-tests")]
+
+use std::{net::UdpSocket, time::Duration};
+
+use recursor::{
+    dns::{
+        header::ResultCode,
+        packet::DnsPacket,
+        question::QueryType,
+        record::{DnsRecord, SvcParam},
+    },
+    rootserver, Resolver,
+};
+
+fn resolve(qname: &str, qtype: QueryType) -> DnsPacket {
+    let socket = UdpSocket::bind(("0.0.0.0", 0)).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    let resolver = Resolver::new(rootserver::A);
+    resolver
+        .recursive_lookup(qname, qtype, resolver.root, &socket)
+        .unwrap_or_else(|e| panic!("{} {:?} failed: {}", qname, qtype, e))
+}
+
+fn assert_answered(qname: &str, qtype: QueryType) {
+    let response = resolve(qname, qtype);
+    assert_eq!(response.header.rcode, ResultCode::NOERROR, "{}", qname);
+    assert!(
+        !response.answers.is_empty(),
+        "{} {:?} has no answers",
+        qname,
+        qtype
+    );
+}
+
+#[test]
+#[ignore]
+fn a_records() {
+    for qname in ["google.com", "wikipedia.org", "nathanielfernandes.ca"] {
+        let response = resolve(qname, QueryType::A);
+        assert!(
+            response
+                .answers
+                .iter()
+                .any(|r| matches!(r, DnsRecord::A { .. })),
+            "{} has no A answer",
+            qname
+        );
+    }
+}
+
+#[test]
+#[ignore]
+fn aaaa_records() {
+    for qname in ["google.com", "cloudflare.com"] {
+        let response = resolve(qname, QueryType::AAAA);
+        assert!(
+            response
+                .answers
+                .iter()
+                .any(|r| matches!(r, DnsRecord::AAAA { .. })),
+            "{} has no AAAA answer",
+            qname
+        );
+    }
+}
+
+#[test]
+#[ignore]
+fn mx_and_ns_records() {
+    assert_answered("gmail.com", QueryType::MX);
+    assert_answered("github.com", QueryType::NS);
+}
+
+#[test]
+#[ignore]
+fn txt_records() {
+    // google.com publishes its SPF policy as one of many TXT records
+    let response = resolve("google.com", QueryType::TXT);
+    assert!(
+        response.answers.iter().any(|r| match r {
+            DnsRecord::TXT { data, .. } => data.concat().starts_with(b"v=spf1"),
+            _ => false,
+        }),
+        "google.com has no SPF record"
+    );
+}
+
+#[test]
+#[ignore]
+fn srv_records() {
+    let socket = UdpSocket::bind(("0.0.0.0", 0)).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    let resolver = Resolver::new(rootserver::A);
+    let targets = resolver
+        .lookup_srv("_imaps._tcp.gmail.com", &socket)
+        .unwrap();
+    assert_eq!(targets.first().map(|(_, port)| *port), Some(993));
+}
+
+#[test]
+#[ignore]
+fn cname_chains() {
+    // both are served as a CNAME into a CDN
+    for qname in ["www.microsoft.com", "www.apple.com"] {
+        let response = resolve(qname, QueryType::A);
+        assert!(
+            response
+                .answers
+                .iter()
+                .any(|r| matches!(r, DnsRecord::CNAME { .. })),
+            "{} has no CNAME answer",
+            qname
+        );
+    }
+}
+
+#[test]
+#[ignore]
+fn dnssec_signed_domains() {
+    for qname in ["isc.org", "ietf.org", "cloudflare.com"] {
+        assert_answered(qname, QueryType::A);
+    }
+}
+
+#[test]
+#[ignore]
+fn ds_records() {
+    // served by the parent zone, vouching for the child's keys
+    for qname in ["isc.org", "cloudflare.com"] {
+        let response = resolve(qname, QueryType::DS);
+        assert!(
+            response
+                .answers
+                .iter()
+                .any(|r| matches!(r, DnsRecord::DS { .. })),
+            "{} has no DS answer",
+            qname
+        );
+    }
+}
+
+#[test]
+#[ignore]
+fn tlsa_records() {
+    // DANE for the smtp server of a zone that publishes it
+    let response = resolve("_25._tcp.mail.ietf.org", QueryType::TLSA);
+    assert!(
+        response
+            .answers
+            .iter()
+            .any(|r| matches!(r, DnsRecord::TLSA { .. })),
+        "mail.ietf.org has no TLSA answer"
+    );
+}
+
+#[test]
+#[ignore]
+fn https_records() {
+    let response = resolve("cloudflare.com", QueryType::HTTPS);
+    assert!(
+        response.answers.iter().any(|r| match r {
+            DnsRecord::HTTPS { params, .. } => params.iter().any(
+                |param| matches!(param, SvcParam::Alpn(alpn) if alpn.iter().any(|id| id == "h2"))
+            ),
+            _ => false,
+        }),
+        "cloudflare.com has no HTTPS answer offering h2"
+    );
+}
+
+#[test]
+#[ignore]
+fn internationalized_names() {
+    // bücher.ch and the .中国 tld, in their punycode form
+    assert_answered("xn--bcher-kva.ch", QueryType::A);
+    assert_answered("xn--fiqs8s", QueryType::NS);
+}
+
+#[test]
+#[ignore]
+fn nonexistent_name() {
+    let response = resolve(
+        "this-name-does-not-exist.nathanielfernandes.ca",
+        QueryType::A,
+    );
+    assert_eq!(response.header.rcode, ResultCode::NXDOMAIN);
+
+    // the zone's SOA carries the negative ttl for the answer
+    assert!(
+        response
+            .authorities
+            .iter()
+            .any(|r| matches!(r, DnsRecord::SOA { .. })),
+        "NXDOMAIN has no SOA"
+    );
+}
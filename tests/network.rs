@@ -0,0 +1,85 @@
+// following the default route, and dropping upstream state when it changes
+
+use std::{
+    net::Ipv4Addr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use recursor::{
+    dns::packet::DnsPacket,
+    layer::{Layer, Next, Query},
+    network::{parse_default_route, DefaultRoute, Watch},
+    rootserver, Resolver,
+};
+
+const TABLE: &str = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+eth0\t00000000\t0100000A\t0003\t0\t0\t100\t00000000\t0\t0\t0
+eth0\t0000000A\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+wwan0\t00000000\t01010101\t0002\t0\t0\t50\t00000000\t0\t0\t0
+";
+
+fn route(interface: &str, gateway: [u8; 4]) -> DefaultRoute {
+    DefaultRoute {
+        interface: interface.to_string(),
+        gateway: Ipv4Addr::from(gateway),
+    }
+}
+
+#[test]
+fn picks_the_default_route_with_the_lowest_metric() {
+    // wwan0 has a lower metric but is down
+    let expected = if cfg!(target_endian = "little") {
+        route("eth0", [10, 0, 0, 1])
+    } else {
+        route("eth0", [1, 0, 0, 10])
+    };
+    assert_eq!(parse_default_route(TABLE), Some(expected));
+
+    let header = TABLE.lines().next().unwrap();
+    assert_eq!(parse_default_route(header), None);
+}
+
+#[test]
+fn reports_changes_after_the_first_route() {
+    let watch = Watch::new();
+    let home = route("wlan0", [192, 168, 1, 1]);
+
+    assert!(!watch.observe(Some(home.clone())));
+    assert!(!watch.observe(Some(home.clone())));
+    assert_eq!(watch.changes(), 0);
+
+    // the interface goes down, then comes back on another network
+    assert!(watch.observe(None));
+    assert!(watch.observe(Some(route("wlan0", [172, 20, 10, 1]))));
+    assert_eq!(watch.changes(), 2);
+    assert_eq!(watch.route(), Some(route("wlan0", [172, 20, 10, 1])));
+}
+
+// counts how often it was told the network changed
+struct Changes(Arc<AtomicUsize>);
+
+impl Layer for Changes {
+    fn handle(&self, query: &Query, next: Next) -> Result<DnsPacket, &'static str> {
+        next.run(query)
+    }
+
+    fn network_changed(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn tells_every_layer_the_network_changed() {
+    let changes = Arc::new(AtomicUsize::new(0));
+    let mut resolver = Resolver::new(rootserver::A);
+    resolver.layers.push(Box::new(Changes(changes.clone())));
+    resolver.layers.push(Box::new(Changes(changes.clone())));
+
+    resolver.network_changed();
+    assert_eq!(changes.load(Ordering::SeqCst), 2);
+}
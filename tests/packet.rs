@@ -0,0 +1,135 @@
+// responses as the resolution loop sees them
+
+use std::net::Ipv4Addr;
+
+use recursor::dns::{
+    header::ResultCode,
+    packet::{DnsPacket, ResponseKind},
+    question::{DnsClass, DnsQuestion, QueryType},
+    record::DnsRecord,
+};
+
+// a response to example.com A with rcode, nothing in it yet
+fn response(rcode: ResultCode) -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.header.qr = true;
+    packet.header.rcode = rcode;
+    packet.add_question(DnsQuestion::new("example.com".to_string(), QueryType::A));
+    packet
+}
+
+fn ns(domain: &str, ns: &str) -> DnsRecord {
+    DnsRecord::NS {
+        domain: domain.to_string(),
+        ns: ns.to_string(),
+        class: DnsClass::IN,
+        ttl: 3600,
+    }
+}
+
+fn soa(domain: &str) -> DnsRecord {
+    DnsRecord::SOA {
+        domain: domain.to_string(),
+        mname: format!("ns1.{}", domain),
+        rname: format!("hostmaster.{}", domain),
+        serial: 1,
+        refresh: 7200,
+        retry: 3600,
+        expire: 1209600,
+        minimum: 300,
+        class: DnsClass::IN,
+        ttl: 3600,
+    }
+}
+
+fn a(domain: &str, addr: Ipv4Addr) -> DnsRecord {
+    DnsRecord::A {
+        domain: domain.to_string(),
+        addr,
+        class: DnsClass::IN,
+        ttl: 300,
+    }
+}
+
+#[test]
+fn classifies_responses() {
+    let mut answer = response(ResultCode::NOERROR);
+    answer.header.aa = true;
+    answer.add_answer(a("example.com", Ipv4Addr::new(192, 0, 2, 1)));
+    assert_eq!(answer.classify(), ResponseKind::Answer);
+
+    let mut referral = response(ResultCode::NOERROR);
+    referral.add_authority(ns("com", "a.gtld-servers.net"));
+    referral.add_additional(a("a.gtld-servers.net", Ipv4Addr::new(192, 5, 6, 30)));
+    assert_eq!(referral.classify(), ResponseKind::Referral);
+
+    // an empty authoritative answer
+    let mut no_data = response(ResultCode::NOERROR);
+    no_data.header.aa = true;
+    assert_eq!(no_data.classify(), ResponseKind::NoData);
+
+    let mut nx_domain = response(ResultCode::NXDOMAIN);
+    nx_domain.header.aa = true;
+    assert_eq!(nx_domain.classify(), ResponseKind::NxDomain);
+
+    // neither an answer nor nameservers for anything above the name
+    let mut lame = response(ResultCode::NOERROR);
+    assert_eq!(lame.classify(), ResponseKind::Lame);
+    lame.add_authority(ns("org", "a0.org.afilias-nst.info"));
+    assert_eq!(lame.classify(), ResponseKind::Lame);
+
+    for rcode in [
+        ResultCode::SERVFAIL,
+        ResultCode::REFUSED,
+        ResultCode::FORMERR,
+    ] {
+        assert_eq!(response(rcode).classify(), ResponseKind::Error);
+    }
+}
+
+#[test]
+fn classifies_a_soa_without_answers_as_no_data() {
+    // not authoritative, e.g. relayed by a forwarder, but the SOA says the
+    // name exists without records of the type
+    let mut no_data = response(ResultCode::NOERROR);
+    no_data.add_authority(soa("example.com"));
+    assert_eq!(no_data.classify(), ResponseKind::NoData);
+
+    // the SOA wins over nameservers next to it, which aren't a referral
+    no_data.add_authority(ns("example.com", "ns1.example.com"));
+    assert_eq!(no_data.classify(), ResponseKind::NoData);
+
+    // while NXDOMAIN carries a SOA too
+    let mut nx_domain = response(ResultCode::NXDOMAIN);
+    nx_domain.add_authority(soa("example.com"));
+    assert_eq!(nx_domain.classify(), ResponseKind::NxDomain);
+}
+
+#[test]
+fn adds_target_glue_once() {
+    let mx = |exchange: &str| DnsRecord::MX {
+        domain: "example.com".to_string(),
+        preference: 10,
+        exchange: exchange.to_string(),
+        class: DnsClass::IN,
+        ttl: 3600,
+    };
+    let mail = a("mail.example.com", Ipv4Addr::new(192, 0, 2, 25));
+    let backup = a("backup.example.com", Ipv4Addr::new(192, 0, 2, 26));
+    let unrelated = a("www.example.com", Ipv4Addr::new(192, 0, 2, 80));
+
+    let mut packet = response(ResultCode::NOERROR);
+    packet.add_answer(mx("mail.example.com"));
+    packet.add_answer(mx("backup.example.com"));
+    // the server sent glue for one of them already
+    packet.add_additional(mail.clone());
+
+    // known twice over, e.g. from the A lookup of each target
+    let known = [mail.clone(), backup.clone(), unrelated, backup.clone()];
+    packet.add_target_glue(&known);
+    packet.add_target_glue(&known);
+
+    assert_eq!(packet.additionals.len(), 2);
+    assert!(packet.additionals[0].same_data(&mail));
+    assert!(packet.additionals[1].same_data(&backup));
+}
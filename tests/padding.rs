@@ -0,0 +1,77 @@
+// EDNS padding of responses over the encrypted transports (RFC 7830)
+
+use std::net::{Ipv4Addr, UdpSocket};
+
+use recursor::{
+    buffer::PacketBuffer,
+    dns::{
+        packet::{DnsPacket, OPTION_PADDING},
+        question::{DnsClass, DnsQuestion, QueryType},
+    },
+    Resolver, Transport,
+};
+
+// the raw response to a CH class query over transport, which the resolver
+// answers itself, with EDNS if edns is set
+fn response(resolver: &Resolver, transport: Transport, edns: bool) -> Vec<u8> {
+    let mut question = DnsQuestion::new("version.bind".to_string(), QueryType::TXT);
+    question.qclass = DnsClass::CH;
+    let mut query = DnsPacket::new();
+    query.header.rd = true;
+    query.add_question(question);
+    if edns {
+        query.add_edns(1232);
+    }
+
+    let mut buffer = PacketBuffer::new();
+    query.write(&mut buffer).unwrap();
+    let len = buffer.pos();
+    buffer.seek(0);
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let answer = resolver
+        .answer(&mut buffer, len, "client", None, transport, &socket)
+        .unwrap();
+    answer.as_slice().to_vec()
+}
+
+fn parse(response: &[u8]) -> DnsPacket {
+    let mut buffer = PacketBuffer::with_len(response.len());
+    buffer.buf.copy_from_slice(response);
+    DnsPacket::read(&mut buffer).unwrap()
+}
+
+#[test]
+fn pads_encrypted_responses_to_the_block_size() {
+    let mut resolver = Resolver::new(Ipv4Addr::LOCALHOST);
+
+    for transport in [Transport::Tls, Transport::Https] {
+        let response = response(&resolver, transport, true);
+        assert_eq!(response.len(), 468);
+
+        let padding = parse(&response).edns_option(OPTION_PADDING).unwrap();
+        assert!(padding.iter().all(|byte| *byte == 0));
+    }
+
+    resolver.padding_block = 64;
+    assert_eq!(response(&resolver, Transport::Tls, true).len(), 64);
+}
+
+#[test]
+fn leaves_other_responses_alone() {
+    let mut resolver = Resolver::new(Ipv4Addr::LOCALHOST);
+
+    // plain transports, and clients without EDNS that can't take an OPT record
+    for (transport, edns) in [
+        (Transport::Udp, true),
+        (Transport::Tcp, true),
+        (Transport::Tls, false),
+    ] {
+        let response = parse(&response(&resolver, transport, edns));
+        assert_eq!(response.edns_option(OPTION_PADDING), None);
+    }
+
+    resolver.padding_block = 0;
+    let response = parse(&response(&resolver, Transport::Https, true));
+    assert_eq!(response.edns_option(OPTION_PADDING), None);
+}
@@ -0,0 +1,118 @@
+// paranoid mode, against a local root that refers example.com to two
+// nameservers on other loopback addresses, sharing its port
+
+use std::net::{Ipv4Addr, UdpSocket};
+
+use common::{authoritative, nameserver, query, serve_udp, ADDR};
+use recursor::{
+    buffer::PacketBuffer,
+    dns::{
+        header::ResultCode,
+        packet::DnsPacket,
+        question::{DnsClass, QueryType},
+        record::DnsRecord,
+    },
+    Resolver, Transport,
+};
+
+mod common;
+
+const NS1: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 2);
+const NS2: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 3);
+
+// the referral to ns1.example.com and ns2.example.com, with glue
+fn referral(query: &DnsPacket) -> DnsPacket {
+    let mut response = DnsPacket::new();
+    for (name, addr) in [("ns1.example.com", NS1), ("ns2.example.com", NS2)] {
+        response.add_authority(DnsRecord::NS {
+            domain: "example.com".to_string(),
+            ns: name.to_string(),
+            class: DnsClass::IN,
+            ttl: 3600,
+        });
+        response.add_additional(DnsRecord::A {
+            domain: name.to_string(),
+            addr,
+            class: DnsClass::IN,
+            ttl: 3600,
+        });
+    }
+    for question in &query.questions {
+        response.add_question(question.clone());
+    }
+    response
+}
+
+// a paranoid resolver whose root refers to nameservers answering with
+// first and second
+fn resolver(first: Ipv4Addr, second: Ipv4Addr) -> Resolver {
+    // the first free port on the root's address that the others have free
+    let (root, ns1, ns2) = loop {
+        let root = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = root.local_addr().unwrap().port();
+        if let (Ok(ns1), Ok(ns2)) = (UdpSocket::bind((NS1, port)), UdpSocket::bind((NS2, port))) {
+            break (root, ns1, ns2);
+        }
+    };
+
+    let mut resolver = Resolver::new(Ipv4Addr::LOCALHOST);
+    resolver.ns_port = root.local_addr().unwrap().port();
+    resolver.loopback_ns = true;
+    resolver.paranoid = true;
+
+    serve_udp(root, referral);
+    serve_udp(ns1, move |query| authoritative(query, first));
+    serve_udp(ns2, move |query| authoritative(query, second));
+    resolver
+}
+
+// the response a client gets for example.com A
+fn answer(resolver: &Resolver) -> DnsPacket {
+    let query = query(1);
+    let mut buffer = PacketBuffer::new();
+    buffer.buf[..query.len()].copy_from_slice(&query);
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut answer = resolver
+        .answer(
+            &mut buffer,
+            query.len(),
+            "client",
+            None,
+            Transport::Udp,
+            &socket,
+        )
+        .unwrap();
+    answer.seek(0);
+    DnsPacket::read(&mut answer).unwrap()
+}
+
+#[test]
+fn answers_when_nameservers_agree() {
+    let resolver = resolver(ADDR, ADDR);
+
+    let response = answer(&resolver);
+    assert_eq!(response.header.rcode, ResultCode::NOERROR);
+    assert_eq!(response.get_any_a(), Some(ADDR));
+}
+
+#[test]
+fn fails_when_nameservers_disagree() {
+    let resolver = resolver(ADDR, Ipv4Addr::new(192, 0, 2, 66));
+
+    let response = answer(&resolver);
+    assert_eq!(response.header.rcode, ResultCode::SERVFAIL);
+    assert!(response.answers.is_empty());
+}
+
+#[test]
+fn fails_without_a_second_nameserver() {
+    // the root answers itself, and has no other nameserver to ask
+    let mut resolver = Resolver::new(Ipv4Addr::LOCALHOST);
+    resolver.ns_port = nameserver();
+    resolver.paranoid = true;
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let result = resolver.resolve("example.com", QueryType::A, &socket);
+    assert_eq!(result.err(), Some("no second nameserver to verify with"));
+}
@@ -0,0 +1,102 @@
+// reading udp datagrams back out of captures in the link layers tcpdump
+// writes with
+#![cfg(feature = "pcap")]
+
+use std::net::SocketAddr;
+
+use recursor::pcap::{datagrams, Datagram};
+
+const QUERY: &[u8] =
+    b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x07example\x03com\x00\x00\x01\x00\x01";
+
+fn udp(src: [u8; 4], dst: [u8; 4], sport: u16, dport: u16, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0x45, 0];
+    packet.extend(((28 + payload.len()) as u16).to_be_bytes());
+    packet.extend([0, 0, 0, 0, 64, 17, 0, 0]);
+    packet.extend(src);
+    packet.extend(dst);
+    packet.extend(sport.to_be_bytes());
+    packet.extend(dport.to_be_bytes());
+    packet.extend(((8 + payload.len()) as u16).to_be_bytes());
+    packet.extend([0, 0]);
+    packet.extend(payload);
+    packet
+}
+
+fn ethernet(ethertype: u16, packet: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0; 12];
+    frame.extend(ethertype.to_be_bytes());
+    frame.extend(packet);
+    frame
+}
+
+// a little endian, microsecond capture
+fn capture(linktype: u32, frames: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend(0xA1B2C3D4u32.to_le_bytes());
+    data.extend(2u16.to_le_bytes());
+    data.extend(4u16.to_le_bytes());
+    data.extend([0; 8]);
+    data.extend(65535u32.to_le_bytes());
+    data.extend(linktype.to_le_bytes());
+    for (i, frame) in frames.iter().enumerate() {
+        data.extend((1_700_000_000 + i as u32).to_le_bytes());
+        data.extend(250u32.to_le_bytes());
+        data.extend((frame.len() as u32).to_le_bytes());
+        data.extend((frame.len() as u32).to_le_bytes());
+        data.extend(frame);
+    }
+    data
+}
+
+#[test]
+fn reads_ethernet_frames() {
+    let query = udp([10, 0, 0, 1], [10, 0, 0, 2], 5555, 53, QUERY);
+    let arp = ethernet(0x0806, &[0; 28]);
+    let data = capture(1, &[ethernet(0x0800, &query), arp]);
+
+    let src: SocketAddr = "10.0.0.1:5555".parse().unwrap();
+    let dst: SocketAddr = "10.0.0.2:53".parse().unwrap();
+    assert_eq!(
+        datagrams(&data).unwrap(),
+        vec![Datagram {
+            secs: 1_700_000_000,
+            nanos: 250_000,
+            src,
+            dst,
+            payload: QUERY.to_vec(),
+        }]
+    );
+}
+
+#[test]
+fn reads_vlan_tagged_and_raw_ip() {
+    let query = udp([10, 0, 0, 1], [10, 0, 0, 2], 5555, 53, QUERY);
+
+    let mut tagged = vec![0; 12];
+    tagged.extend([0x81, 0x00, 0x00, 0x2A, 0x08, 0x00]);
+    tagged.extend(&query);
+    let found = datagrams(&capture(1, &[tagged])).unwrap();
+    assert_eq!(found[0].payload, QUERY);
+
+    let found = datagrams(&capture(101, &[query])).unwrap();
+    assert_eq!(found[0].payload, QUERY);
+}
+
+#[test]
+fn skips_fragments() {
+    let mut fragment = udp([10, 0, 0, 1], [10, 0, 0, 2], 5555, 53, QUERY);
+    fragment[6] = 0x20; // more fragments
+    assert!(datagrams(&capture(101, &[fragment])).unwrap().is_empty());
+}
+
+#[test]
+fn rejects_other_formats() {
+    assert!(datagrams(b"not a capture at all, clearly").is_err());
+    assert!(datagrams(&[0x0A, 0x0D, 0x0D, 0x0A].repeat(8)).is_err());
+
+    // a frame that runs past the end of the file
+    let mut data = capture(101, &[udp([10, 0, 0, 1], [10, 0, 0, 2], 1, 53, QUERY)]);
+    data.truncate(data.len() - 1);
+    assert!(datagrams(&data).is_err());
+}
@@ -0,0 +1,125 @@
+// per transport access control and rate limits
+
+use std::{
+    net::{IpAddr, Ipv4Addr, UdpSocket},
+    thread,
+    time::Duration,
+};
+
+use recursor::{
+    buffer::PacketBuffer,
+    dns::{
+        header::ResultCode,
+        packet::DnsPacket,
+        question::{DnsClass, DnsQuestion, QueryType},
+    },
+    policy::{parse_network, Policies},
+    Resolver, Transport,
+};
+
+fn ip(addr: &str) -> IpAddr {
+    addr.parse().unwrap()
+}
+
+// the rcode a CH class query from addr over transport is answered with,
+// NOTIMP once it's past the policies
+fn rcode(resolver: &Resolver, addr: Option<&str>, transport: Transport) -> ResultCode {
+    let mut question = DnsQuestion::new("version.bind".to_string(), QueryType::TXT);
+    question.qclass = DnsClass::CH;
+    let mut query = DnsPacket::new();
+    query.header.rd = true;
+    query.add_question(question);
+
+    let mut buffer = PacketBuffer::new();
+    query.write(&mut buffer).unwrap();
+    let len = buffer.pos();
+    buffer.seek(0);
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut answer = resolver
+        .answer(&mut buffer, len, "client", addr.map(ip), transport, &socket)
+        .unwrap();
+    answer.seek(0);
+    DnsPacket::read(&mut answer).unwrap().header.rcode
+}
+
+#[test]
+fn parses_networks() {
+    assert_eq!(
+        parse_network("192.168.0.0/16"),
+        Some((ip("192.168.0.0"), 16))
+    );
+    assert_eq!(parse_network("10.0.0.1"), Some((ip("10.0.0.1"), 32)));
+    assert_eq!(parse_network("fd00::/8"), Some((ip("fd00::"), 8)));
+    assert_eq!(parse_network("10.0.0.0/33"), None);
+    assert_eq!(parse_network("lan"), None);
+}
+
+#[test]
+fn refuses_clients_outside_a_transports_networks() {
+    let mut policies = Policies::new();
+    policies.policy(Transport::Udp).allow = vec![
+        (ip("192.168.0.0"), 16),
+        (ip("fd00::"), 8),
+        (ip("0.0.0.0"), 0),
+    ];
+    policies.policy(Transport::Https).allow = vec![(ip("192.168.0.0"), 16)];
+
+    // everyone matches the empty prefix
+    assert!(policies.admits(Transport::Udp, ip("203.0.113.1")));
+
+    assert!(policies.admits(Transport::Https, ip("192.168.1.10")));
+    assert!(policies.admits(Transport::Https, ip("::ffff:192.168.1.10")));
+    assert!(!policies.admits(Transport::Https, ip("192.169.1.10")));
+    assert!(!policies.admits(Transport::Https, ip("fd00::1")));
+
+    // transports without a policy serve everyone
+    assert!(policies.admits(Transport::Tls, ip("203.0.113.1")));
+}
+
+#[test]
+fn limits_each_clients_rate() {
+    let mut policies = Policies::new();
+    policies.policy(Transport::Https).rate = Some(5);
+
+    let client = ip("203.0.113.1");
+    let admitted = (0..10)
+        .filter(|_| policies.admits(Transport::Https, client))
+        .count();
+    assert_eq!(admitted, 5);
+
+    // other clients and transports have allowances of their own
+    assert!(policies.admits(Transport::Https, ip("203.0.113.2")));
+    assert!(policies.admits(Transport::Udp, client));
+
+    // which refill at the rate
+    thread::sleep(Duration::from_millis(250));
+    assert!(policies.admits(Transport::Https, client));
+}
+
+#[test]
+fn answers_refused_queries_with_refused() {
+    let mut policies = Policies::new();
+    policies.policy(Transport::Https).allow = vec![(ip("192.168.0.0"), 16)];
+    policies.policy(Transport::Unix).allow = vec![(ip("192.168.0.0"), 16)];
+
+    let mut resolver = Resolver::new(Ipv4Addr::LOCALHOST);
+    resolver.layers.push(Box::new(policies));
+
+    assert_eq!(
+        rcode(&resolver, Some("192.168.1.10"), Transport::Https),
+        ResultCode::NOTIMP
+    );
+    assert_eq!(
+        rcode(&resolver, Some("10.0.0.1"), Transport::Https),
+        ResultCode::REFUSED
+    );
+    assert_eq!(
+        rcode(&resolver, Some("10.0.0.1"), Transport::Udp),
+        ResultCode::NOTIMP
+    );
+
+    // clients without an address can't be in the ACL's networks
+    assert_eq!(rcode(&resolver, None, Transport::Unix), ResultCode::REFUSED);
+    assert_eq!(rcode(&resolver, None, Transport::Tcp), ResultCode::NOTIMP);
+}
@@ -0,0 +1,203 @@
+// upstream tcp connections are shared by concurrent queries and kept open
+// between them, against a local server that answers pipelined queries in
+// reverse order
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use recursor::{
+    buffer::PacketBuffer,
+    dns::packet::{DnsPacket, OPTION_TCP_KEEPALIVE},
+    pool::TcpPool,
+};
+
+// echoes queries back in pairs, the second of each pair first, and counts
+// the connections it accepts
+fn reversing_server() -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let accepted = Arc::new(AtomicUsize::new(0));
+
+    let count = accepted.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            count.fetch_add(1, Ordering::SeqCst);
+            let mut stream = stream.unwrap();
+            thread::spawn(move || loop {
+                let mut messages = Vec::new();
+                for _ in 0..2 {
+                    let mut len = [0; 2];
+                    if stream.read_exact(&mut len).is_err() {
+                        return;
+                    }
+                    let mut message = vec![0; u16::from_be_bytes(len) as usize];
+                    stream.read_exact(&mut message).unwrap();
+                    messages.push(message);
+                }
+
+                for message in messages.iter().rev() {
+                    stream
+                        .write_all(&(message.len() as u16).to_be_bytes())
+                        .unwrap();
+                    stream.write_all(message).unwrap();
+                }
+            });
+        }
+    });
+
+    (addr, accepted)
+}
+
+// echoes each query back as soon as it arrives, except those asking to be
+// left unanswered or answered slowly, and counts the connections it accepts
+fn selective_server() -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let accepted = Arc::new(AtomicUsize::new(0));
+
+    let count = accepted.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            count.fetch_add(1, Ordering::SeqCst);
+            let mut stream = stream.unwrap();
+            thread::spawn(move || loop {
+                let mut len = [0; 2];
+                if stream.read_exact(&mut len).is_err() {
+                    return;
+                }
+                let mut message = vec![0; u16::from_be_bytes(len) as usize];
+                stream.read_exact(&mut message).unwrap();
+
+                if message.ends_with(b"slow") {
+                    thread::sleep(Duration::from_millis(900));
+                }
+                if !message.ends_with(b"unanswered") {
+                    stream.write_all(&len).unwrap();
+                    stream.write_all(&message).unwrap();
+                }
+            });
+        }
+    });
+
+    (addr, accepted)
+}
+
+// two queries at once, which only complete if both are in flight on the
+// same connection
+fn exchange_pair(pool: &TcpPool, server: SocketAddr) {
+    thread::scope(|scope| {
+        for query in [&b"\x00\x07first"[..], &b"\x00\x09second"[..]] {
+            scope.spawn(move || {
+                let (buffer, len) = pool.exchange(query, server).unwrap();
+                assert_eq!(&buffer.buf[..len], query);
+            });
+        }
+    });
+}
+
+#[test]
+fn matches_out_of_order_responses() {
+    let (server, accepted) = reversing_server();
+    let pool = TcpPool::new();
+
+    exchange_pair(&pool, server);
+    exchange_pair(&pool, server);
+
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    assert_eq!(pool.len(), 1);
+}
+
+#[test]
+fn reopens_idle_connections() {
+    let (server, accepted) = reversing_server();
+    let mut pool = TcpPool::new();
+    pool.idle_timeout = Duration::from_millis(100);
+
+    exchange_pair(&pool, server);
+    thread::sleep(Duration::from_millis(200));
+    exchange_pair(&pool, server);
+
+    assert_eq!(accepted.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn honors_server_keepalive() {
+    let (server, accepted) = reversing_server();
+    let pool = TcpPool::new();
+
+    // echoed back, as if the server keeps idle connections for 100ms
+    let mut packet = DnsPacket::new();
+    packet.add_edns(512);
+    packet.add_edns_option(OPTION_TCP_KEEPALIVE, &1u16.to_be_bytes());
+    let mut buffer = PacketBuffer::new();
+    packet.write(&mut buffer).unwrap();
+    let query = buffer.as_slice();
+
+    for _ in 0..2 {
+        thread::scope(|scope| {
+            for _ in 0..2 {
+                scope.spawn(|| pool.exchange(query, server).unwrap());
+            }
+        });
+        thread::sleep(Duration::from_millis(200));
+    }
+
+    assert_eq!(accepted.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn reopens_cleared_connections() {
+    let (server, accepted) = reversing_server();
+    let pool = TcpPool::new();
+
+    exchange_pair(&pool, server);
+    pool.clear();
+    assert!(pool.is_empty());
+    exchange_pair(&pool, server);
+
+    assert_eq!(accepted.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn keeps_connections_a_query_timed_out_on() {
+    let (server, accepted) = selective_server();
+    let mut pool = TcpPool::new();
+    pool.timeout = Duration::from_millis(200);
+
+    let result = pool.exchange(b"\x00\x01unanswered", server);
+    assert_eq!(result.err(), Some("failed to recv"));
+
+    // nothing of a response was read, so the connection is still in step
+    exchange_pair(&pool, server);
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn answers_queries_in_flight_when_another_times_out() {
+    let (server, accepted) = selective_server();
+    let mut pool = TcpPool::new();
+    pool.timeout = Duration::from_millis(1000);
+
+    thread::scope(|scope| {
+        let pool = &pool;
+        let unanswered = scope.spawn(move || pool.exchange(b"\x00\x01unanswered", server));
+
+        // waiting while the first query reads and times out at 1000ms, its
+        // response comes at 1100ms
+        thread::sleep(Duration::from_millis(200));
+        let (buffer, len) = pool.exchange(b"\x00\x02slow", server).unwrap();
+        assert_eq!(&buffer.buf[..len], b"\x00\x02slow");
+
+        assert_eq!(unanswered.join().unwrap().err(), Some("failed to recv"));
+    });
+
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+}
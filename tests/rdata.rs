@@ -0,0 +1,158 @@
+// rdata read off the wire and written back: character strings and CAA values
+// as the bytes they came as, and names in older types expanded. and the key
+// tags of DNSKEYs
+
+use std::net::Ipv4Addr;
+
+use recursor::{
+    buffer::{NameWriter, PacketBuffer},
+    dns::{question::DnsClass, record::DnsRecord},
+    doh::base64url_decode,
+};
+
+// a record for example.com with rdata of type qtype, on the wire
+fn wire(qtype: u16, rdata: &[u8]) -> Vec<u8> {
+    let mut record = b"\x07example\x03com\x00".to_vec();
+    record.extend_from_slice(&qtype.to_be_bytes());
+    record.extend_from_slice(&[0, 1, 0, 0, 0x0e, 0x10]); // IN, ttl 3600
+    record.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    record.extend_from_slice(rdata);
+    record
+}
+
+// read a record off the wire and write it back
+fn round_trip(record: &[u8]) -> (DnsRecord, Vec<u8>) {
+    let mut buffer = PacketBuffer::with_len(record.len());
+    buffer.buf.copy_from_slice(record);
+    let parsed = DnsRecord::read(&mut buffer).unwrap();
+
+    let mut buffer = PacketBuffer::new();
+    parsed.write(&mut buffer, &mut NameWriter::new()).unwrap();
+    (parsed, buffer.as_slice().to_vec())
+}
+
+#[test]
+fn keeps_binary_txt_strings() {
+    // a full length string of bytes that aren't utf8, and an empty one
+    let mut rdata = vec![255];
+    rdata.extend_from_slice(&[0xff; 255]);
+    rdata.extend_from_slice(&[3, b'a', 0x80, b'b', 0]);
+
+    let record = wire(16, &rdata);
+    let (parsed, written) = round_trip(&record);
+    match &parsed {
+        DnsRecord::TXT { data, .. } => {
+            assert_eq!(data, &[vec![0xff; 255], b"a\x80b".to_vec(), Vec::new()]);
+        }
+        other => panic!("expected TXT, got {:?}", other),
+    }
+    assert_eq!(written, record);
+
+    // presentation escapes them instead
+    assert!(parsed.to_string().ends_with("\"a\\128b\" \"\""));
+}
+
+#[test]
+fn keeps_binary_caa_values() {
+    let mut rdata = vec![0, 5];
+    rdata.extend_from_slice(b"issue");
+    rdata.extend_from_slice(b"ca.test\xfe\xff");
+
+    let record = wire(257, &rdata);
+    let (parsed, written) = round_trip(&record);
+    match &parsed {
+        DnsRecord::CAA { tag, value, .. } => {
+            assert_eq!(tag, "issue");
+            assert_eq!(value, b"ca.test\xfe\xff");
+        }
+        other => panic!("expected CAA, got {:?}", other),
+    }
+    assert_eq!(written, record);
+}
+
+#[test]
+fn expands_compressed_names_of_older_types() {
+    let expanded = b"\x05admin\x07example\x03com\x00\x07example\x03com\x00";
+
+    // RP and MINFO, with two names each pointing back at the owner's
+    for qtype in [17, 14] {
+        let record = wire(qtype, b"\x05admin\xc0\x00\xc0\x00");
+        let (parsed, written) = round_trip(&record);
+        match &parsed {
+            DnsRecord::UNKOWN { data, .. } => assert_eq!(data, expanded),
+            other => panic!("expected an unknown record, got {:?}", other),
+        }
+
+        // the rdata no longer depends on where it's written
+        assert_eq!(written, wire(qtype, expanded));
+        assert_eq!(round_trip(&written).0, parsed);
+    }
+
+    // names after fixed fields, AFSDB's subtype
+    let (parsed, _) = round_trip(&wire(18, b"\x00\x01\x03afs\xc0\x00"));
+    match parsed {
+        DnsRecord::UNKOWN { data, .. } => {
+            assert_eq!(data, b"\x00\x01\x03afs\x07example\x03com\x00")
+        }
+        other => panic!("expected an unknown record, got {:?}", other),
+    }
+}
+
+// a DNSKEY with the key in base64, as in zone files
+fn dnskey(domain: &str, flags: u16, algorithm: u8, key: &str) -> DnsRecord {
+    let key: String = key
+        .chars()
+        .map(|c| match c {
+            '+' => '-',
+            '/' => '_',
+            c => c,
+        })
+        .collect();
+    DnsRecord::DNSKEY {
+        domain: domain.to_string(),
+        flags,
+        protocol: 3,
+        algorithm,
+        key: base64url_decode(&key).unwrap(),
+        class: DnsClass::IN,
+        ttl: 172800,
+    }
+}
+
+#[test]
+fn computes_key_tags() {
+    // the root zone's KSK-2017
+    let root = dnskey(
+        "",
+        257,
+        8,
+        "AwEAAaz/tAm8yTn4Mfeh5eyI96WSVexTBAvkMgJzkKTOiW1vkIbzxeF3+/4RgWOq7HrxRixHlFlExOLAJr5emLvN\
+         7SWXgnLh4+B5xQlNVz8Og8kvArMtNROxVQuCaSnIDdD5LKyWbRd2n9WGe2R8PzgCmr3EgVLrjyBxWezF0jLHwVN8\
+         efS3rCj/EWgvIWgb9tarpVUDK/b58Da+sqqls3eNbuv7pr+eoZG+SrDK6nWeL3c6H5Apxz7LjVc1uTIdsIXxuOLY\
+         A4/ilBmSVIzuDWfdRUfhHdY6+cn8HFRm+2hM8AnXGXws9555KrUB5qihylGa8subX2Nn6UwNR1AkUTV74bU=",
+    );
+    assert_eq!(root.key_tag(), Some(20326));
+
+    // the example in RFC 4034 5.4
+    let example = dnskey(
+        "dskey.example.com",
+        256,
+        5,
+        "AQOeiiR0GOMYkDshWoSKz9XzfwJr1AYtsmx3TGkJaNXVbfi/2pHm822aJ5iI9BMzNXxeYCmZDRD99WYwYqUSdjMm\
+         mAphXdvxegXd/M5+X7OrzKBaMbCVdFLUUh6DhweJBjEVv5f2wwjM9XzcnOf+EPbtG9DMBmADjFDc2w/rljwvFw==",
+    );
+    assert_eq!(example.key_tag(), Some(60485));
+
+    // RSA/MD5 keys are tagged by the two bytes before the last of the
+    // modulus, ...78 84 37 here, not the sum
+    let md5 = dnskey("example.com", 256, 1, "AQPSKmynfzW4kyBv015MUG2DeIQ3");
+    assert_eq!(md5.key_tag(), Some(0x7884));
+
+    let a = DnsRecord::A {
+        domain: "example.com".to_string(),
+        addr: Ipv4Addr::LOCALHOST,
+        class: DnsClass::IN,
+        ttl: 300,
+    };
+    assert_eq!(a.key_tag(), None);
+}
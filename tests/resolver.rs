@@ -0,0 +1,225 @@
+// the resolver's own checks and stub conveniences, against a local root
+// where they go upstream
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+use common::{authoritative, serve_udp, ADDR};
+use recursor::{
+    dns::{
+        header::ResultCode,
+        packet::DnsPacket,
+        question::{DnsClass, QueryType},
+        record::DnsRecord,
+    },
+    srv_targets, Resolver,
+};
+
+mod common;
+
+#[test]
+fn rejects_bogus_nameserver_addresses() {
+    let mut resolver = Resolver::new(Ipv4Addr::LOCALHOST);
+
+    for bogus in [
+        "0.0.0.0",
+        "0.1.2.3",
+        "127.0.0.1",
+        "127.1.2.3",
+        "169.254.169.254",
+        "224.0.0.1",
+        "239.255.255.250",
+        "255.255.255.255",
+        "240.0.0.1",
+    ] {
+        assert!(!resolver.is_valid_ns(bogus.parse().unwrap()), "{}", bogus);
+    }
+    for valid in ["198.41.0.4", "192.168.1.1", "10.0.0.53", "8.8.8.8"] {
+        assert!(resolver.is_valid_ns(valid.parse().unwrap()), "{}", valid);
+    }
+
+    // local test zones may refer to loopback
+    resolver.loopback_ns = true;
+    assert!(resolver.is_valid_ns(Ipv4Addr::new(127, 0, 0, 2)));
+    assert!(!resolver.is_valid_ns(Ipv4Addr::UNSPECIFIED));
+}
+
+#[test]
+fn recognizes_our_own_listen_addresses() {
+    let addr = |addr: &str| addr.parse::<SocketAddr>().unwrap();
+    let mut resolver = Resolver::new(Ipv4Addr::LOCALHOST);
+    resolver.listen_addrs = vec![addr("192.168.1.2:2053"), addr("0.0.0.0:853")];
+
+    assert!(resolver.is_self(addr("192.168.1.2:2053")));
+    assert!(!resolver.is_self(addr("192.168.1.2:53")));
+    assert!(!resolver.is_self(addr("192.168.1.3:2053")));
+
+    // a wildcard listener is reached over loopback, or the unspecified
+    // address, on its port
+    assert!(resolver.is_self(addr("127.0.0.1:853")));
+    assert!(resolver.is_self(addr("127.0.0.53:853")));
+    assert!(resolver.is_self(addr("0.0.0.0:853")));
+    assert!(!resolver.is_self(addr("127.0.0.1:2053")));
+    assert!(!resolver.is_self(addr("203.0.113.1:853")));
+
+    // and on ipv6 loopback for one bound to [::]
+    resolver.listen_addrs = vec![addr("[::]:2053")];
+    assert!(resolver.is_self(addr("[::1]:2053")));
+    assert!(!resolver.is_self(addr("[2001:db8::1]:2053")));
+}
+
+#[test]
+fn answers_address_literals_without_querying() {
+    // a root that would see any query sent
+    let root = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut resolver = Resolver::new(Ipv4Addr::LOCALHOST);
+    resolver.ns_port = root.local_addr().unwrap().port();
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    let response = resolver
+        .resolve("192.0.2.1", QueryType::A, &socket)
+        .unwrap();
+    assert_eq!(response.header.rcode, ResultCode::NOERROR);
+    assert_eq!(response.get_any_a(), Some(Ipv4Addr::new(192, 0, 2, 1)));
+
+    let response = resolver
+        .resolve("2001:db8::1", QueryType::AAAA, &socket)
+        .unwrap();
+    match response.answers.as_slice() {
+        [DnsRecord::AAAA { domain, addr, .. }] => {
+            assert_eq!(domain, "2001:db8::1");
+            assert_eq!(*addr, "2001:db8::1".parse::<Ipv6Addr>().unwrap());
+        }
+        answers => panic!("unexpected answers {:?}", answers),
+    }
+
+    // other types of a literal exist without data
+    let response = resolver
+        .resolve("192.0.2.1", QueryType::AAAA, &socket)
+        .unwrap();
+    assert_eq!(response.header.rcode, ResultCode::NOERROR);
+    assert!(response.answers.is_empty());
+
+    // nor go through the search domains
+    resolver.search = vec!["corp.example".to_string()];
+    let addrs = resolver.lookup_ip("::1", &socket).unwrap();
+    assert_eq!(addrs, [IpAddr::from(Ipv6Addr::LOCALHOST)]);
+
+    root.set_nonblocking(true).unwrap();
+    assert!(root.recv(&mut [0; 512]).is_err());
+}
+
+#[test]
+fn orders_search_names_by_ndots() {
+    let mut resolver = Resolver::new(Ipv4Addr::LOCALHOST);
+    resolver.search = vec!["corp.example".to_string(), ".example.net.".to_string()];
+
+    // short names try the search domains first
+    assert_eq!(
+        resolver.search_names("myhost"),
+        ["myhost.corp.example", "myhost.example.net", "myhost"]
+    );
+    assert_eq!(
+        resolver.search_names("www.example.com"),
+        [
+            "www.example.com",
+            "www.example.com.corp.example",
+            "www.example.com.example.net"
+        ]
+    );
+    // absolute names never search
+    assert_eq!(resolver.search_names("myhost."), ["myhost"]);
+
+    resolver.ndots = 3;
+    assert_eq!(
+        resolver.search_names("www.example.com"),
+        [
+            "www.example.com.corp.example",
+            "www.example.com.example.net",
+            "www.example.com"
+        ]
+    );
+
+    resolver.search.clear();
+    assert_eq!(resolver.search_names("myhost"), ["myhost"]);
+}
+
+#[test]
+fn looks_up_hosts_through_the_search_domains() {
+    // a root authoritative for myhost.corp.example alone
+    let root = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut resolver = Resolver::new(Ipv4Addr::LOCALHOST);
+    resolver.ns_port = root.local_addr().unwrap().port();
+    resolver.search = vec!["example.net".to_string(), "corp.example".to_string()];
+    serve_udp(root, |query| {
+        let question = &query.questions[0];
+        if question.qname == "myhost.corp.example" && question.qtype == QueryType::A {
+            return authoritative(query, ADDR);
+        }
+        let mut response = DnsPacket::new();
+        response.header.aa = true;
+        response.header.rcode = ResultCode::NXDOMAIN;
+        response.questions = query.questions.clone();
+        response
+    });
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addrs = resolver.lookup_ip("myhost", &socket).unwrap();
+    assert_eq!(addrs, [IpAddr::V4(ADDR)]);
+    assert!(resolver.lookup_ip("other", &socket).unwrap().is_empty());
+}
+
+#[test]
+fn orders_srv_targets_by_priority_then_weight() {
+    let srv = |priority, weight, target: &str| DnsRecord::SRV {
+        domain: "_imaps._tcp.example.com".to_string(),
+        priority,
+        weight,
+        port: 993,
+        target: target.to_string(),
+        class: DnsClass::IN,
+        ttl: 300,
+    };
+    let answers = vec![
+        srv(20, 0, "last.example.com"),
+        srv(10, 60, "heavy.example.com"),
+        srv(10, 0, "zero.example.com"),
+        srv(5, 10, "first.example.com"),
+        srv(10, 40, "light.example.com"),
+        // not offered
+        srv(1, 0, ""),
+    ];
+
+    let mut firsts = [0; 3];
+    for seed in 0..1000 {
+        let targets: Vec<String> = srv_targets(answers.clone(), seed)
+            .into_iter()
+            .map(|(target, _)| target)
+            .collect();
+
+        // lower priorities always come first, weight 0 ones included
+        assert_eq!(targets.len(), 5);
+        assert_eq!(targets[0], "first.example.com");
+        assert_eq!(targets[4], "last.example.com");
+        let mut middle = targets[1..4].to_vec();
+        middle.sort();
+        assert_eq!(
+            middle,
+            ["heavy.example.com", "light.example.com", "zero.example.com"]
+        );
+
+        let first = ["heavy.example.com", "light.example.com", "zero.example.com"]
+            .iter()
+            .position(|target| *target == targets[1])
+            .unwrap();
+        firsts[first] += 1;
+    }
+
+    // within a priority, heavier targets tend to come first, and weight 0
+    // only on a draw of 0
+    let [heavy, light, zero] = firsts;
+    assert!(heavy > light && light > zero, "{:?}", firsts);
+    assert!(zero < 20, "{:?}", firsts);
+
+    // the same seed, the same order
+    assert_eq!(srv_targets(answers.clone(), 7), srv_targets(answers, 7));
+}
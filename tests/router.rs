@@ -0,0 +1,43 @@
+// routes to fixed upstreams
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use recursor::{
+    dns::question::QueryType,
+    router::{Router, Upstream},
+    Resolver,
+};
+
+#[test]
+fn drops_routes_to_our_own_listeners() {
+    let mut resolver = Resolver::new(Ipv4Addr::LOCALHOST);
+    resolver
+        .listen_addrs
+        .push(SocketAddr::from(([0, 0, 0, 0], 2053)));
+
+    let lan = Upstream::Udp(Ipv4Addr::new(192, 168, 1, 1), 53);
+    let mut router = Router::new();
+    router.route(QueryType::A, (Ipv4Addr::LOCALHOST, 2053));
+    router.route(QueryType::A, lan.clone());
+    router.route(QueryType::PTR, (Ipv4Addr::UNSPECIFIED, 2053));
+    // another port on loopback is someone else
+    router.route(QueryType::MX, (Ipv4Addr::LOCALHOST, 53));
+
+    let dropped = router.drop_self(|addr| resolver.is_self(addr));
+    assert_eq!(
+        dropped,
+        [
+            Upstream::Udp(Ipv4Addr::LOCALHOST, 2053),
+            Upstream::Udp(Ipv4Addr::UNSPECIFIED, 2053),
+        ]
+    );
+
+    // a type left without upstreams is resolved as usual again
+    assert_eq!(
+        router.rules,
+        [
+            (QueryType::A, vec![lan]),
+            (QueryType::MX, vec![Upstream::Udp(Ipv4Addr::LOCALHOST, 53)]),
+        ]
+    );
+}
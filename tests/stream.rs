@@ -0,0 +1,69 @@
+// length prefixed messages reassembled from a stream fed in pieces
+
+use std::net::Ipv4Addr;
+
+use recursor::{
+    buffer::PacketBuffer,
+    dns::{
+        packet::DnsPacket,
+        question::{DnsClass, DnsQuestion, QueryType},
+        record::DnsRecord,
+        stream::StreamDecoder,
+    },
+};
+
+// a response with count A records under distinct names, too many for 512
+// bytes once count is large
+fn response(count: u8) -> Vec<u8> {
+    let mut packet = DnsPacket::new();
+    packet.header.qr = true;
+    packet.add_question(DnsQuestion::new("example.com".to_string(), QueryType::A));
+    for i in 0..count {
+        packet.add_answer(DnsRecord::A {
+            domain: format!("host{}.example.com", i),
+            addr: Ipv4Addr::new(192, 0, 2, i),
+            class: DnsClass::IN,
+            ttl: 300,
+        });
+    }
+
+    let mut buffer = PacketBuffer::with_len(PacketBuffer::MAX_LEN);
+    packet.write(&mut buffer).unwrap();
+    let mut message = (buffer.pos() as u16).to_be_bytes().to_vec();
+    message.extend_from_slice(buffer.as_slice());
+    message
+}
+
+#[test]
+fn reassembles_messages_longer_than_512_bytes() {
+    let large = response(100);
+    assert!(large.len() > 2 + 512);
+    let small = response(1);
+
+    let mut stream = large.clone();
+    stream.extend_from_slice(&small);
+
+    // one byte into the length prefix, then in the middle of the message
+    let mut decoder = StreamDecoder::new();
+    for chunk in [&stream[..1], &stream[1..300], &stream[300..]] {
+        decoder.feed(chunk);
+    }
+
+    let (buffer, len) = decoder.next_message().unwrap().unwrap();
+    assert_eq!(len, large.len() - 2);
+    assert_eq!(buffer.buf, large[2..]);
+
+    let packet = decoder.next_packet().unwrap().unwrap();
+    assert_eq!(packet.answers.len(), 1);
+    assert!(decoder.is_empty());
+}
+
+#[test]
+fn parses_large_packets() {
+    let mut decoder = StreamDecoder::new();
+    decoder.feed(&response(100));
+
+    let packet = decoder.next_packet().unwrap().unwrap();
+    assert_eq!(packet.answers.len(), 100);
+    assert_eq!(packet.answers[99].domain(), "host99.example.com");
+}
@@ -0,0 +1,240 @@
+// dns over tls upstreams and the listener, against a local server with a
+// certificate for dns.test from the ca in tests/tls
+#![cfg(feature = "tls")]
+
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use recursor::{
+    buffer::PacketBuffer,
+    dns::{
+        header::ResultCode,
+        packet::DnsPacket,
+        question::{DnsClass, DnsQuestion, QueryType},
+    },
+    tls::{self, TlsClient},
+    Resolver, Transport,
+};
+use rustls::{
+    client::ClientConnection,
+    crypto::ring,
+    pki_types::{pem::PemObject, CertificateDer},
+    CipherSuite, ClientConfig, HandshakeKind, RootCertStore, ServerConnection, StreamOwned,
+};
+
+const CA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls/ca.pem");
+const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls/dns.test.pem");
+const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls/dns.test.key");
+
+// echoes every message back, and counts the connections it accepts
+fn echo_server() -> (SocketAddr, Arc<AtomicUsize>) {
+    let config = tls::server_config(Path::new(CERT), Path::new(KEY), &[]).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let accepted = Arc::new(AtomicUsize::new(0));
+
+    let count = accepted.clone();
+    thread::spawn(move || {
+        for tcp in listener.incoming() {
+            count.fetch_add(1, Ordering::SeqCst);
+            let conn = ServerConnection::new(config.clone()).unwrap();
+            let mut stream = StreamOwned::new(conn, tcp.unwrap());
+            thread::spawn(move || loop {
+                let mut len = [0; 2];
+                if stream.read_exact(&mut len).is_err() {
+                    return;
+                }
+                let mut message = vec![0; u16::from_be_bytes(len) as usize];
+                stream.read_exact(&mut message).unwrap();
+                stream.write_all(&len).unwrap();
+                stream.write_all(&message).unwrap();
+                stream.flush().unwrap();
+            });
+        }
+    });
+
+    (addr, accepted)
+}
+
+fn test_roots() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    roots
+        .add(CertificateDer::from_pem_file(CA).unwrap())
+        .unwrap();
+    roots
+}
+
+fn test_client() -> TlsClient {
+    TlsClient::with_roots(test_roots())
+}
+
+// a client config trusting the test ca, with ring's default suites
+fn client_config() -> Arc<ClientConfig> {
+    Arc::new(
+        ClientConfig::builder_with_provider(ring::default_provider().into())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(test_roots())
+            .with_no_client_auth(),
+    )
+}
+
+fn connect(
+    config: &Arc<ClientConfig>,
+    server: SocketAddr,
+) -> StreamOwned<ClientConnection, TcpStream> {
+    let conn = ClientConnection::new(config.clone(), "dns.test".try_into().unwrap()).unwrap();
+    StreamOwned::new(conn, TcpStream::connect(server).unwrap())
+}
+
+#[test]
+fn reuses_connections() {
+    let (server, accepted) = echo_server();
+    let client = test_client();
+
+    for id in 0..3 {
+        let query = [0, id, 1, 0, 0, 0];
+        let (buffer, len) = client.exchange(&query, server, "dns.test").unwrap();
+        assert_eq!(&buffer.buf[..len], query);
+    }
+
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    assert_eq!(client.idle_len(), 1);
+}
+
+#[test]
+fn verifies_certificates() {
+    let (server, _) = echo_server();
+
+    // valid, but for another name
+    let result = test_client().exchange(&[0, 1], server, "other.test");
+    assert_eq!(result.err(), Some("tls handshake failed"));
+
+    // from a ca that isn't trusted
+    let result = TlsClient::new().exchange(&[0, 1], server, "dns.test");
+    assert_eq!(result.err(), Some("tls handshake failed"));
+}
+
+#[test]
+fn rejects_mismatched_keys() {
+    // the ca's certificate with the server's key
+    let result = tls::server_config(Path::new(CA), Path::new(KEY), &[]);
+    assert_eq!(result.err(), Some("certificate does not match private key"));
+}
+
+#[test]
+fn resumes_sessions() {
+    let (server, accepted) = echo_server();
+    let config = client_config();
+
+    let mut kinds = Vec::new();
+    for id in 0..2 {
+        let mut stream = connect(&config, server);
+
+        // the ticket arrives after the handshake, read along with the echo
+        let query = [0, 6, 0, id, 1, 0, 0, 0];
+        stream.write_all(&query).unwrap();
+        let mut echo = [0; 8];
+        stream.read_exact(&mut echo).unwrap();
+        assert_eq!(echo, query);
+        kinds.push(stream.conn.handshake_kind());
+    }
+
+    assert_eq!(
+        kinds,
+        [Some(HandshakeKind::Full), Some(HandshakeKind::Resumed)]
+    );
+    assert_eq!(accepted.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn negotiates_only_the_configured_cipher_suites() {
+    let ciphers = ["tls13_chacha20_poly1305_sha256".to_string()];
+    let config = tls::server_config(Path::new(CERT), Path::new(KEY), &ciphers).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let conn = ServerConnection::new(config).unwrap();
+        let mut stream = StreamOwned::new(conn, listener.accept().unwrap().0);
+        stream.write_all(&[0]).unwrap();
+        stream.flush().unwrap();
+    });
+
+    let mut stream = connect(&client_config(), server);
+    stream.read_exact(&mut [0]).unwrap();
+    let negotiated = stream.conn.negotiated_cipher_suite().unwrap();
+    assert_eq!(
+        negotiated.suite(),
+        CipherSuite::TLS13_CHACHA20_POLY1305_SHA256
+    );
+
+    let result = tls::server_config(Path::new(CERT), Path::new(KEY), &["rc4".to_string()]);
+    assert_eq!(result.err(), Some("unknown cipher suite"));
+}
+
+// a CH class query with id on the wire, answered with NOTIMP without
+// going upstream
+fn chaos_query(id: u16) -> Vec<u8> {
+    let mut question = DnsQuestion::new("version.bind".to_string(), QueryType::TXT);
+    question.qclass = DnsClass::CH;
+    let mut packet = DnsPacket::new();
+    packet.header.id = id;
+    packet.add_question(question);
+
+    let mut buffer = PacketBuffer::new();
+    packet.write(&mut buffer).unwrap();
+    buffer.as_slice().to_vec()
+}
+
+#[test]
+fn counts_dot_connections_and_resumptions() {
+    let config = tls::server_config(Path::new(CERT), Path::new(KEY), &[]).unwrap();
+    let resolver = Resolver::new(Ipv4Addr::LOCALHOST).shared();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = listener.local_addr().unwrap();
+
+    let listening = resolver.clone();
+    thread::spawn(move || {
+        for tcp in listener.incoming() {
+            let (resolver, config) = (listening.clone(), config.clone());
+            thread::spawn(move || {
+                let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+                tls::serve(&resolver, tcp.unwrap(), config, &socket).unwrap();
+            });
+        }
+    });
+
+    let config = client_config();
+    for id in 0..2 {
+        let mut stream = connect(&config, server);
+        // two queries on each connection
+        for id in [id * 2, id * 2 + 1] {
+            let query = chaos_query(id);
+            stream
+                .write_all(&(query.len() as u16).to_be_bytes())
+                .unwrap();
+            stream.write_all(&query).unwrap();
+
+            let mut len = [0; 2];
+            stream.read_exact(&mut len).unwrap();
+            let mut buffer = PacketBuffer::with_len(u16::from_be_bytes(len) as usize);
+            stream.read_exact(buffer.as_mut_slice()).unwrap();
+            let response = DnsPacket::read(&mut buffer).unwrap();
+            assert_eq!(response.header.id, id);
+            assert_eq!(response.header.rcode, ResultCode::NOTIMP);
+        }
+    }
+
+    assert_eq!(resolver.dot.connections.load(Ordering::Relaxed), 2);
+    assert_eq!(resolver.dot.resumed.load(Ordering::Relaxed), 1);
+    assert_eq!(resolver.ingress.get(Transport::Tls), 4);
+}